@@ -1,8 +1,61 @@
 #![allow(unused)]
 
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use simple_nav::engine::Engine;
-use simple_nav::graph::LatLon;
+use simple_nav::engine::{Engine, SnappedAt};
+use simple_nav::graph::{Graph, LatLon, NodeIndex, SearchContext};
+
+// the searches before SearchContext: a dist and a prev vector of the size of the graph and a
+// BinaryHeap, allocated for each search, and nodes pushed again instead of a decrease-key
+#[derive(PartialEq)]
+struct Queued(f64, NodeIndex);
+
+impl Eq for Queued {}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn fresh_buffers_path(g: &Graph, s: NodeIndex, t: NodeIndex) -> Option<(f64, Vec<NodeIndex>)> {
+    let mut dist = vec![f64::INFINITY; g.get_total_nodes()];
+    let mut prev: Vec<Option<NodeIndex>> = vec![None; g.get_total_nodes()];
+    let mut heap = BinaryHeap::new();
+    dist[s.index()] = 0.0;
+    heap.push(Reverse(Queued(0.0, s)));
+    while let Some(Reverse(Queued(d, u))) = heap.pop() {
+        if u == t {
+            let mut path = vec![t];
+            while let Some(p) = prev[path[path.len() - 1].index()] {
+                path.push(p);
+            }
+            path.reverse();
+            return Some((d, path));
+        }
+        if d > dist[u.index()] {
+            continue; // an older entry of a node reached again since
+        }
+        for edge in g.adjacent_edges(u).into_iter().flatten() {
+            let v = edge.to_node;
+            let through_u = d + edge.distance();
+            if through_u < dist[v.index()] {
+                dist[v.index()] = through_u;
+                prev[v.index()] = Some(u);
+                heap.push(Reverse(Queued(through_u, v)));
+            }
+        }
+    }
+    None
+}
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     let engine = Engine::build("./data/delaware-latest.osm.pbf").unwrap();
@@ -22,6 +75,34 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             result.unwrap();
         })
     });
+
+    // a short trip in a big graph, where preparing the buffers of the search costs the most
+    let graph = engine.graph();
+    // the closest graph nodes, a search between nodes doesn't start from shape points
+    let node = |p| {
+        engine
+            .nearest(p, 50)
+            .into_iter()
+            .find_map(|s| match s.at {
+                SnappedAt::Node(n) => Some(n),
+                SnappedAt::ShapePoint(_) => None,
+            })
+            .unwrap()
+    };
+    let s = node(orig);
+    let t = node(LatLon { lon: -75.06, lat: 38.54 });
+    let mut group = c.benchmark_group("short trip");
+    group.bench_function("new buffers per search", |b| {
+        b.iter(|| fresh_buffers_path(graph, black_box(s), black_box(t)).unwrap())
+    });
+    let mut context = SearchContext::new(graph.get_total_nodes());
+    // both arms do the same search
+    let reused = context.shortest_path(graph, s, t).unwrap();
+    assert_eq!(fresh_buffers_path(graph, s, t).map(|(d, path)| (d, path.len())), Some((reused.0, reused.1.len())));
+    group.bench_function("reused SearchContext", |b| {
+        b.iter(|| context.shortest_path(graph, black_box(s), black_box(t)).unwrap())
+    });
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
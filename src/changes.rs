@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::{ImportConfig, ProfileConfig},
+    graph::Node,
+    input::OsmElement,
+    osm,
+    osmxml::XmlSource,
+    parser::{referenced_nodes, road, ParseError, Roads},
+};
+
+// OSM change files (.osc / .osc.gz, e.g. the daily diffs of Geofabrik) applied to the roads of a
+// prepared graph, which is then rebuilt from them (see Engine::apply_changes):
+//   <osmChange>
+//     <create> <node .../> </create>
+//     <modify> <way id='10'> <nd ref='1'/> ... <tag .../> </way> </modify>
+//     <delete> <node id='3'/> </delete>
+//   </osmChange>
+// a way is replaced as a whole (a diff has all its nodes and tags), a node only if a road uses it.
+// the diffs must be applied in order, the last change of an object wins.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ChangeStats {
+    pub nodes_changed: usize, // moved, added or deleted nodes of roads
+    pub ways_added: usize,
+    pub ways_modified: usize,
+    pub ways_removed: usize, // deleted, or no longer a road for the profile
+    pub missing_nodes: usize, // referenced by a changed way but neither in the graph nor in the diff
+}
+
+// the change files in order. a node created or moved by one of them may only be used by a way
+// in a later one, so the nodes no road uses are only dropped after the last file.
+pub fn apply(
+    roads: &mut Roads,
+    changes: &[XmlSource],
+    import: &ImportConfig,
+    profile: &ProfileConfig,
+) -> Result<ChangeStats, ParseError> {
+    let mut stats = ChangeStats::default();
+    let mut nodes: HashMap<osm::NodeID, Option<Node>> = HashMap::new(); // None: deleted
+    let mut changed_ways = Vec::new();
+    for source in changes {
+        source.for_each_change(&mut |action, element| match (action, element) {
+            (None, _) => {} // outside of <create>, <modify>, <delete>
+            (Some(Action::Delete), OsmElement::Node(node)) => {
+                nodes.insert(node.id, None);
+            }
+            (Some(_), OsmElement::Node(node)) => {
+                nodes.insert(node.id, Some(node));
+            }
+            (Some(Action::Delete), OsmElement::Way(way)) => {
+                if roads.ways.remove(&way.id).is_some() {
+                    stats.ways_removed += 1;
+                }
+            }
+            (Some(_), OsmElement::Way(way)) => {
+                let refs = way.nodes.iter().map(|n| n.0);
+                match road(way.tags(), refs, import, profile).ok() {
+                    Some(new) => {
+                        match roads.ways.insert(way.id, new) {
+                            Some(_) => stats.ways_modified += 1,
+                            None => stats.ways_added += 1,
+                        }
+                        changed_ways.push(way.id);
+                    }
+                    None => {
+                        if roads.ways.remove(&way.id).is_some() {
+                            stats.ways_removed += 1;
+                        }
+                    }
+                }
+            }
+        })?;
+    }
+
+    let referenced = referenced_nodes(&roads.ways);
+    for (id, node) in nodes {
+        match node {
+            Some(node) if referenced.contains(&id) => {
+                roads.nodes.insert(id, node);
+                stats.nodes_changed += 1;
+            }
+            Some(_) => {}
+            None => {
+                if roads.nodes.remove(&id).is_some() {
+                    stats.nodes_changed += 1;
+                }
+            }
+        }
+    }
+    // the nodes of the removed ways
+    roads.nodes.retain(|id, _| referenced.contains(id));
+
+    // e.g. a way now using a node which was on a footpath: the graph never had it, and a diff
+    // only has the nodes which changed. such ways are cut around it when the graph is built
+    stats.missing_nodes = changed_ways
+        .iter()
+        .filter_map(|id| roads.ways.get(id))
+        .flat_map(|way| way.nodes.iter())
+        .filter(|id| !roads.nodes.contains_key(id))
+        .collect::<HashSet<_>>()
+        .len();
+
+    tracing::info!(
+        nodes_changed = stats.nodes_changed,
+        ways_added = stats.ways_added,
+        ways_modified = stats.ways_modified,
+        ways_removed = stats.ways_removed,
+        missing_nodes = stats.missing_nodes,
+        "changes applied"
+    );
+    if stats.missing_nodes > 0 {
+        tracing::warn!(
+            missing_nodes = stats.missing_nodes,
+            "changed roads use nodes which are neither in the graph nor in the change files, they are cut \
+             around them: run `preprocess` on a current extract to get them back"
+        );
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DistanceMetric,
+        parser::{build_graph, read_roads_with},
+    };
+
+    // 1 - 2 - 3 along a residential street, 4 is the end of a fence
+    const MAP: &str = r#"<osm version='0.6'>
+  <node id='1' lat='38.00' lon='-75.0' />
+  <node id='2' lat='38.01' lon='-75.0' />
+  <node id='3' lat='38.02' lon='-75.0' />
+  <node id='4' lat='38.02' lon='-75.01' />
+  <way id='10'> <nd ref='1' /> <nd ref='2' /> <nd ref='3' /> <tag k='highway' v='residential' /> </way>
+  <way id='11'> <nd ref='3' /> <nd ref='4' /> <tag k='barrier' v='fence' /> </way>
+</osm>"#;
+
+    fn roads() -> Roads {
+        let path = std::env::temp_dir().join(format!("simple-nav-changes-{}.osm", std::process::id()));
+        std::fs::write(&path, MAP).unwrap();
+        let roads = read_roads_with(&[path.to_str().unwrap()], &ImportConfig::default(), &ProfileConfig::default());
+        std::fs::remove_file(&path).unwrap();
+        roads.unwrap()
+    }
+
+    fn apply_osc(roads: &mut Roads, osc: &str) -> ChangeStats {
+        apply(roads, &[XmlSource::from_string(osc)], &ImportConfig::default(), &ProfileConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let mut roads = roads();
+        assert_eq!(roads.ways.len(), 1);
+        assert_eq!(roads.nodes.len(), 3);
+
+        // the fence becomes a road, a new street goes from 2 to a new node 5
+        let stats = apply_osc(
+            &mut roads,
+            r#"<osmChange version='0.6'>
+<create>
+  <node id='5' lat='38.01' lon='-74.99' />
+  <way id='12'> <nd ref='2' /> <nd ref='5' /> <tag k='highway' v='service' /> </way>
+</create>
+<modify>
+  <node id='3' lat='38.03' lon='-75.0' />
+  <way id='11'> <nd ref='3' /> <nd ref='4' /> <tag k='highway' v='residential' /> </way>
+</modify>
+</osmChange>"#,
+        );
+        assert_eq!(
+            stats,
+            ChangeStats { nodes_changed: 2, ways_added: 2, ways_modified: 0, ways_removed: 0, missing_nodes: 1 }
+        );
+        assert_eq!(roads.nodes[&osm::NodeID(3)].location.lat, 38.03);
+        // 11 lost its end 4, the graph only had the nodes of roads: 3 - 4 isn't a road
+        let (graph, _) = build_graph(&roads, None, DistanceMetric::Haversine).unwrap();
+        assert_eq!(graph.get_total_nodes(), 4);
+
+        // the street and then the node are gone
+        let stats = apply_osc(
+            &mut roads,
+            r#"<osmChange version='0.6'>
+<delete>
+  <way id='12' />
+  <node id='5' />
+</delete>
+</osmChange>"#,
+        );
+        assert_eq!(stats, ChangeStats { nodes_changed: 1, ways_removed: 1, ..ChangeStats::default() });
+        assert!(!roads.nodes.contains_key(&osm::NodeID(5)));
+
+        // a street which isn't a road anymore
+        let stats = apply_osc(
+            &mut roads,
+            r#"<osmChange version='0.6'>
+<modify>
+  <way id='10'> <nd ref='1' /> <nd ref='2' /> <nd ref='3' /> <tag k='railway' v='abandoned' /> </way>
+</modify>
+</osmChange>"#,
+        );
+        assert_eq!(stats.ways_removed, 1);
+        assert!(!roads.nodes.contains_key(&osm::NodeID(1)));
+    }
+
+    #[test]
+    fn test_node_used_in_a_later_file() {
+        // the first diff creates 5 on its own, the second one a street to it
+        let first = r#"<osmChange version='0.6'>
+<create> <node id='5' lat='38.01' lon='-74.99' /> </create>
+</osmChange>"#;
+        let second = r#"<osmChange version='0.6'>
+<create> <way id='12'> <nd ref='2' /> <nd ref='5' /> <tag k='highway' v='service' /> </way> </create>
+</osmChange>"#;
+        let mut roads = roads();
+        let sources = [XmlSource::from_string(first), XmlSource::from_string(second)];
+        let stats = apply(&mut roads, &sources, &ImportConfig::default(), &ProfileConfig::default()).unwrap();
+        assert_eq!(stats, ChangeStats { nodes_changed: 1, ways_added: 1, ..ChangeStats::default() });
+        assert!(roads.nodes.contains_key(&osm::NodeID(5)));
+
+        // applied one run after the other, the node no road used is gone: reported missing
+        let mut roads = self::roads();
+        apply_osc(&mut roads, first);
+        assert_eq!(apply_osc(&mut roads, second).missing_nodes, 1);
+    }
+}
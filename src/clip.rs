@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use geo::{Contains, Coord, LineString, MultiPolygon, Point, Polygon};
+use serde_json::Value;
+
+use crate::{
+    config::ClipConfig,
+    graph::{BoundingBox, LatLon, Node},
+    osm,
+    parser::ParseError,
+};
+
+// the area the import is limited to ([import.clip] or `preprocess --bbox / --polygon`).
+//
+// the polygon files are GeoJSON (a Polygon or MultiPolygon, bare or in a Feature / FeatureCollection)
+// or the osmosis .poly format of the extracts of Geofabrik:
+//   delaware
+//   1
+//      -75.79 39.72
+//      ...
+//   END
+//   !2          <- a hole
+//      ...
+//   END
+//   END
+
+pub enum Area {
+    Bbox(BoundingBox),
+    Polygon(MultiPolygon<f64>),
+}
+
+impl Area {
+    // the border counts as inside
+    pub fn contains(&self, p: LatLon) -> bool {
+        match self {
+            Area::Bbox(b) => b.contains(p),
+            Area::Polygon(polygons) => {
+                let point = Point::new(p.lon, p.lat);
+                polygons.iter().any(|polygon| {
+                    polygon.contains(&point)
+                        || polygon.exterior().contains(&point)
+                        || polygon.interiors().iter().any(|ring| ring.contains(&point))
+                })
+            }
+        }
+    }
+}
+
+pub struct Clip {
+    pub area: Area,
+    pub keep_border_ways: bool,
+}
+
+impl Clip {
+    // None when the config doesn't limit the import
+    pub fn from_config(config: &ClipConfig) -> Result<Option<Clip>, ParseError> {
+        let area = match (&config.bbox, &config.polygon) {
+            (Some([min_lon, min_lat, max_lon, max_lat]), _) => Area::Bbox(BoundingBox {
+                min: LatLon { lat: *min_lat, lon: *min_lon },
+                max: LatLon { lat: *max_lat, lon: *max_lon },
+            }),
+            (None, Some(path)) => Area::Polygon(load_polygon(path)?),
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Clip { area, keep_border_ways: config.keep_border_ways }))
+    }
+
+    // the way if it has a node inside: whole with keep_border_ways, else only its parts inside.
+    // a node missing from `nodes` is outside.
+    pub fn way(&self, way: &osm::Way, nodes: &HashMap<osm::NodeID, Node>) -> Vec<osm::Way> {
+        let inside = |id: osm::NodeID| nodes.get(&id).is_some_and(|n| self.area.contains(n.location));
+        if self.keep_border_ways {
+            return way.nodes.iter().any(|n| inside(*n)).then(|| way.clone()).into_iter().collect();
+        }
+        way.split(inside)
+    }
+}
+
+pub fn load_polygon(path: &str) -> Result<MultiPolygon<f64>, ParseError> {
+    let text = std::fs::read_to_string(path).map_err(|e| ParseError::Invalid(format!("{}: {}", path, e)))?;
+    let polygon = match text.trim_start().starts_with('{') {
+        true => parse_geojson(&text),
+        false => parse_poly(&text),
+    };
+    polygon.map_err(|e| ParseError::Invalid(format!("{}: {}", path, e)))
+}
+
+fn parse_poly(text: &str) -> Result<MultiPolygon<f64>, String> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    lines.next().ok_or("empty file")?; // the name
+    let mut outers = Vec::new();
+    let mut holes = Vec::new();
+    loop {
+        let header = lines.next().ok_or("missing END")?;
+        if header == "END" {
+            break;
+        }
+        let mut ring = Vec::new();
+        for line in lines.by_ref() {
+            if line == "END" {
+                break;
+            }
+            let mut numbers = line.split_whitespace().map(|n| n.parse::<f64>());
+            match (numbers.next(), numbers.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => ring.push(Coord { x, y }),
+                _ => return Err(format!("not a coordinate: {}", line)),
+            }
+        }
+        if ring.len() < 3 {
+            return Err(format!("ring {} has less than 3 points", header));
+        }
+        match header.starts_with('!') {
+            true => holes.push(LineString::from(ring)),
+            false => outers.push(LineString::from(ring)),
+        }
+    }
+    if outers.is_empty() {
+        return Err("no ring".to_string());
+    }
+
+    // a hole belongs to the ring around it
+    let mut polygons: Vec<Polygon<f64>> = outers.into_iter().map(|r| Polygon::new(r, vec![])).collect();
+    for hole in holes {
+        let first = Point::from(hole.0[0]);
+        if let Some(polygon) = polygons.iter_mut().find(|p| p.contains(&first)) {
+            polygon.interiors_push(hole);
+        }
+    }
+    Ok(MultiPolygon::new(polygons))
+}
+
+fn parse_geojson(text: &str) -> Result<MultiPolygon<f64>, String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut polygons = Vec::new();
+    geojson_polygons(&json, &mut polygons)?;
+    if polygons.is_empty() {
+        return Err("no Polygon or MultiPolygon".to_string());
+    }
+    Ok(MultiPolygon::new(polygons))
+}
+
+fn geojson_polygons(json: &Value, polygons: &mut Vec<Polygon<f64>>) -> Result<(), String> {
+    match json["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in json["features"].as_array().ok_or("features isn't an array")? {
+                geojson_polygons(feature, polygons)?;
+            }
+        }
+        Some("Feature") => geojson_polygons(&json["geometry"], polygons)?,
+        Some("Polygon") => polygons.push(geojson_polygon(&json["coordinates"])?),
+        Some("MultiPolygon") => {
+            for polygon in json["coordinates"].as_array().ok_or("bad MultiPolygon coordinates")? {
+                polygons.push(geojson_polygon(polygon)?);
+            }
+        }
+        _ => {} // points, lines... aren't areas
+    }
+    Ok(())
+}
+
+// [exterior ring, holes...], a ring is [[lon, lat], ...]
+fn geojson_polygon(coordinates: &Value) -> Result<Polygon<f64>, String> {
+    let mut rings = Vec::new();
+    for ring in coordinates.as_array().ok_or("bad Polygon coordinates")? {
+        let mut points = Vec::new();
+        for point in ring.as_array().ok_or("bad ring")? {
+            match (point[0].as_f64(), point[1].as_f64()) {
+                (Some(x), Some(y)) => points.push(Coord { x, y }),
+                _ => return Err(format!("not a coordinate: {}", point)),
+            }
+        }
+        rings.push(LineString::from(points));
+    }
+    if rings.is_empty() || rings[0].0.len() < 3 {
+        return Err("a Polygon needs an exterior ring".to_string());
+    }
+    let exterior = rings.remove(0);
+    Ok(Polygon::new(exterior, rings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a square around (0, 0) with a hole in its north east quarter
+    const POLY: &str = "square
+1
+   -1.0 -1.0
+   1.0 -1.0
+   1.0 1.0
+   -1.0 1.0
+END
+!2
+   0.2 0.2
+   0.8 0.2
+   0.8 0.8
+   0.2 0.8
+END
+END
+";
+
+    fn at(lat: f64, lon: f64) -> LatLon {
+        LatLon { lat, lon }
+    }
+
+    #[test]
+    fn test_polygon_files() {
+        let poly = Area::Polygon(parse_poly(POLY).unwrap());
+        let geojson = Area::Polygon(
+            parse_geojson(
+                r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "properties": {}, "geometry":
+                {"type": "Polygon", "coordinates": [[[-1, -1], [1, -1], [1, 1], [-1, 1], [-1, -1]],
+                                                    [[0.2, 0.2], [0.8, 0.2], [0.8, 0.8], [0.2, 0.8], [0.2, 0.2]]]}}]}"#,
+            )
+            .unwrap(),
+        );
+        for area in [poly, geojson] {
+            assert!(area.contains(at(0.0, 0.0)));
+            assert!(area.contains(at(-1.0, 0.0))); // on the border
+            assert!(!area.contains(at(0.5, 0.5))); // in the hole
+            assert!(!area.contains(at(2.0, 0.0)));
+        }
+
+        assert!(parse_poly("name\n1\n 0 0\n 1 1\nEND\nEND\n").is_err());
+        assert!(parse_poly("name\n1\n 0 0\n 1 x\n 1 0\nEND\nEND\n").is_err());
+        assert!(parse_geojson(r#"{"type": "Point", "coordinates": [0, 0]}"#).is_err());
+    }
+
+    #[test]
+    fn test_clip_ways() {
+        // 1 -- 2 -- 3 -- 4 -- 5 along the equator, 3 is outside the box
+        let lons = [-0.5, -0.2, 2.0, 0.3, 0.6];
+        let nodes: HashMap<osm::NodeID, Node> = lons
+            .iter()
+            .enumerate()
+            .map(|(i, lon)| {
+                let id = osm::NodeID(i as i64 + 1);
+                (id, Node { id, location: at(0.0, *lon) })
+            })
+            .collect();
+        let way = || osm::Way {
+            nodes: (1..=5).map(osm::NodeID).collect(),
+            name: "Main Street".to_string(),
+            speed: 50.0,
+            restriction: None,
+        };
+        let clip = |keep_border_ways| Clip {
+            area: Area::Bbox(BoundingBox { min: at(-1.0, -1.0), max: at(1.0, 1.0) }),
+            keep_border_ways,
+        };
+
+        let parts = clip(false).way(&way(), &nodes);
+        let ids = |w: &osm::Way| w.nodes.iter().map(|n| n.0).collect::<Vec<_>>();
+        assert_eq!(parts.iter().map(ids).collect::<Vec<_>>(), vec![vec![1, 2], vec![4, 5]]);
+        assert_eq!(parts[1].name, "Main Street");
+
+        assert_eq!(ids(&clip(true).way(&way(), &nodes)[0]), vec![1, 2, 3, 4, 5]);
+        let outside = osm::Way { nodes: vec![osm::NodeID(3), osm::NodeID(9)], ..way() };
+        assert!(clip(true).way(&outside, &nodes).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+
+use crate::traffic::{WeekTime, DAY_SECONDS};
+
+// a small subset of the OSM `opening_hours` syntax, enough for the usual conditional restrictions:
+//   access:conditional = no @ (Mo-Fr 07:00-09:00,16:00-18:00; Sa 10:00-12:00)
+// supported: weekday lists and ranges (Mo,We / Fr-Mo), time spans (also past midnight, 22:00-06:00),
+// rules separated by `;`, and `24/7`. Anything else (holidays, months, sunrise...) is rejected,
+// the caller decides what to do (we simply ignore restrictions we don't understand).
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+const DAY_MINUTES: u32 = 24 * 60;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Rule {
+    days: [bool; 7],        // Monday first
+    spans: Vec<(u32, u32)>, // minutes since midnight, start > end means it ends the next day
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimeDomain {
+    rules: Vec<Rule>,
+}
+
+impl TimeDomain {
+    pub fn parse(input: &str) -> Result<TimeDomain, String> {
+        let mut rules = Vec::new();
+        for rule in input.split(';') {
+            let rule = rule.trim();
+            if rule.is_empty() {
+                continue;
+            }
+            rules.push(parse_rule(rule)?);
+        }
+        if rules.is_empty() {
+            return Err("empty time domain".to_string());
+        }
+        Ok(TimeDomain { rules })
+    }
+
+    pub fn contains(&self, t: WeekTime) -> bool {
+        let day = (t.0 / DAY_SECONDS) as usize % 7;
+        let minute = ((t.0 % DAY_SECONDS) / 60.0) as u32;
+        let yesterday = (day + 6) % 7;
+
+        self.rules.iter().any(|rule| {
+            rule.spans.iter().any(|&(start, end)| {
+                if start < end {
+                    rule.days[day] && start <= minute && minute < end
+                } else {
+                    // 22:00-06:00 on Friday covers Friday night and Saturday morning
+                    (rule.days[day] && minute >= start) || (rule.days[yesterday] && minute < end)
+                }
+            })
+        })
+    }
+}
+
+// `no @ (Mo-Fr 07:00-09:00); destination @ (Sa)` -> when the value is `no`.
+// returns None when there is no `no` condition or we can't understand it.
+pub fn parse_conditional_restriction(value: &str) -> Option<TimeDomain> {
+    let mut rules = Vec::new();
+    for condition in split_top_level(value) {
+        let (restriction, when) = match condition.split_once('@') {
+            Some(x) => x,
+            None => continue,
+        };
+        if restriction.trim() != "no" {
+            continue;
+        }
+        let when = when.trim().trim_start_matches('(').trim_end_matches(')');
+        match TimeDomain::parse(when) {
+            Ok(domain) => rules.extend(domain.rules),
+            Err(_) => return None,
+        }
+    }
+
+    if rules.is_empty() {
+        None
+    } else {
+        Some(TimeDomain { rules })
+    }
+}
+
+// `;` separates conditions, but only outside of the parentheses
+fn split_top_level(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+fn parse_rule(rule: &str) -> Result<Rule, String> {
+    if rule == "24/7" {
+        return Ok(Rule {
+            days: [true; 7],
+            spans: vec![(0, DAY_MINUTES)],
+        });
+    }
+
+    let mut days = [true; 7];
+    let mut times = rule;
+    if rule.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        let (selector, rest) = rule.split_once(' ').unwrap_or((rule, ""));
+        days = parse_weekdays(selector)?;
+        times = rest.trim();
+    }
+
+    let spans = if times.is_empty() {
+        vec![(0, DAY_MINUTES)]
+    } else {
+        times
+            .split(',')
+            .map(|span| parse_span(span.trim()))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    Ok(Rule { days, spans })
+}
+
+fn parse_weekday(day: &str) -> Result<usize, String> {
+    WEEKDAYS
+        .iter()
+        .position(|d| *d == day)
+        .ok_or(format!("unsupported weekday: {}", day))
+}
+
+fn parse_weekdays(selector: &str) -> Result<[bool; 7], String> {
+    let mut days = [false; 7];
+    for part in selector.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let from = parse_weekday(from)?;
+                let to = parse_weekday(to)?;
+                // Fr-Mo wraps around the weekend
+                let mut d = from;
+                loop {
+                    days[d] = true;
+                    if d == to {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days[parse_weekday(part)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+fn parse_time(time: &str) -> Result<u32, String> {
+    let (h, m) = time
+        .split_once(':')
+        .ok_or(format!("bad time: {}", time))?;
+    let h: u32 = h.parse().map_err(|_| format!("bad time: {}", time))?;
+    let m: u32 = m.parse().map_err(|_| format!("bad time: {}", time))?;
+    if h > 24 || m > 59 || (h == 24 && m > 0) {
+        return Err(format!("bad time: {}", time));
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_span(span: &str) -> Result<(u32, u32), String> {
+    let (start, end) = span
+        .split_once('-')
+        .ok_or(format!("bad time span: {}", span))?;
+    Ok((parse_time(start)?, parse_time(end)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: usize, hour: u32, minute: u32) -> WeekTime {
+        WeekTime(day as f64 * DAY_SECONDS + (hour * 3600 + minute * 60) as f64)
+    }
+
+    #[test]
+    fn test_weekday_rush_hours() {
+        let domain =
+            parse_conditional_restriction("no @ (Mo-Fr 07:00-09:00,16:00-18:00)").unwrap();
+        assert!(domain.contains(at(0, 7, 30)));
+        assert!(domain.contains(at(4, 17, 0)));
+        assert!(!domain.contains(at(0, 9, 0)));
+        assert!(!domain.contains(at(5, 8, 0))); // Saturday
+    }
+
+    #[test]
+    fn test_overnight_and_wrapping_days() {
+        let domain = TimeDomain::parse("Fr-Mo 22:00-06:00").unwrap();
+        assert!(domain.contains(at(4, 23, 0))); // Friday night
+        assert!(domain.contains(at(5, 5, 0))); // Saturday morning
+        assert!(domain.contains(at(1, 5, 0))); // Tuesday morning, Monday night
+        assert!(!domain.contains(at(2, 5, 0)));
+    }
+
+    #[test]
+    fn test_only_no_restrictions() {
+        assert!(parse_conditional_restriction("destination @ (Mo-Fr)").is_none());
+        assert!(parse_conditional_restriction("no @ (PH)").is_none());
+        let domain = parse_conditional_restriction("delivery @ (Sa); no @ (Su)").unwrap();
+        assert!(domain.contains(at(6, 12, 0)));
+        assert!(!domain.contains(at(5, 12, 0)));
+    }
+}
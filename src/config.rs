@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    net::IpAddr,
+};
+
+use serde::Deserialize;
+
+// settings loaded at startup from a TOML file, see config.example.toml.
+// every value has a default, so an empty file (or no file at all) behaves like before.
+//
+// precedence: defaults < config file < environment variables < command line flags.
+// environment variables are `SIMPLE_NAV_<SECTION>__<KEY>`, e.g.
+//   SIMPLE_NAV_SERVER__PORT=8080
+//   SIMPLE_NAV_LOGGING__LEVEL=debug
+//   SIMPLE_NAV_SERVER__CORS_ORIGINS='["https://example.com"]'
+// the value is read as TOML when possible (numbers, booleans, arrays), as a string otherwise.
+
+pub const ENV_PREFIX: &str = "SIMPLE_NAV_";
+
+// the name of the only region when `regions` isn't used
+pub const DEFAULT_REGION: &str = "default";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => f.write_str(&format!("can't read config file {}: {}", path, e)),
+            ConfigError::Parse(e) => f.write_str(&format!("invalid config: {}", e)),
+            ConfigError::Invalid(e) => f.write_str(&format!("invalid config: {}", e)),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>, // "car" if there is one, else the first enabled profile
+    pub server: ServerConfig,
+    pub data: DataConfig,
+    pub regions: BTreeMap<String, RegionConfig>, // several extracts in one process, instead of data.path
+    pub import: ImportConfig,
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    pub snapping: SnappingConfig,
+    pub limits: LimitsConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    pub threads: Option<usize>,    // default: one per cpu core
+    pub cors_origins: Vec<String>, // empty: any origin
+    pub web_dir: String,           // static files of the web page, the built-in page if it doesn't exist
+    pub admin_token: Option<String>, // enables POST /admin/reload
+    pub watch: bool,                 // reload the data when the files change
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DataConfig {
+    pub path: Option<String>,           // osm extract or prepared graph
+    pub speed_profiles: Option<String>, // weekly speeds csv
+}
+
+// one extract, e.g. [regions.delaware] data = "delaware-latest.osm.pbf"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    pub data: String,                      // osm extract or prepared graph
+    #[serde(default)]
+    pub prepared: BTreeMap<String, String>, // profile -> prepared graph, instead of `data`
+}
+
+// which ways are imported at all (for every profile)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportConfig {
+    pub way_tag: String,                       // ways without this tag are ignored
+    pub exclude: HashMap<String, Vec<String>>, // tag -> values, e.g. highway = ["proposed"]
+    pub distance: DistanceMetric,              // how the edge lengths are computed
+    pub clip: ClipConfig,
+}
+
+// haversine (a sphere) is within 0.5% of the ellipsoid and the fastest,
+// vincenty and geodesic (Karney) are exact to the millimeter on the WGS84 ellipsoid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceMetric {
+    #[default]
+    Haversine,
+    Vincenty,
+    Geodesic,
+}
+
+// only import the roads in an area, e.g. a city out of a state extract
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipConfig {
+    pub bbox: Option<[f64; 4]>,  // min_lon, min_lat, max_lon, max_lat
+    pub polygon: Option<String>, // GeoJSON or .poly file, instead of bbox
+    pub keep_border_ways: bool,  // keep the ways crossing the border whole, else they are cut at it
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub speeds: BTreeMap<String, f64>, // value of the way tag (highway) -> km/h
+    #[serde(default)]
+    pub fallback_speed: Option<f64>,   // km/h for values not in `speeds`, none: not routable
+    #[serde(default = "enabled")]
+    pub use_maxspeed: bool,            // the `maxspeed` tag wins over `speeds`
+    #[serde(default)]
+    pub max_speed: Option<f64>,        // km/h cap, e.g. for bicycles
+    #[serde(default)]
+    pub data: Option<String>,          // prepared graph of this profile, instead of data.path
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnappingConfig {
+    pub max_distance: Option<f64>, // meters, coordinates further away from any road are rejected
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_coordinates: usize, // /route and /match
+    pub max_table_size: usize,  // /table
+    pub max_nearest: usize,     // /nearest
+    pub max_radius: f64,        // meters, the radiuses of /match and /nearest
+    pub max_concurrent_queries: usize, // searches running at once, default: one per cpu core
+    pub max_queued_queries: usize,     // waiting for their turn, 503 beyond
+    pub query_timeout: f64,            // seconds, waiting included
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,  // error, warn, info, debug or trace
+    pub format: String, // text (for humans) or json (one object per line, for log collectors)
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "0.0.0.0".to_string(),
+            port: 3000,
+            threads: None,
+            cors_origins: vec![],
+            web_dir: "web".to_string(),
+            admin_token: None,
+            watch: false,
+        }
+    }
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        ImportConfig {
+            way_tag: "highway".to_string(),
+            exclude: HashMap::new(),
+            distance: DistanceMetric::default(),
+            clip: ClipConfig::default(),
+        }
+    }
+}
+
+impl Default for ProfileConfig {
+    // the speeds we always used: every highway is routable, unknown ones at walking speed
+    fn default() -> Self {
+        let speeds = [
+            ("motorway", 100.0),
+            ("trunk", 80.0),
+            ("primary", 65.0),
+            ("secondary", 55.0),
+            ("tertiary", 45.0),
+            ("motorway_link", 50.0),
+            ("trunk_link", 50.0),
+            ("primary_link", 40.0),
+            ("secondary_link", 40.0),
+            ("tertiary_link", 40.0),
+            ("unclassified", 35.0),
+            ("residential", 30.0),
+            ("service", 15.0),
+            ("track", 15.0),
+            ("living_street", 10.0),
+        ];
+        ProfileConfig {
+            enabled: true,
+            speeds: speeds.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            fallback_speed: Some(5.0), // footway, path, steps...
+            use_maxspeed: true,
+            max_speed: None,
+            data: None,
+        }
+    }
+}
+
+impl ProfileConfig {
+    // km/h on a way, None if this profile can't use it
+    pub fn speed(&self, way_tag_value: &str, maxspeed: Option<f64>) -> Option<f64> {
+        let speed = self.speeds.get(way_tag_value).copied().or(self.fallback_speed)?;
+        let speed = match maxspeed {
+            Some(m) if self.use_maxspeed => m,
+            _ => speed,
+        };
+        Some(match self.max_speed {
+            Some(cap) => speed.min(cap),
+            None => speed,
+        })
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_coordinates: 100,
+            max_table_size: 100,
+            max_nearest: 100,
+            max_radius: 1000.0,
+            max_concurrent_queries: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            max_queued_queries: 100,
+            query_timeout: 30.0,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: "text".to_string(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut profiles = BTreeMap::new();
+        profiles.insert("car".to_string(), ProfileConfig::default());
+        Config {
+            default_profile: None,
+            server: ServerConfig::default(),
+            data: DataConfig::default(),
+            regions: BTreeMap::new(),
+            import: ImportConfig::default(),
+            profiles,
+            snapping: SnappingConfig::default(),
+            limits: LimitsConfig::default(),
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    // `path`: the config file, None for the defaults. environment variables are applied in both cases.
+    pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+        let content = match path {
+            Some(p) => fs::read_to_string(p).map_err(|e| ConfigError::Io(p.to_string(), e))?,
+            None => String::new(),
+        };
+        Config::parse(&content, std::env::vars())
+    }
+
+    pub fn parse(
+        content: &str,
+        env: impl Iterator<Item = (String, String)>,
+    ) -> Result<Config, ConfigError> {
+        let mut table: toml::Table =
+            toml::from_str(content).map_err(|e| ConfigError::Parse(e.to_string()))?;
+
+        for (key, value) in env {
+            let path = match key.strip_prefix(ENV_PREFIX) {
+                Some(p) => p.to_lowercase(),
+                None => continue,
+            };
+            let path: Vec<&str> = path.split("__").collect();
+            let value = parse_env_value(&table, &path, &value);
+            set_path(&mut table, &path, value).map_err(|e| ConfigError::Invalid(format!("{}: {}", key, e)))?;
+        }
+
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        if self.server.bind.parse::<IpAddr>().is_err() {
+            return invalid(format!("server.bind: `{}` is not an ip address", self.server.bind));
+        }
+        if self.server.threads == Some(0) {
+            return invalid("server.threads must be at least 1".to_string());
+        }
+        if self.import.way_tag.is_empty() {
+            return invalid("import.way_tag can't be empty".to_string());
+        }
+
+        let clip = &self.import.clip;
+        if clip.bbox.is_some() && clip.polygon.is_some() {
+            return invalid("import.clip: bbox and polygon can't be used together".to_string());
+        }
+        if let Some([min_lon, min_lat, max_lon, max_lat]) = clip.bbox {
+            let lons = (-180.0..=180.0).contains(&min_lon) && (-180.0..=180.0).contains(&max_lon);
+            let lats = (-90.0..=90.0).contains(&min_lat) && (-90.0..=90.0).contains(&max_lat);
+            if !lons || !lats || min_lon >= max_lon || min_lat >= max_lat {
+                return invalid("import.clip.bbox must be [min_lon, min_lat, max_lon, max_lat]".to_string());
+            }
+        }
+
+        if self.enabled_profiles().is_empty() {
+            return invalid("no enabled profile".to_string());
+        }
+        for (name, profile) in &self.profiles {
+            if profile.speeds.is_empty() && profile.fallback_speed.is_none() {
+                return invalid(format!("profiles.{}: no speeds and no fallback_speed, nothing is routable", name));
+            }
+            let speeds = profile.speeds.iter().map(|(k, v)| (k.as_str(), *v));
+            let others = [("fallback_speed", profile.fallback_speed), ("max_speed", profile.max_speed)];
+            for (what, speed) in speeds.chain(others.into_iter().filter_map(|(k, v)| Some((k, v?)))) {
+                if !speed.is_finite() || speed <= 0.0 {
+                    return invalid(format!("profiles.{}: speed of {} must be positive, got {}", name, what, speed));
+                }
+            }
+        }
+        if let Some(default) = &self.default_profile {
+            if !self.enabled_profiles().contains(&default.as_str()) {
+                return invalid(format!("default_profile `{}` is not an enabled profile", default));
+            }
+        }
+
+        if self.data.path.is_some() && !self.regions.is_empty() {
+            return invalid("data.path and regions can't be used together".to_string());
+        }
+        for (name, region) in &self.regions {
+            if let Some(profile) = region.prepared.keys().find(|p| !self.enabled_profiles().contains(&p.as_str())) {
+                return invalid(format!("regions.{}.prepared: `{}` is not an enabled profile", name, profile));
+            }
+        }
+
+        if self.snapping.max_distance.is_some_and(|d| !d.is_finite() || d <= 0.0) {
+            return invalid("snapping.max_distance must be positive".to_string());
+        }
+        let limits = &self.limits;
+        if limits.max_coordinates < 2 || limits.max_table_size < 1 || limits.max_nearest < 1 {
+            return invalid("limits: max_coordinates must be at least 2, the others at least 1".to_string());
+        }
+        if limits.max_concurrent_queries < 1 {
+            return invalid("limits.max_concurrent_queries must be at least 1".to_string());
+        }
+        if !limits.query_timeout.is_finite() || limits.query_timeout <= 0.0 {
+            return invalid("limits.query_timeout must be positive".to_string());
+        }
+        if !limits.max_radius.is_finite() || limits.max_radius <= 0.0 {
+            return invalid("limits.max_radius must be positive".to_string());
+        }
+        if !matches!(self.logging.level.as_str(), "error" | "warn" | "info" | "debug" | "trace") {
+            return invalid(format!("logging.level: unknown level `{}`", self.logging.level));
+        }
+        if !matches!(self.logging.format.as_str(), "text" | "json") {
+            return invalid(format!("logging.format must be text or json, got `{}`", self.logging.format));
+        }
+        Ok(())
+    }
+
+    pub fn enabled_profiles(&self) -> Vec<&str> {
+        self.profiles
+            .iter()
+            .filter(|(_, p)| p.enabled)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    pub fn default_profile(&self) -> &str {
+        let enabled = self.enabled_profiles();
+        match &self.default_profile {
+            Some(name) => name,
+            None if enabled.contains(&"car") => "car",
+            None => enabled[0],
+        }
+    }
+}
+
+// numbers, booleans and arrays are parsed, anything else is a plain string (`12#x` too, it's not
+// a number with a comment). So is a number the key doesn't take: an admin token can be all digits.
+fn parse_env_value(table: &toml::Table, path: &[&str], value: &str) -> toml::Value {
+    let raw = toml::Value::String(value.to_string());
+    let parsed = match value.parse::<toml::Value>() {
+        Ok(v) if v.is_str() => return v, // quoted
+        Ok(v) => v,
+        Err(_) => return raw,
+    };
+    let accepted = |value: &toml::Value| {
+        let mut table = table.clone();
+        set_path(&mut table, path, value.clone()).is_ok() && toml::Value::Table(table).try_into::<Config>().is_ok()
+    };
+    if !accepted(&parsed) && accepted(&raw) {
+        raw
+    } else {
+        parsed
+    }
+}
+
+fn set_path(table: &mut toml::Table, path: &[&str], value: toml::Value) -> Result<(), String> {
+    match path {
+        [] => Err("empty key".to_string()),
+        [key] => {
+            table.insert(key.to_string(), value);
+            Ok(())
+        }
+        [key, rest @ ..] => {
+            let entry = table
+                .entry(key.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            match entry {
+                toml::Value::Table(t) => set_path(t, rest, value),
+                _ => Err(format!("`{}` is not a section", key)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::parse("", env(&[])).unwrap();
+        assert_eq!(config.server.port, 3000);
+        assert_eq!(config.default_profile(), "car");
+        let car = &config.profiles["car"];
+        assert_eq!(car.speed("residential", None), Some(30.0));
+        assert_eq!(car.speed("footway", None), Some(5.0));
+        assert_eq!(car.speed("residential", Some(50.0)), Some(50.0));
+    }
+
+    #[test]
+    fn test_file_and_env() {
+        let content = r#"
+            [server]
+            port = 8080
+
+            [profiles.bike]
+            speeds = { cycleway = 18.0, residential = 18.0 }
+            max_speed = 20.0
+
+            [import]
+            distance = "geodesic"
+
+            [import.exclude]
+            highway = ["proposed", "construction"]
+        "#;
+        let config = Config::parse(
+            content,
+            env(&[
+                ("SIMPLE_NAV_SERVER__PORT", "9090"),
+                ("SIMPLE_NAV_SERVER__CORS_ORIGINS", "[\"https://example.com\"]"),
+                ("SIMPLE_NAV_LOGGING__LEVEL", "debug"),
+                ("OTHER_VARIABLE", "ignored"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 9090);
+        assert_eq!(config.server.cors_origins, vec!["https://example.com"]);
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.enabled_profiles(), vec!["bike"]);
+        assert_eq!(config.default_profile(), "bike");
+        let bike = &config.profiles["bike"];
+        assert_eq!(bike.speed("motorway", None), None);
+        assert_eq!(bike.speed("cycleway", Some(50.0)), Some(20.0));
+        assert_eq!(config.import.exclude["highway"].len(), 2);
+        assert_eq!(config.import.distance, DistanceMetric::Geodesic);
+    }
+
+    #[test]
+    fn test_env_strings() {
+        let config = |vars: &[(&str, &str)]| Config::parse("", env(vars));
+        let token = |value: &str| config(&[("SIMPLE_NAV_SERVER__ADMIN_TOKEN", value)]).unwrap().server.admin_token;
+        assert_eq!(token("12345"), Some("12345".to_string()));
+        assert_eq!(token("12#x"), Some("12#x".to_string()));
+        assert_eq!(token("true"), Some("true".to_string()));
+        assert_eq!(token("\"quoted\""), Some("quoted".to_string()));
+
+        // still numbers where numbers are expected
+        assert_eq!(config(&[("SIMPLE_NAV_SERVER__PORT", "9090")]).unwrap().server.port, 9090);
+        assert!(config(&[("SIMPLE_NAV_SERVER__PORT", "90#90")]).is_err());
+        assert!(config(&[("SIMPLE_NAV_LIMITS__MAX_RADIUS", "0")]).unwrap_err().to_string().contains("max_radius"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |content: &str| Config::parse(content, env(&[])).unwrap_err().to_string();
+        assert!(error("[server]\nprot = 1").contains("prot"));
+        assert!(error("[server]\nbind = \"localhost:3\"").contains("server.bind"));
+        assert!(error("[profiles.car]\nspeeds = { primary = -1.0 }").contains("primary"));
+        assert!(error("default_profile = \"boat\"").contains("boat"));
+        assert!(error("[logging]\nlevel = \"loud\"").contains("loud"));
+        assert!(error("[logging]\nformat = \"xml\"").contains("xml"));
+        assert!(error("[data]\npath = \"a.pbf\"\n[regions.b]\ndata = \"b.pbf\"").contains("regions"));
+        assert!(error("[regions.b]\ndata = \"b.pbf\"\nprepared = { boat = \"b.graph\" }").contains("boat"));
+        assert!(error("[import]\ndistance = \"manhattan\"").contains("manhattan"));
+        assert!(error("[limits]\nmax_radius = -1.0").contains("max_radius"));
+        assert!(error("[limits]\nmax_radius = inf").contains("max_radius"));
+        assert!(error("[import.clip]\nbbox = [-75.6, 39.8, -75.5, 39.7]").contains("bbox"));
+        assert!(error("[import.clip]\nbbox = [-75.6, 39.7, -75.5, 39.8]\npolygon = \"a.poly\"").contains("together"));
+    }
+
+    #[test]
+    fn test_example_file() {
+        let config = Config::parse(include_str!("../config.example.toml"), env(&[])).unwrap();
+        assert_eq!(config.enabled_profiles(), vec!["bike", "car"]);
+        assert_eq!(config.default_profile(), "car");
+        assert_eq!(config.profiles["bike"].speed("motorway", None), None);
+        assert_eq!(config.profiles["car"].speed("motorway", None), Some(100.0));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    engine::Engine,
+    graph::{BoundingBox, LatLon},
+};
+
+// everything the server routes on: one engine per region and profile.
+//
+// regions are separate extracts (e.g. Delaware and Maryland), a route never crosses them.
+// A request names its region, or gets the smallest one whose bounds contain all its coordinates.
+
+pub struct Dataset {
+    regions: BTreeMap<String, Region>,
+    pub(crate) default_profile: String,
+    pub(crate) version: u64, // 1 for the first dataset, +1 for every reload
+    pub(crate) loaded_at: DateTime<Utc>,
+    pub(crate) load_seconds: f64,
+}
+
+#[derive(Default)]
+pub(crate) struct Region {
+    engines: BTreeMap<String, Engine>, // profile name -> engine
+    profiles: BTreeMap<String, ProfileInfo>,
+    bounds: Option<BoundingBox>, // of all the profiles, None while empty
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProfileInfo {
+    source: String, // the osm extract or prepared graph
+    nodes: usize,
+    edges: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DatasetError {
+    UnknownRegion(String),
+    UnknownProfile(String),
+    OutsideRegions, // no region contains all the coordinates
+}
+
+impl std::fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatasetError::UnknownRegion(r) => f.write_str(&format!("unknown region: {}", r)),
+            DatasetError::UnknownProfile(p) => f.write_str(&format!("unknown profile: {}", p)),
+            DatasetError::OutsideRegions => f.write_str("no region contains all the coordinates"),
+        }
+    }
+}
+
+impl Region {
+    pub(crate) fn engine(&self, profile: &str) -> Result<&Engine, DatasetError> {
+        self.engines
+            .get(profile)
+            .ok_or_else(|| DatasetError::UnknownProfile(profile.to_string()))
+    }
+
+    pub(crate) fn bounds(&self) -> Option<BoundingBox> {
+        self.bounds
+    }
+
+    pub(crate) fn profiles(&self) -> &BTreeMap<String, ProfileInfo> {
+        &self.profiles
+    }
+
+    fn contains(&self, points: &[LatLon]) -> bool {
+        self.bounds.is_some_and(|b| points.iter().all(|p| b.contains(*p)))
+    }
+}
+
+impl Dataset {
+    pub fn new(default_profile: &str) -> Dataset {
+        Dataset {
+            regions: BTreeMap::new(),
+            default_profile: default_profile.to_string(),
+            version: 0,
+            loaded_at: Utc::now(),
+            load_seconds: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, region: &str, profile: &str, engine: Engine, source: &str) {
+        let region = self.regions.entry(region.to_string()).or_default();
+        let graph = engine.graph();
+        region.bounds = match (region.bounds, graph.bounds()) {
+            (Some(a), Some(b)) => Some(a.union(&b)),
+            (a, b) => a.or(b),
+        };
+        let info = ProfileInfo {
+            source: source.to_string(),
+            nodes: graph.get_total_nodes(),
+            edges: graph.get_total_edges(),
+        };
+        region.profiles.insert(profile.to_string(), info);
+        region.engines.insert(profile.to_string(), engine);
+    }
+
+    pub(crate) fn regions(&self) -> &BTreeMap<String, Region> {
+        &self.regions
+    }
+
+    // every profile of any region
+    pub(crate) fn profiles(&self) -> Vec<&str> {
+        let mut profiles: Vec<&str> = self
+            .regions
+            .values()
+            .flat_map(|r| r.engines.keys().map(|p| p.as_str()))
+            .collect();
+        profiles.sort();
+        profiles.dedup();
+        profiles
+    }
+
+    // `name` if given, else the smallest region containing all the points.
+    // A lone region is used whatever the points: snapping decides if they're too far.
+    pub(crate) fn region(&self, name: Option<&str>, points: &[LatLon]) -> Result<&Region, DatasetError> {
+        if let Some(name) = name {
+            return self
+                .regions
+                .get(name)
+                .ok_or_else(|| DatasetError::UnknownRegion(name.to_string()));
+        }
+        if self.regions.len() == 1 {
+            return Ok(self.regions.values().next().unwrap());
+        }
+        self.regions
+            .values()
+            .filter(|r| r.contains(points))
+            .min_by(|a, b| {
+                let area = |r: &Region| r.bounds.map_or(0.0, |b| b.area());
+                area(a).total_cmp(&area(b))
+            })
+            .ok_or(DatasetError::OutsideRegions)
+    }
+
+    // profile None: the default profile
+    pub(crate) fn engine(
+        &self,
+        region: Option<&str>,
+        profile: Option<&str>,
+        points: &[LatLon],
+    ) -> Result<&Engine, DatasetError> {
+        self.region(region, points)?
+            .engine(profile.unwrap_or(&self.default_profile))
+    }
+
+    // the files it was loaded from
+    pub(crate) fn sources(&self) -> Vec<String> {
+        self.regions
+            .values()
+            .flat_map(|r| r.profiles.values().map(|p| p.source.clone()))
+            .collect()
+    }
+
+    // before it's used by the server
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.regions.is_empty() {
+            return Err("no region".to_string());
+        }
+        for (name, region) in &self.regions {
+            if !region.engines.contains_key(&self.default_profile) {
+                return Err(format!("region {}: no engine for the default profile {}", name, self.default_profile));
+            }
+            for (profile, engine) in &region.engines {
+                if engine.graph().get_total_nodes() == 0 {
+                    return Err(format!("region {}, profile {}: the graph is empty", name, profile));
+                }
+                let problems = engine.graph().validate();
+                if let Some(first) = problems.first() {
+                    return Err(format!(
+                        "region {}, profile {}: {} problems, e.g. {}",
+                        name,
+                        profile,
+                        problems.len(),
+                        first
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::Graph, osm};
+
+    // a street going north east from `lat`
+    fn engine(lat: f64) -> Engine {
+        let points: Vec<_> = (1..=3).map(|i| (i, lat + i as f64 * 0.1, -75.0 + i as f64 * 0.1)).collect();
+        let (nodes, way) = osm::street("Main Street", &points);
+        Engine::from_graph(Graph::build(nodes, vec![way]))
+    }
+
+    #[test]
+    fn test_region_dispatch() {
+        let mut dataset = Dataset::new("car");
+        dataset.add("north", "car", engine(39.0), "north.graph");
+        dataset.add("big", "car", engine(38.0), "big.graph");
+        dataset.add("big", "bike", engine(39.0), "big-bike.graph"); // bounds 38.1 .. 39.3
+        assert!(dataset.validate().is_ok());
+
+        let at = |lat| LatLon { lat, lon: -74.8 };
+        let region_of = |points: &[LatLon]| {
+            dataset
+                .region(None, points)
+                .map(|r| r.bounds().unwrap().min.lat)
+        };
+        assert_eq!(region_of(&[at(39.15), at(39.25)]), Ok(39.1)); // both contain them, north is smaller
+        assert_eq!(region_of(&[at(38.15), at(39.25)]), Ok(38.1));
+        assert_eq!(region_of(&[at(38.15), at(40.0)]).unwrap_err(), DatasetError::OutsideRegions);
+        assert!(dataset.region(Some("south"), &[]).is_err());
+        assert!(dataset.engine(Some("north"), Some("bike"), &[]).is_err());
+        assert!(dataset.engine(Some("north"), None, &[]).is_ok());
+    }
+}
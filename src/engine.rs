@@ -1,19 +1,62 @@
-use std::error::Error;
+use std::{
+    cell::RefCell,
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+};
+
+use chrono::{DateTime, Duration, FixedOffset};
+use thread_local::ThreadLocal;
 
 use crate::{
-    graph::{shortest_path, Graph, LatLon, NodeID},
-    parser::{parse_map, NodeLocation, SpaitialIndex},
+    changes::{self, ChangeStats},
+    clip::Clip,
+    graph::{EdgePosition, Graph, LatLon, NoRouteFound, NodeIndex, RouteSegment, SearchContext, Seed},
+    instructions::{build_steps, Step},
+    monitoring,
+    osm,
+    config::{ImportConfig, ProfileConfig},
+    osmxml::XmlSource,
+    parser::{build_graph_reported, read_roads_with, Roads},
+    report::ImportReport,
+    spatialindex::{Neighbor, SpatialIndex},
+    traffic::{load_speed_profiles, WeekTime},
 };
 
 pub struct Engine {
-    spaitial_index: SpaitialIndex,
+    spaitial_index: SpatialIndex,
     graph: Graph,
+    max_snap_distance: Option<f64>, // meters, see set_max_snap_distance
+    search_contexts: ThreadLocal<RefCell<SearchContext>>, // see with_search_context
+    roads: Option<Roads>, // what the graph was built from, only to apply change files (see prepare)
+    import_report: Option<ImportReport>, // when the graph was built from osm data, see import_report
 }
 
 pub struct RouteResult {
     pub total_distance: f64,
-    pub route_path: Vec<LatLon>,
-    pub nodes: Vec<NodeID>,
+    pub total_duration: f64, // seconds
+    pub route_path: Vec<LatLon>,     // every osm node along the route
+    pub nodes: Vec<NodeIndex>,       // the graph nodes it goes through
+    pub segments: Vec<RouteSegment>, // the edges between them, and the parts of edges at both ends
+    pub arrival: Option<DateTime<FixedOffset>>, // only for time-dependent routing (a departure time is given)
+}
+
+// (distance, duration) from each source (rows) to each destination (columns), None when there is no route
+pub type DistanceMatrix = Vec<Vec<Option<(f64, f64)>>>;
+
+// where a coordinate snaps to
+#[derive(Clone, Copy, Debug)]
+pub struct Snapped {
+    pub at: SnappedAt,
+    pub osm_id: osm::NodeID,
+    pub location: LatLon,
+    pub distance: f64, // meters from the input coordinate
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnappedAt {
+    Node(NodeIndex),
+    ShapePoint(EdgePosition), // routes leave and arrive through the ends of its edge
 }
 
 // naming is hard: it's an abstraction of things
@@ -22,8 +65,20 @@ pub enum EngineErrors {
     CantFindNearestNode,
     CantFindRoute,
     CantFindLatLon,
+    NotAPreparedFile,
+    PreparedFileVersion { found: u32, expected: u32 },
+    NoRoadsToChange, // a graph built without `prepare` can't apply change files
 }
 
+// prepared graph file: magic, format version (little endian u32), the bincode encoded Graph,
+// then the Option<Roads> to apply change files to (only read by load_with_roads).
+// the spatial index is rebuilt when loading, it's fast and keeps the file small.
+const PREPARED_MAGIC: &[u8; 8] = b"SNAVGRPH";
+// 2: integer weights and coordinates, 3: road ways for change files, 4: north-south edge lengths fixed,
+// 5: chains of nodes compressed into edges with shapes, 6: road ways stored without distances,
+// 7: edges without their from_node
+const PREPARED_VERSION: u32 = 7;
+
 impl std::fmt::Display for EngineErrors {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!("engine error: {:?}", self))
@@ -41,74 +96,505 @@ impl Engine {
     // Box<dyn Error> == &dyn Error : pointer
     // Box<dyn Error> (fixed size like String) != dyn Error (unkown size/unsized type)
     pub fn build(osmfile: &str) -> Result<Engine, Box<dyn Error>> {
+        Engine::build_with(&[osmfile], &ImportConfig::default(), &ProfileConfig::default())
+    }
+
+    // which ways are imported and how fast they are comes from the config.
+    // several extracts are merged into one graph (the nodes and ways they share only once).
+    pub fn build_with(osmfiles: &[&str], import: &ImportConfig, profile: &ProfileConfig) -> Result<Engine, Box<dyn Error>> {
+        Engine::import(osmfiles, import, profile, false)
+    }
+
+    // build_with for `simple-nav preprocess`: the engine keeps the road ways and their nodes,
+    // saved with the graph so that change files can be applied to it later (see apply_changes)
+    pub fn prepare(osmfiles: &[&str], import: &ImportConfig, profile: &ProfileConfig) -> Result<Engine, Box<dyn Error>> {
+        Engine::import(osmfiles, import, profile, true)
+    }
+
+    fn import(osmfiles: &[&str], import: &ImportConfig, profile: &ProfileConfig, keep_roads: bool) -> Result<Engine, Box<dyn Error>> {
         // ? report error, two methods to fix:
         // 1. implement Error for ParseError
         // 2. .map_err() to convert error type (make ParseError compatible with Error trait)
         // 3. implement From<ParseError> for Error (Best one)
-        let (graph, tree) = parse_map(osmfile)?;
+        let clip = Clip::from_config(&import.clip)?; // before reading, a bad polygon fails early
+        let roads = read_roads_with(osmfiles, import, profile)?;
+        let (graph, tree, report) = build_graph_reported(&roads, clip.as_ref(), import.distance)?;
 
         // when s is destroyed (out of scope), compiler will reclaim the memory of underlying string.
         // let s = Box::new(String::from("hello"));
         Ok(Engine {
             spaitial_index: tree,
             graph,
+            max_snap_distance: None,
+            search_contexts: ThreadLocal::new(),
+            roads: keep_roads.then_some(roads),
+            import_report: Some(report),
+        })
+    }
+
+    // a graph written by `save` (see `simple-nav preprocess`)
+    #[tracing::instrument]
+    pub fn load(prepared_file: &str) -> Result<Engine, Box<dyn Error>> {
+        Engine::read_prepared(prepared_file, false)
+    }
+
+    // load with what apply_changes needs, which a server doesn't
+    #[tracing::instrument]
+    pub fn load_with_roads(prepared_file: &str) -> Result<Engine, Box<dyn Error>> {
+        Engine::read_prepared(prepared_file, true)
+    }
+
+    fn read_prepared(prepared_file: &str, with_roads: bool) -> Result<Engine, Box<dyn Error>> {
+        let mut reader = BufReader::new(File::open(prepared_file)?);
+
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic).map_err(|_| EngineErrors::NotAPreparedFile)?;
+        if &magic != PREPARED_MAGIC {
+            return Err(Box::new(EngineErrors::NotAPreparedFile));
+        }
+        let mut version = [0_u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != PREPARED_VERSION {
+            return Err(Box::new(EngineErrors::PreparedFileVersion {
+                found: version,
+                expected: PREPARED_VERSION,
+            }));
+        }
+
+        let graph: Graph = bincode::deserialize_from(&mut reader)?;
+        let mut engine = Engine::from_graph(graph);
+        if with_roads {
+            engine.roads = bincode::deserialize_from(reader)?;
+        }
+        Ok(engine)
+    }
+
+    pub fn from_graph(graph: Graph) -> Engine {
+        Engine {
+            spaitial_index: SpatialIndex::from_graph(&graph),
+            graph,
+            max_snap_distance: None,
+            search_contexts: ThreadLocal::new(),
+            roads: None,
+            import_report: None,
+        }
+    }
+
+    pub fn save(&self, prepared_file: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(prepared_file)?);
+        writer.write_all(PREPARED_MAGIC)?;
+        writer.write_all(&PREPARED_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, &self.graph)?;
+        bincode::serialize_into(&mut writer, &self.roads)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // a prepared graph file or an osm extract, whatever `path` is
+    pub fn open(path: &str) -> Result<Engine, Box<dyn Error>> {
+        Engine::open_with(path, &ImportConfig::default(), &ProfileConfig::default())
+    }
+
+    // the import settings and the profile are only used for osm extracts,
+    // a prepared graph already has the speeds of the profile it was built with.
+    pub fn open_with(path: &str, import: &ImportConfig, profile: &ProfileConfig) -> Result<Engine, Box<dyn Error>> {
+        let mut magic = [0_u8; 8];
+        let is_prepared = File::open(path)?.read_exact(&mut magic).is_ok() && &magic == PREPARED_MAGIC;
+        if is_prepared {
+            Engine::load(path)
+        } else {
+            Engine::build_with(&[path], import, profile)
+        }
+    }
+
+    // osm change files (.osc, .osc.gz), oldest first, applied to the roads kept by `prepare`.
+    // the graph, its node ids and the spatial index are rebuilt, what was derived from the old
+    // graph is dropped: the search contexts, and the speed profiles (load them again).
+    // the import settings and the profile should be the ones the graph was prepared with.
+    pub fn apply_changes(
+        &mut self,
+        osc_files: &[&str],
+        import: &ImportConfig,
+        profile: &ProfileConfig,
+    ) -> Result<ChangeStats, Box<dyn Error>> {
+        let roads = self.roads.as_mut().ok_or(EngineErrors::NoRoadsToChange)?;
+        let _span = tracing::info_span!("apply_changes", files = osc_files.join(",")).entered();
+        let sources: Vec<XmlSource> = osc_files.iter().map(|f| XmlSource::from_path(f)).collect();
+        let stats = changes::apply(roads, &sources, import, profile)?;
+
+        // the roads stay in the engine, the graph is built from them in place
+        let clip = Clip::from_config(&import.clip)?;
+        let (graph, tree, report) = build_graph_reported(roads, clip.as_ref(), import.distance)?;
+        self.graph = graph;
+        self.spaitial_index = tree;
+        self.import_report = Some(report); // without what the reading of the extracts skipped
+        self.search_contexts = ThreadLocal::new();
+        Ok(stats)
+    }
+
+    // coordinates further than `meters` from any node can't be routed (CantFindNearestNode)
+    pub fn set_max_snap_distance(&mut self, meters: Option<f64>) {
+        self.max_snap_distance = meters;
+    }
+
+    // weekly speed profiles, see traffic::load_speed_profiles for the file format.
+    // returns the number of edges which got a profile.
+    pub fn load_speed_profiles(&mut self, path: &str) -> Result<usize, Box<dyn Error>> {
+        let profiles = load_speed_profiles(path)?;
+        Ok(self.graph.set_speed_profiles(profiles))
+    }
+
+    // what the import skipped and what looks wrong in the data, None for a loaded prepared graph
+    pub fn import_report(&self) -> Option<&ImportReport> {
+        self.import_report.as_ref()
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    // the `k` closest nodes (graph nodes or shape points), closest first
+    pub fn nearest(&self, location: LatLon, k: usize) -> Vec<Snapped> {
+        self.to_snapped(self.spaitial_index.k_nearest(location, k))
+    }
+
+    // all nodes closer than `radius` meters, closest first
+    pub fn within_radius(&self, location: LatLon, radius: f64) -> Vec<Snapped> {
+        self.to_snapped(self.spaitial_index.within_radius(location, radius))
+    }
+
+    fn to_snapped(&self, neighbors: Vec<Neighbor>) -> Vec<Snapped> {
+        neighbors
+            .into_iter()
+            .filter_map(|n| {
+                let at = match self.graph.get_node_index(n.node_id) {
+                    Some(node) => SnappedAt::Node(node),
+                    None => SnappedAt::ShapePoint(self.graph.shape_point_position(n.node_id)?),
+                };
+                Some(Snapped {
+                    at,
+                    osm_id: n.node_id,
+                    location: n.location,
+                    distance: n.distance,
+                })
+            })
+            .collect()
+    }
+
+    // names of the roads going through the snapped node, without duplicates
+    pub fn road_names(&self, snapped: &Snapped) -> Vec<&str> {
+        let edges: Vec<_> = match snapped.at {
+            SnappedAt::Node(node) => self.graph.adjacent_edges(node).into_iter().flatten().collect(),
+            SnappedAt::ShapePoint(position) => self.graph.shape_edge(&position).into_iter().collect(),
+        };
+        let mut names: Vec<&str> = Vec::new();
+        for edge in edges {
+            let name = self.graph.get_name(edge);
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    // coordinate -> closest graph node, not further than the max snap distance
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn snap(&self, location: LatLon) -> Result<Snapped, EngineErrors> {
+        let nearest = self
+            .nearest(location, 1)
+            .pop()
+            .filter(|n| self.max_snap_distance.is_none_or(|max| n.distance <= max));
+        nearest.ok_or_else(|| {
+            monitoring::routing_outcome("snap_failure");
+            EngineErrors::CantFindNearestNode
         })
     }
 
+    // the nodes a route leaves or arrives through, with (meters, seconds) between them and the snapped point
+    fn access(&self, snapped: &Snapped) -> Vec<(NodeIndex, f64, f64)> {
+        match snapped.at {
+            SnappedAt::Node(node) => vec![(node, 0.0, 0.0)],
+            SnappedAt::ShapePoint(p) => match self.graph.shape_edge(&p) {
+                Some(edge) => {
+                    let (meters, seconds) = (edge.distance(), edge.duration());
+                    vec![
+                        (p.from_node, meters * p.fraction, seconds * p.fraction),
+                        (p.to_node, meters * (1.0 - p.fraction), seconds * (1.0 - p.fraction)),
+                    ]
+                }
+                None => Vec::new(),
+            },
+        }
+    }
+
+    // (meters, seconds) without leaving the edge, when both points are on the same one
+    fn along_edge(&self, from: &Snapped, to: &Snapped) -> Option<(f64, f64)> {
+        match (from.at, to.at) {
+            (SnappedAt::ShapePoint(a), SnappedAt::ShapePoint(b)) if a.shape == b.shape => {
+                let edge = self.graph.shape_edge(&a)?;
+                let share = (a.fraction - b.fraction).abs();
+                Some((edge.distance() * share, edge.duration() * share))
+            }
+            _ => None,
+        }
+    }
+
+    // the segments of a route between snapped points through `path` and its segments, None: along their edge
+    fn route_segments(&self, from: &Snapped, to: &Snapped, path: Option<(&[NodeIndex], Vec<RouteSegment>)>) -> Vec<RouteSegment> {
+        let edge_of = |p: &EdgePosition| self.graph.shape_edge(p).map(|e| self.graph.segment(p.from_node, e));
+        let shape_point = |s: &Snapped| match s.at {
+            SnappedAt::ShapePoint(p) => Some(p),
+            SnappedAt::Node(_) => None,
+        };
+        let (path, path_segments) = match (path, from.at, to.at) {
+            (Some(path), _, _) => path,
+            (None, SnappedAt::ShapePoint(a), SnappedAt::ShapePoint(b)) => {
+                let share = (a.fraction - b.fraction).abs();
+                return edge_of(&a).map(|edge| edge.part(a.offset, b.offset, share)).into_iter().collect();
+            }
+            (None, _, _) => return Vec::new(),
+        };
+
+        let mut segments = Vec::new();
+        // from the point to the first node, and from the last one to the point
+        if let Some((p, edge)) = shape_point(from).and_then(|p| Some((p, edge_of(&p)?))) {
+            let last = edge.nodes.len() - 1;
+            segments.push(if path.first() == Some(&p.from_node) {
+                edge.part(p.offset, 0, p.fraction)
+            } else {
+                edge.part(p.offset, last, 1.0 - p.fraction)
+            });
+        }
+        segments.extend(path_segments);
+        if let Some((p, edge)) = shape_point(to).and_then(|p| Some((p, edge_of(&p)?))) {
+            let last = edge.nodes.len() - 1;
+            segments.push(if path.last() == Some(&p.from_node) {
+                edge.part(0, p.offset, p.fraction)
+            } else {
+                edge.part(last, p.offset, 1.0 - p.fraction)
+            });
+        }
+        segments
+    }
+
+    fn route_result(&self, from: &Snapped, to: &Snapped, path: Option<(Vec<NodeIndex>, Vec<RouteSegment>)>) -> RouteResult {
+        let (nodes, path_segments) = path.unzip();
+        let segments = self.route_segments(from, to, nodes.as_deref().zip(path_segments));
+        let mut route_path: Vec<LatLon> = Vec::new();
+        for segment in &segments {
+            let skip = if route_path.is_empty() { 0 } else { 1 }; // the end of the previous one
+            route_path.extend(segment.nodes.iter().skip(skip).map(|n| n.location));
+        }
+        if route_path.is_empty() {
+            route_path.push(from.location); // from a node to itself
+        }
+
+        RouteResult {
+            total_distance: segments.iter().map(|s| s.distance).sum(),
+            total_duration: segments.iter().map(|s| s.duration).sum(),
+            route_path,
+            nodes: nodes.unwrap_or_default(),
+            segments,
+            arrival: None,
+        }
+    }
+
     pub fn routing(
         &self,
         origin: LatLon,
         destination: LatLon,
     ) -> Result<RouteResult, Box<dyn Error>> {
-        // let a: Option<&NodeLocation> = self.spaitial_index.nearest_neighbor(&[origin.lon, origin.lat]);
-        // let b: Result<_, &str> = a.ok_or("error ...");
-        // let c: &NodeLocation = b?;
-
-        let start_node = self
-            .spaitial_index
-            .nearest_neighbor(&[origin.lon, origin.lat])
-            .ok_or(EngineErrors::CantFindNearestNode)?;
-        let target_node = self
-            .spaitial_index
-            .nearest_neighbor(&[destination.lon, destination.lat])
-            .ok_or(EngineErrors::CantFindNearestNode)?;
+        let start = self.snap(origin)?;
+        let target = self.snap(destination)?;
 
         // println!("== start: {}, target: {}",start_node.data.0, target_node.data.0);
 
-        let (dist, path) = shortest_path(&self.graph, start_node.data, target_node.data)
-            .map_err(|_| EngineErrors::CantFindRoute)?;
-
-        // that closure function will be executed for each iteration.
-        // we want some short-circuit effect like before (for loop)
-        // let mut navpath = vec![];
-        // for n in path {
-        //     let loc = self.graph.get_latlon(n).ok_or(EngineErrors::CantFindLatLon)?; // return early
-        //     navpath.push(loc);
-        // }
-
-        // on longer early-return
-        // rust provide magic
-        // Vec<Result<LatLon, EngineErrors>> no early-return -> Result<Vec<LatLon>, EngineErrors> return early
-        let navpath = path
-            .iter()
-            .map(|nodeId| {
-                // new function context
-                // return or ? only exit that closure instead of the outer function.
-                self.graph
-                    .get_latlon(*nodeId)
-                    .ok_or(EngineErrors::CantFindLatLon) // no longer use ? to return (outer function) early
-            })
-            .collect::<Result<Vec<LatLon>, EngineErrors>>()?; // turbofish ::<>
+        self.routing_between(&start, &target)
+    }
+
+    // same as `routing`, for points which are already snapped
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn routing_between(
+        &self,
+        start: &Snapped,
+        target: &Snapped,
+    ) -> Result<RouteResult, Box<dyn Error>> {
+        let meters = |s: &Snapped| -> Vec<Seed> { self.access(s).iter().map(|(n, m, _)| (*n, *m)).collect() };
+        let (sources, targets) = (meters(start), meters(target));
+        let search = |context: &mut SearchContext| {
+            let (dist, path) = context.shortest_path_between(&self.graph, &sources, &targets)?;
+            let segments = context.path_segments(&self.graph, &path);
+            Ok::<_, NoRouteFound>((dist, (path, segments)))
+        };
+        let found = self.with_search_context(search);
+        let path = match (found, self.along_edge(start, target)) {
+            (Ok((dist, _)), Some((direct, _))) if direct <= dist => None,
+            (Ok((_, path)), _) => Some(path),
+            (Err(_), Some(_)) => None,
+            (Err(_), None) => {
+                monitoring::routing_outcome("no_route");
+                return Err(Box::new(EngineErrors::CantFindRoute));
+            }
+        };
+        monitoring::routing_outcome("success");
+
+        Ok(self.route_result(start, target, path))
+    }
+
+    // fastest route when leaving at `depart_at`: uses the speed profiles and the conditional restrictions.
+    // the weekday and time of day are taken in the time zone of `depart_at`.
+    // (not on the parts of edges before the first node and after the last one)
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn routing_at(
+        &self,
+        origin: LatLon,
+        destination: LatLon,
+        depart_at: DateTime<FixedOffset>,
+    ) -> Result<RouteResult, Box<dyn Error>> {
+        let start = self.snap(origin)?;
+        let target = self.snap(destination)?;
 
-        // for nodeId in path {
-        //     let latlon = self.graph.get_latlon(nodeId).ok_or(EngineErrors::CantFindLatLon)?;
-        //     navpath.push(latlon);
-        // }
+        let depart = WeekTime::from_datetime(&depart_at);
+        let seconds = |s: &Snapped| -> Vec<Seed> { self.access(s).iter().map(|(n, _, t)| (*n, *t)).collect() };
+        let (sources, targets) = (seconds(&start), seconds(&target));
+        let search = |context: &mut SearchContext| {
+            let (duration, path) = context.shortest_path_at_between(&self.graph, &sources, &targets, depart)?;
+            let segments = context.path_segments(&self.graph, &path);
+            Ok::<_, NoRouteFound>((duration, (path, segments)))
+        };
+        let found = self.with_search_context(search);
+        let (duration, path) = match (found, self.along_edge(&start, &target)) {
+            (Ok((duration, _)), Some((_, direct))) if direct <= duration => (direct, None),
+            (Ok((duration, path)), _) => (duration, Some(path)),
+            (Err(_), Some((_, direct))) => (direct, None),
+            (Err(_), None) => {
+                monitoring::routing_outcome("no_route");
+                return Err(Box::new(EngineErrors::CantFindRoute));
+            }
+        };
+        monitoring::routing_outcome("success");
 
         Ok(RouteResult {
-            total_distance: dist,
-            route_path: navpath,
-            nodes: path,
+            total_duration: duration,
+            arrival: Some(depart_at + Duration::milliseconds((duration * 1000.0).round() as i64)),
+            ..self.route_result(&start, &target, path)
         })
     }
+
+    // runs a search with the buffers of this thread: every worker thread allocates them once,
+    // at its first search, instead of once per search. They go away with the engine.
+    pub fn with_search_context<T>(&self, search: impl FnOnce(&mut SearchContext) -> T) -> T {
+        let context = self
+            .search_contexts
+            .get_or(|| RefCell::new(SearchContext::new(self.graph.get_total_nodes())));
+        search(&mut context.borrow_mut())
+    }
+
+    // turn-by-turn instructions of a route
+    pub fn steps(&self, route: &RouteResult) -> Vec<Step> {
+        build_steps(&self.graph, &route.segments)
+    }
+
+    // (distance, duration) from a snapped point to each of `to`, None when there's no route
+    // shorter than `max_distance`. one search for all of them, see SearchContext::one_to_many
+    pub fn distances(&self, from: &Snapped, to: &[Snapped], max_distance: f64) -> Vec<Option<(f64, f64)>> {
+        let sources = self.access(from);
+        let ends: Vec<Vec<(NodeIndex, f64, f64)>> = to.iter().map(|t| self.access(t)).collect();
+        let targets: Vec<NodeIndex> = ends.iter().flatten().map(|(n, _, _)| *n).collect();
+        let found = self.with_search_context(|context| {
+            context.one_to_many_from(&self.graph, &sources, &targets, max_distance)
+        });
+
+        let mut first = 0; // of the ends of the target in `found`
+        to.iter()
+            .zip(&ends)
+            .map(|(target, ends)| {
+                let through_ends = ends.iter().zip(&found[first..]).filter_map(|((_, meters, seconds), found)| {
+                    found.map(|(distance, duration)| (distance + meters, duration + seconds))
+                });
+                first += ends.len();
+                through_ends
+                    .chain(self.along_edge(from, target))
+                    .filter(|(distance, _)| *distance <= max_distance)
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+            })
+            .collect()
+    }
+
+    // one search per source
+    #[tracing::instrument(level = "debug", skip_all, fields(sources = sources.len(), destinations = destinations.len()))]
+    pub fn table(
+        &self,
+        sources: &[LatLon],
+        destinations: &[LatLon],
+    ) -> Result<DistanceMatrix, Box<dyn Error>> {
+        let snap_all = |points: &[LatLon]| points.iter().map(|p| self.snap(*p)).collect::<Result<Vec<_>, _>>();
+        Ok(self.table_between(&snap_all(sources)?, &snap_all(destinations)?))
+    }
+
+    // same as `table`, for points which are already snapped
+    pub fn table_between(&self, sources: &[Snapped], destinations: &[Snapped]) -> DistanceMatrix {
+        sources
+            .iter()
+            .map(|s| self.distances(s, destinations, f64::INFINITY))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let points = [(1, 38.0, -75.0), (2, 38.01, -75.0), (3, 38.02, -75.0), (4, 38.03, -75.0)];
+        let (nodes, way) = osm::street("Main Street", &points);
+        let engine = Engine {
+            spaitial_index: SpatialIndex::build(&nodes),
+            graph: Graph::build(nodes, vec![way]),
+            max_snap_distance: None,
+            search_contexts: ThreadLocal::new(),
+            roads: None,
+            import_report: None,
+        };
+
+        let path = std::env::temp_dir().join(format!("simple-nav-test-{}.graph", std::process::id()));
+        let path = path.to_str().unwrap();
+        engine.save(path).unwrap();
+        let loaded = Engine::open(path).unwrap();
+        let mut with_roads = Engine::load_with_roads(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // not built by `prepare`
+        let error = with_roads.apply_changes(&[], &ImportConfig::default(), &ProfileConfig::default()).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(EngineErrors::NoRoadsToChange)));
+
+        // 2 and 3 are shape points of a single edge 1 - 4
+        assert_eq!(loaded.graph().get_total_edges(), 2);
+        assert_eq!(loaded.graph().get_total_shape_points(), 2);
+        assert!(loaded.graph().validate().is_empty());
+        let at = |lat| LatLon { lat, lon: -75.0 };
+        let route = loaded.routing(at(38.0), at(38.03)).unwrap();
+        assert!((route.total_distance - 3336.0).abs() < 1.0);
+        assert_eq!(route.route_path, vec![at(38.0), at(38.01), at(38.02), at(38.03)]);
+        assert_eq!(route.nodes.len(), 2);
+        let middle = loaded.snap(at(38.01)).unwrap();
+        assert!(matches!(middle.at, SnappedAt::ShapePoint(_)));
+        assert_eq!(loaded.road_names(&middle), vec!["Main Street"]);
+
+        // from and to shape points, back along the way, and without leaving the edge
+        let route = loaded.routing(at(38.02), at(38.0)).unwrap();
+        assert!((route.total_distance - 2224.0).abs() < 1.0);
+        assert_eq!(route.route_path, vec![at(38.02), at(38.01), at(38.0)]);
+        let route = loaded.routing(at(38.01), at(38.02)).unwrap();
+        assert!((route.total_distance - 1112.0).abs() < 1.0);
+        assert_eq!(route.route_path, vec![at(38.01), at(38.02)]);
+        assert!(route.nodes.is_empty());
+        assert_eq!(loaded.steps(&route)[0].instruction(), "Head north on Main Street");
+
+        let table = loaded.table(&[at(38.01)], &[at(38.0), at(38.01), at(38.03)]).unwrap();
+        let meters: Vec<f64> = table[0].iter().map(|d| d.unwrap().0.round()).collect();
+        assert_eq!(meters, vec![1112.0, 0.0, 2224.0]);
+    }
 }
@@ -0,0 +1,184 @@
+use serde_json::{json, Value};
+
+use crate::{engine::RouteResult, graph::LatLon};
+
+// output formats for a route geometry, usable without the http server.
+
+// Google encoded polyline: https://developers.google.com/maps/documentation/utilities/polylinealgorithm
+// precision 5 is the google default, 6 is what OSRM calls `polyline6`.
+pub fn encode_polyline(points: &[LatLon], precision: u32) -> String {
+    let factor = 10_f64.powi(precision as i32);
+    let mut encoded = String::new();
+    let (mut prev_lat, mut prev_lon) = (0_i64, 0_i64);
+
+    for p in points {
+        let lat = (p.lat * factor).round() as i64;
+        let lon = (p.lon * factor).round() as i64;
+        encode_value(lat - prev_lat, &mut encoded);
+        encode_value(lon - prev_lon, &mut encoded);
+        prev_lat = lat;
+        prev_lon = lon;
+    }
+    encoded
+}
+
+fn encode_value(value: i64, output: &mut String) {
+    // zig-zag: the sign goes into the lowest bit
+    let mut v = if value < 0 { !(value << 1) } else { value << 1 } as u64;
+    // 5 bits per char, 0x20 means "more chunks follow"
+    while v >= 0x20 {
+        output.push((((v & 0x1f) | 0x20) as u8 + 63) as char);
+        v >>= 5;
+    }
+    output.push((v as u8 + 63) as char);
+}
+
+pub fn decode_polyline(encoded: &str, precision: u32) -> Result<Vec<LatLon>, String> {
+    let factor = 10_f64.powi(precision as i32);
+    let bytes = encoded.as_bytes();
+    let mut points = Vec::new();
+    let (mut lat, mut lon) = (0_i64, 0_i64);
+    let mut i = 0;
+
+    while i < bytes.len() {
+        lat += decode_value(bytes, &mut i)?;
+        lon += decode_value(bytes, &mut i)?;
+        points.push(LatLon {
+            lat: lat as f64 / factor,
+            lon: lon as f64 / factor,
+        });
+    }
+    Ok(points)
+}
+
+fn decode_value(bytes: &[u8], i: &mut usize) -> Result<i64, String> {
+    let mut result = 0_u64;
+    let mut shift = 0;
+    loop {
+        let b = *bytes.get(*i).ok_or("truncated polyline")? as i64 - 63;
+        *i += 1;
+        if !(0..64).contains(&b) || shift > 60 {
+            return Err("invalid polyline".to_string());
+        }
+        result |= ((b & 0x1f) as u64) << shift;
+        shift += 5;
+        if b < 0x20 {
+            break;
+        }
+    }
+    let value = (result >> 1) as i64;
+    Ok(if result & 1 == 1 { !value } else { value })
+}
+
+// GeoJSON positions are [lon, lat]
+pub fn geojson_linestring(points: &[LatLon]) -> Value {
+    json!({
+        "type": "LineString",
+        "coordinates": points.iter().map(|p| [p.lon, p.lat]).collect::<Vec<_>>(),
+    })
+}
+
+impl RouteResult {
+    pub fn to_polyline(&self, precision: u32) -> String {
+        encode_polyline(&self.route_path, precision)
+    }
+
+    pub fn to_geojson_linestring(&self) -> Value {
+        geojson_linestring(&self.route_path)
+    }
+
+    // LineString + the numbers of the route as properties
+    pub fn to_geojson_feature(&self) -> Value {
+        let mut properties = json!({
+            "distance": self.total_distance,
+            "duration": self.total_duration,
+        });
+        if let Some(arrival) = self.arrival {
+            properties["arrival"] = json!(arrival.to_rfc3339());
+        }
+        json!({
+            "type": "Feature",
+            "geometry": self.to_geojson_linestring(),
+            "properties": properties,
+        })
+    }
+
+    // GPX 1.1 with a single track, e.g. for gps devices
+    pub fn to_gpx(&self, name: &str) -> String {
+        let mut gpx = String::new();
+        gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        gpx.push_str("<gpx version=\"1.1\" creator=\"simple-nav\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n");
+        gpx.push_str("  <trk>\n");
+        gpx.push_str(&format!("    <name>{}</name>\n", xml_escape(name)));
+        gpx.push_str(&format!(
+            "    <desc>distance: {:.0} m, duration: {:.0} s</desc>\n",
+            self.total_distance, self.total_duration
+        ));
+        gpx.push_str("    <trkseg>\n");
+        for p in &self.route_path {
+            gpx.push_str(&format!("      <trkpt lat=\"{:.7}\" lon=\"{:.7}\"/>\n", p.lat, p.lon));
+        }
+        gpx.push_str("    </trkseg>\n");
+        gpx.push_str("  </trk>\n");
+        gpx.push_str("</gpx>\n");
+        gpx
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latlon(lat: f64, lon: f64) -> LatLon {
+        LatLon { lat, lon }
+    }
+
+    #[test]
+    fn test_polyline() {
+        // the example of the google documentation
+        let points = vec![
+            latlon(38.5, -120.2),
+            latlon(40.7, -120.95),
+            latlon(43.252, -126.453),
+        ];
+        let encoded = encode_polyline(&points, 5);
+        assert_eq!(encoded, "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+
+        let decoded = decode_polyline(&encoded, 5).unwrap();
+        assert_eq!(decoded, points);
+
+        let decoded6 = decode_polyline(&encode_polyline(&points, 6), 6).unwrap();
+        for (a, b) in decoded6.iter().zip(&points) {
+            assert!((a.lat - b.lat).abs() < 1e-6 && (a.lon - b.lon).abs() < 1e-6);
+        }
+
+        assert!(decode_polyline("_p~iF~ps|", 5).is_err());
+    }
+
+    #[test]
+    fn test_geojson_and_gpx() {
+        let route = RouteResult {
+            total_distance: 1234.0,
+            total_duration: 60.0,
+            route_path: vec![latlon(38.5, -75.1), latlon(38.6, -75.2)],
+            nodes: vec![],
+            segments: vec![],
+            arrival: None,
+        };
+
+        let feature = route.to_geojson_feature();
+        assert_eq!(feature["geometry"]["coordinates"][0], json!([-75.1, 38.5]));
+        assert_eq!(feature["properties"]["distance"], json!(1234.0));
+
+        let gpx = route.to_gpx("a & b");
+        assert!(gpx.contains("<name>a &amp; b</name>"));
+        assert!(gpx.contains("<trkpt lat=\"38.6000000\" lon=\"-75.2000000\"/>"));
+    }
+}
@@ -1,9 +1,15 @@
 use core::f64;
-use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, f64::INFINITY, hash::Hash};
-use rstar::Point;
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
-use crate::{engine::EngineErrors, osm};
+use crate::{
+    conditional::TimeDomain,
+    heap::IndexedHeap,
+    monitoring,
+    osm,
+    traffic::{SpeedProfile, WeekTime},
+};
 
 // depth-first search
 // breadth-first search
@@ -35,21 +41,77 @@ pub struct LatLon {
 }
 
 impl LatLon {
-    fn to_string(&self) -> String {
-        todo!()
-    }
-
     pub fn parse(input: &str) -> Result<LatLon, String> {
         match input.split(',').collect::<Vec<_>>().as_slice() {
             [lat, long] => {
                 // correct case
-                return Ok(LatLon {
+                Ok(LatLon {
                     lat: lat.parse().map_err(|e| format!("{}", e))?,
                     lon: long.parse().map_err(|e| format!("{}", e))?,
-                });
+                })
             }
-            _ => return Err("incorrect format".to_string()), // ()
-        };
+            _ => Err("incorrect format".to_string()), // ()
+        }
+    }
+}
+
+// a LatLon in 1e-7 degrees, how the graph stores its nodes: half the size of two f64, and still
+// ~1 cm precise (it's also what the osm files use).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct FixedLatLon {
+    pub lat: i32,
+    pub lon: i32,
+}
+
+const FIXED_SCALE: f64 = 1e7;
+
+impl FixedLatLon {
+    pub fn from_latlon(p: LatLon) -> FixedLatLon {
+        FixedLatLon {
+            lat: (p.lat * FIXED_SCALE).round() as i32,
+            lon: (p.lon * FIXED_SCALE).round() as i32,
+        }
+    }
+
+    pub fn to_latlon(self) -> LatLon {
+        LatLon {
+            lat: self.lat as f64 / FIXED_SCALE,
+            lon: self.lon as f64 / FIXED_SCALE,
+        }
+    }
+}
+
+// the smallest box around some points
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct BoundingBox {
+    pub min: LatLon, // south west corner
+    pub max: LatLon, // north east corner
+}
+
+impl BoundingBox {
+    pub fn around(points: impl IntoIterator<Item = LatLon>) -> Option<BoundingBox> {
+        points.into_iter().fold(None, |bbox, p| {
+            Some(match bbox {
+                None => BoundingBox { min: p, max: p },
+                Some(b) => b.union(&BoundingBox { min: p, max: p }),
+            })
+        })
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: LatLon { lat: self.min.lat.min(other.min.lat), lon: self.min.lon.min(other.min.lon) },
+            max: LatLon { lat: self.max.lat.max(other.max.lat), lon: self.max.lon.max(other.max.lon) },
+        }
+    }
+
+    pub fn contains(&self, p: LatLon) -> bool {
+        (self.min.lat..=self.max.lat).contains(&p.lat) && (self.min.lon..=self.max.lon).contains(&p.lon)
+    }
+
+    // in square degrees, only to compare boxes
+    pub fn area(&self) -> f64 {
+        (self.max.lat - self.min.lat) * (self.max.lon - self.min.lon)
     }
 }
 
@@ -62,22 +124,92 @@ impl LatLon {
 
 
 // newtype pattern in rus
+#[derive(Serialize, Deserialize)] // prepared graph files, see Engine::save
 pub struct Graph {
     adj_edges: Vec<Vec<Edge>>,
     // nodes: HashMap<NodeID, Node>,
-    nodes2: Vec<Node>,
-    node_id_map: HashMap<osm::NodeID, NodeIndex> // mapping: external osm nodeid -> internal graph node id (which is just index)
+    nodes2: Vec<StoredNode>,
+    node_id_map: HashMap<osm::NodeID, NodeIndex>, // mapping: external osm nodeid -> internal graph node id (which is just index)
+    speed_profiles: Vec<SpeedProfile>, // Edge.speed_profile points into it
+    restrictions: Vec<TimeDomain>,     // Edge.restriction points into it, shared by all edges of a way
+    names: Vec<String>,                // Edge.name points into it, names[0] is "" (unnamed roads)
+    shapes: Vec<Shape>,                // Edge.shape points into it, shapes[0] is empty (consecutive nodes of a way)
+    shape_points: Vec<StoredNode>,     // the osm nodes between the two nodes of the edges, see Shape
+    shape_offsets: Vec<u32>,           // of each shape point, decimeters along the way from Shape.from
+    shape_point_index: HashMap<osm::NodeID, u32>, // osm id -> index in shape_points
+}
+
+// the nodes of a way between two graph nodes: only this way goes through them, so the chain is
+// a single edge (one for each direction, both point to the same Shape) and a search doesn't
+// have to relax them one by one. they are kept for the geometry of the routes, and to snap to.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Shape {
+    from: NodeIndex, // shape_points[start..end] go from `from` to `to`
+    to: NodeIndex,
+    start: u32,
+    end: u32,
 }
 
 impl Graph {
     // constructor function
-    pub fn build(used_nodes:HashMap<osm::NodeID, Node>, used_ways:Vec<osm::Way>) -> Self {
-        let nodes2 = used_nodes.into_values().collect::<Vec<Node>>(); // turbo-fish
+    pub fn build(used_nodes:HashMap<osm::NodeID, Node>, used_ways:Vec<osm::MeasuredWay>) -> Self {
+        // the graph nodes are the ends of the ways and the nodes used more than once (intersections),
+        // the nodes in between are shape points of the edges
+        let mut uses: HashMap<osm::NodeID, u32> = HashMap::new();
+        for way in &used_ways {
+            for (i, id) in way.nodes().iter().enumerate() {
+                let is_end = i == 0 || i + 1 == way.nodes().len();
+                *uses.entry(*id).or_insert(0) += if is_end { 2 } else { 1 };
+            }
+        }
+
+        // the chains of each way, between 2 graph nodes: (start, end) positions in the way
+        let chains: Vec<Vec<(usize, usize)>> = used_ways
+            .iter()
+            .map(|way| {
+                let ends: Vec<usize> = (0..way.nodes().len()).filter(|i| uses[&way.nodes()[*i]] > 1).collect();
+                ends.windows(2).map(|pair| (pair[0], pair[1])).collect()
+            })
+            .collect();
+
+        // a chain stays one edge per segment when it would be a loop, or when other chains (or
+        // single segments) link the same 2 nodes, whichever way comes first: a route is a list of
+        // nodes, it couldn't tell which of 2 edges between them it took.
+        let key = |a: osm::NodeID, b: osm::NodeID| if a.0 <= b.0 { (a, b) } else { (b, a) };
+        let mut links: HashMap<(osm::NodeID, osm::NodeID), u32> = HashMap::new();
+        for (way, way_chains) in used_ways.iter().zip(&chains) {
+            for (start, end) in way_chains {
+                *links.entry(key(way.nodes()[*start], way.nodes()[*end])).or_insert(0) += 1;
+            }
+        }
+        let chains: Vec<Vec<(usize, usize, bool)>> = used_ways
+            .iter()
+            .zip(chains)
+            .map(|(way, way_chains)| {
+                way_chains
+                    .into_iter()
+                    .map(|(start, end)| {
+                        let (a, b) = (way.nodes()[start], way.nodes()[end]);
+                        let compressed = a != b && links[&key(a, b)] == 1 && end > start + 1;
+                        if !compressed {
+                            for id in &way.nodes()[start + 1..end] {
+                                uses.insert(*id, 2); // a graph node after all
+                            }
+                        }
+                        (start, end, compressed)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let nodes2 = used_nodes
+            .values()
+            .filter(|n| uses.get(&n.id).is_some_and(|u| *u > 1))
+            .map(|n| StoredNode { id: n.id, location: FixedLatLon::from_latlon(n.location) })
+            .collect::<Vec<StoredNode>>(); // turbo-fish
         let mut node_id_map = HashMap::new();
-        let mut next_index = 0;
-        for n in &nodes2 {
-            node_id_map.insert(n.id, NodeIndex(next_index));
-            next_index+=1;
+        for (next_index, n) in nodes2.iter().enumerate() {
+            node_id_map.insert(n.id, NodeIndex::new(next_index));
         }
 
         // adj_edges (2-D data structure):
@@ -86,34 +218,147 @@ impl Graph {
         //  node id 3 -> [edge6, eddge7, ]
         //  node id 4 -> [edge8, eddge9, ]
 
-        let mut adj_edges: Vec<Vec<Edge>> = Vec::new();
-        
-        for curr_way in used_ways {
-            // iterate curr.nodes pairwise (each pair is an edge)
-            for curr_node_index in 0..curr_way.nodes.len()-1 {
-                let next_node_index = curr_node_index+1;
-                let curr_node_id = curr_way.nodes[curr_node_index];
-                let next_node_id = curr_way.nodes[next_node_index];
-
-                let forward_edge = Edge {
-                    from_node: node_id_map[&curr_node_id], // osm node id -> node index
-                    to_node: node_id_map[&next_node_id],
-                    distance: curr_way.distances[curr_node_index]
-                };
+        // one (maybe empty) list per node, so that we can index it with the node index
+        let mut adj_edges: Vec<Vec<Edge>> = (0..nodes2.len()).map(|_| Vec::new()).collect();
+        let mut restrictions = Vec::new();
+        // many ways share the same name (a long road is split into many ways)
+        let mut names = vec![String::new()];
+        let mut name_index: HashMap<String, u32> = HashMap::new();
+        name_index.insert(String::new(), 0);
+        let mut shapes = vec![Shape { from: NodeIndex(0), to: NodeIndex(0), start: 0, end: 0 }];
+        let mut shape_points = Vec::new();
+        let mut shape_offsets = Vec::new();
+        let mut shape_point_index = HashMap::new();
 
-                let reverse_edge = Edge {
-                    from_node: node_id_map[&next_node_id],
-                    to_node: node_id_map[&curr_node_id],
-                    distance: curr_way.distances[curr_node_index],
+        for (mut curr_way, way_chains) in used_ways.into_iter().zip(chains) {
+            let name = *name_index.entry(std::mem::take(&mut curr_way.name)).or_insert_with_key(|name| {
+                names.push(name.clone());
+                names.len() as u32 - 1
+            });
+            let restriction = curr_way.restriction.take().map_or(Edge::NONE, |domain| {
+                restrictions.push(domain);
+                restrictions.len() as u32 - 1
+            });
+            // km/h -> m/s
+            let speed = curr_way.speed / 3.6;
+            let mut add_edges = |from: osm::NodeID, to: osm::NodeID, distance: f64, shape: u32| {
+                let (from, to) = (node_id_map[&from], node_id_map[&to]); // osm node id -> node index
+                let decimeters = to_tenths(distance);
+                let deciseconds = to_tenths(distance / speed);
+                let edge = |to_node| Edge {
+                    to_node,
+                    decimeters,
+                    deciseconds,
+                    speed_profile: Edge::NONE,
+                    restriction,
+                    name,
+                    shape,
                 };
+                adj_edges[from.index()].push(edge(to));
+                adj_edges[to.index()].push(edge(from)); // reverse edge
+            };
 
-                adj_edges[node_id_map[&curr_node_id].0].push(forward_edge);
-                adj_edges[node_id_map[&next_node_id].0].push(reverse_edge);
+            for (start, end, compressed) in way_chains {
+                if compressed {
+                    let first = shape_points.len() as u32;
+                    let mut offset = 0.0;
+                    for i in start + 1..end {
+                        let id = curr_way.nodes()[i];
+                        offset += curr_way.distances()[i - 1];
+                        shape_point_index.insert(id, shape_points.len() as u32);
+                        shape_points.push(StoredNode { id, location: FixedLatLon::from_latlon(used_nodes[&id].location) });
+                        shape_offsets.push(to_tenths(offset));
+                    }
+                    shapes.push(Shape {
+                        from: node_id_map[&curr_way.nodes()[start]],
+                        to: node_id_map[&curr_way.nodes()[end]],
+                        start: first,
+                        end: shape_points.len() as u32,
+                    });
+                    let distance = curr_way.distances()[start..end].iter().sum();
+                    add_edges(curr_way.nodes()[start], curr_way.nodes()[end], distance, shapes.len() as u32 - 1);
+                } else {
+                    // iterate curr.nodes pairwise (each pair is an edge)
+                    for curr_node_index in start..end {
+                        let next_node_index = curr_node_index + 1;
+                        let curr_node_id = curr_way.nodes()[curr_node_index];
+                        let next_node_id = curr_way.nodes()[next_node_index];
+                        add_edges(curr_node_id, next_node_id, curr_way.distances()[curr_node_index], 0);
+                    }
+                }
             }
         }
 
+        Graph {
+            adj_edges,
+            nodes2,
+            node_id_map,
+            speed_profiles: Vec::new(),
+            restrictions,
+            names,
+            shapes,
+            shape_points,
+            shape_offsets,
+            shape_point_index,
+        }
+    }
+
+    // attach time-dependent speeds to the edges (from osm node, to osm node).
+    // returns how many edges got a profile, segments not in the graph are ignored.
+    // a segment between shape points gives its profile to the whole edge it's part of.
+    pub fn set_speed_profiles(&mut self, profiles: HashMap<(osm::NodeID, osm::NodeID), SpeedProfile>) -> usize {
+        let mut matched = 0;
+        for ((from, to), profile) in profiles {
+            let edges = self.segment_edges(from, to);
+            if edges.is_empty() {
+                continue;
+            }
+
+            self.speed_profiles.push(profile);
+            let profile_index = self.speed_profiles.len() as u32 - 1;
+            for (node, i) in edges {
+                self.adj_edges[node.index()][i].speed_profile = profile_index;
+                matched += 1;
+            }
+        }
+        matched
+    }
+
+    // the edges going through the segment between 2 consecutive osm nodes of a way, in this direction:
+    // (from node, position in its adjacency list)
+    fn segment_edges(&self, from: osm::NodeID, to: osm::NodeID) -> Vec<(NodeIndex, usize)> {
+        let edges_of = |node: NodeIndex, keep: &dyn Fn(&Edge) -> bool| -> Vec<(NodeIndex, usize)> {
+            self.adj_edges[node.index()]
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| keep(e))
+                .map(|(i, _)| (node, i))
+                .collect()
+        };
+        if let (Some(u), Some(v)) = (self.get_node_index(from), self.get_node_index(to)) {
+            return edges_of(u, &|e: &Edge| e.to_node == v && e.shape == 0);
+        }
 
-        Graph { adj_edges, nodes2, node_id_map }                 
+        // the shape one of them is a point of, and where they are along it
+        let point = match self.shape_point_index.get(&from).or_else(|| self.shape_point_index.get(&to)) {
+            Some(p) => *p,
+            None => return Vec::new(),
+        };
+        let shape_index = self.shapes.partition_point(|s| s.end <= point);
+        let shape = self.shapes[shape_index];
+        let position = |id: osm::NodeID| match self.shape_point_index.get(&id) {
+            Some(p) if (shape.start..shape.end).contains(p) => Some((p - shape.start) as i64 + 1),
+            Some(_) => None,
+            None if self.get_node_index(id) == Some(shape.from) => Some(0),
+            None if self.get_node_index(id) == Some(shape.to) => Some((shape.end - shape.start) as i64 + 1),
+            None => None,
+        };
+        let same_shape = |e: &Edge| e.shape as usize == shape_index;
+        match (position(from), position(to)) {
+            (Some(a), Some(b)) if b == a + 1 => edges_of(shape.from, &same_shape),
+            (Some(a), Some(b)) if a == b + 1 => edges_of(shape.to, &same_shape),
+            _ => Vec::new(),
+        }
     }
 }
 
@@ -121,17 +366,17 @@ impl Graph {
     // prev: iterator over osm node 1, osm node 2, ...
     // now: iterator over index valus like 0, 1, ..., nodes length -1
     pub fn for_each_node(&self) -> impl Iterator<Item = NodeIndex>{
-        (0..self.nodes2.len()).map(|i| NodeIndex(i))
+        (0..self.nodes2.len()).map(NodeIndex::new)
     }
 
-    pub fn adjacent_edges(&self, nodeId : NodeIndex) -> Option<&Vec<Edge>> /* Option<&[Edge]> */ { // zero length
-        self.adj_edges.get(nodeId.0) // safe version: index out of range it returns None
+    pub fn adjacent_edges(&self, node_id : NodeIndex) -> Option<&Vec<Edge>> /* Option<&[Edge]> */ { // zero length
+        self.adj_edges.get(node_id.index()) // safe version: index out of range it returns None
         // self.adj_edges[nodeId.0] // non-safe: index out of range will panic
     }
 
     // distinguish external osm id vs internal index-based id.
-    pub fn get_latlon(&self, nodeId : NodeIndex) -> Option<LatLon> {
-        Some(self.nodes2[nodeId.0].location) // or better change the return type to just LatLong intead of Option<LatLon>.
+    pub fn get_latlon(&self, node_id : NodeIndex) -> Option<LatLon> {
+        self.nodes2.get(node_id.index()).map(|n| n.location.to_latlon())
 
         // match self.nodes.get(&nodeId) {
         //     Some (x ) => {
@@ -141,149 +386,680 @@ impl Graph {
         // }
     }
 
+    // external osm id -> internal index
+    pub fn get_node_index(&self, node_id: osm::NodeID) -> Option<NodeIndex> {
+        self.node_id_map.get(&node_id).copied()
+    }
+
+    // internal index -> external osm id
+    pub fn get_osm_id(&self, node_id: NodeIndex) -> Option<osm::NodeID> {
+        self.nodes2.get(node_id.index()).map(|n| n.id)
+    }
+
+    // name of the road the edge belongs to, "" when unnamed
+    pub fn get_name(&self, edge: &Edge) -> &str {
+        self.name(edge.name)
+    }
+
+    // a name by its index, see Edge.name
+    pub fn name(&self, index: u32) -> &str {
+        &self.names[index as usize]
+    }
+
     pub fn get_total_nodes(&self) -> usize {
-        return self.nodes2.len()
+        self.nodes2.len()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        self.nodes2.iter().map(|n| n.to_node())
+    }
+
+    // the osm nodes between the graph nodes, see Shape
+    pub fn shape_points(&self) -> impl Iterator<Item = Node> + '_ {
+        self.shape_points.iter().map(|n| n.to_node())
+    }
+
+    pub fn get_total_shape_points(&self) -> usize {
+        self.shape_points.len()
+    }
+
+    // None for an empty graph
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        BoundingBox::around(self.nodes().chain(self.shape_points()).map(|n| n.location))
+    }
+
+    // the osm nodes along an edge of `from` (in its adjacency list), from `from` to its to_node
+    pub fn edge_geometry(&self, from: NodeIndex, edge: &Edge) -> Vec<Node> {
+        let mut nodes = vec![self.nodes2[from.index()].to_node()];
+        if edge.shape != 0 {
+            let shape = &self.shapes[edge.shape as usize];
+            let points = self.shape_points[shape.start as usize..shape.end as usize].iter().map(|n| n.to_node());
+            if shape.from == from {
+                nodes.extend(points);
+            } else {
+                nodes.extend(points.rev());
+            }
+        }
+        nodes.push(self.nodes2[edge.to_node.index()].to_node());
+        nodes
+    }
+
+    // where a shape point is along its edge, None for the other nodes
+    pub fn shape_point_position(&self, node_id: osm::NodeID) -> Option<EdgePosition> {
+        let point = *self.shape_point_index.get(&node_id)?;
+        let shape_index = self.shapes.partition_point(|s| s.end <= point);
+        let shape = self.shapes.get(shape_index)?;
+        let position = EdgePosition {
+            from_node: shape.from,
+            to_node: shape.to,
+            shape: shape_index as u32,
+            offset: (point - shape.start) as usize + 1,
+            fraction: 0.0,
+        };
+
+        let edge = self.shape_edge(&position)?;
+        let fraction = match edge.decimeters {
+            0 => position.offset as f64 / (shape.end - shape.start + 1) as f64, // all at the same place
+            total => (self.shape_offsets[point as usize] as f64 / total as f64).min(1.0),
+        };
+        Some(EdgePosition { fraction, ..position })
+    }
+
+    // the edge from_node -> to_node a position is on
+    pub fn shape_edge(&self, position: &EdgePosition) -> Option<&Edge> {
+        self.adjacent_edges(position.from_node)?
+            .iter()
+            .find(|e| e.shape == position.shape && e.to_node == position.to_node)
+    }
+
+    pub fn segment(&self, from: NodeIndex, edge: &Edge) -> RouteSegment {
+        RouteSegment {
+            name: edge.name,
+            distance: edge.distance(),
+            duration: edge.duration(),
+            nodes: self.edge_geometry(from, edge),
+        }
+    }
+
+    // bytes used by the graph, roughly: the allocations, not the allocator's overhead
+    pub fn memory_usage(&self) -> usize {
+        use std::mem::size_of;
+        let edges: usize = self.adj_edges.iter().map(|e| e.capacity() * size_of::<Edge>()).sum();
+        let lists = self.adj_edges.capacity() * size_of::<Vec<Edge>>();
+        let nodes = (self.nodes2.capacity() + self.shape_points.capacity()) * size_of::<StoredNode>()
+            + self.shape_offsets.capacity() * size_of::<u32>();
+        // a hashbrown table: the entries plus one control byte each
+        let id_map = self.node_id_map.capacity() * (size_of::<(osm::NodeID, NodeIndex)>() + 1)
+            + self.shape_point_index.capacity() * (size_of::<(osm::NodeID, u32)>() + 1);
+        let shapes = self.shapes.capacity() * size_of::<Shape>();
+        edges + lists + nodes + id_map + shapes + self.tables_memory_usage()
+    }
+
+    // an estimate of what memory_usage was before the compact layout (prepared file version 1),
+    // to compare in `stats`: this graph counted with the old sizes, not a measure of the old build.
+    // every osm node was a 24-byte node (f64 coordinates) with its own list of 72-byte
+    // edges (usize indices, f64 weights, Option<usize>), a shape point had the 2 edges it splits
+    pub fn uncompacted_memory_usage(&self) -> usize {
+        use std::mem::size_of;
+        let nodes = self.nodes2.len() + self.shape_points.len();
+        // a list grows to 4 edges at its first one
+        let edges = self.adj_edges.iter().map(|e| e.capacity()).sum::<usize>() + self.shape_points.len() * 4;
+        // 7/8 of the buckets of a hashbrown table are usable, its entries were (i64, usize)
+        let id_map = (nodes * 8 / 7).next_power_of_two() / 8 * 7 * (16 + 1);
+        nodes * (24 + size_of::<Vec<Edge>>()) + edges * 72 + id_map + self.tables_memory_usage()
+    }
+
+    // the names, speed profiles and restrictions the edges point into
+    fn tables_memory_usage(&self) -> usize {
+        use std::mem::size_of;
+        let names: usize = self.names.iter().map(|n| n.capacity() + size_of::<String>()).sum();
+        let profiles = self.speed_profiles.capacity() * size_of::<SpeedProfile>();
+        let restrictions = self.restrictions.capacity() * size_of::<TimeDomain>();
+        names + profiles + restrictions
+    }
+
+    // directed edges (every chain of a way between 2 graph nodes gives two)
+    pub fn get_total_edges(&self) -> usize {
+        self.adj_edges.iter().map(|edges| edges.len()).sum()
+    }
+
+    // node counts of the connected components, biggest first.
+    // ignoring the direction of the edges (weakly connected).
+    pub fn components(&self) -> Vec<usize> {
+        // union-find with path halving
+        let mut parent: Vec<usize> = (0..self.nodes2.len()).collect();
+        fn find(parent: &mut [usize], mut x: usize) -> usize {
+            while parent[x] != x {
+                parent[x] = parent[parent[x]];
+                x = parent[x];
+            }
+            x
+        }
+
+        for (from, edges) in self.adj_edges.iter().enumerate() {
+            for edge in edges {
+                let a = find(&mut parent, from);
+                let b = find(&mut parent, edge.to_node.index());
+                if a != b {
+                    parent[a] = b;
+                }
+            }
+        }
+
+        let mut sizes: HashMap<usize, usize> = HashMap::new();
+        for n in 0..self.nodes2.len() {
+            *sizes.entry(find(&mut parent, n)).or_insert(0) += 1;
+        }
+        let mut sizes: Vec<usize> = sizes.into_values().collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        sizes
+    }
+
+    // sanity checks of a built (or loaded) graph, returns the problems found
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.nodes2.is_empty() {
+            problems.push("the graph has no nodes".to_string());
+        }
+        if self.adj_edges.len() != self.nodes2.len() {
+            problems.push(format!("{} adjacency lists for {} nodes", self.adj_edges.len(), self.nodes2.len()));
+        }
+        for (i, n) in self.nodes2.iter().enumerate() {
+            if self.node_id_map.get(&n.id) != Some(&NodeIndex::new(i)) {
+                problems.push(format!("node {:?} is not indexed", n.id));
+            }
+            let location = n.location.to_latlon();
+            if !(-90.0..=90.0).contains(&location.lat) || !(-180.0..=180.0).contains(&location.lon) {
+                problems.push(format!("node {:?} has an invalid location {:?}", n.id, location));
+            }
+        }
+        // the weights are integers: always finite and positive
+        let edges = self.adj_edges.iter().enumerate();
+        for (from, edge) in edges.flat_map(|(i, edges)| edges.iter().map(move |e| (NodeIndex::new(i), e))) {
+            if edge.to_node.index() >= self.nodes2.len() {
+                problems.push(format!("edge {} -> {} points outside of the graph", from.0, edge.to_node.0));
+                continue;
+            }
+            if edge.speed_profile().is_some_and(|p| p as usize >= self.speed_profiles.len())
+                || edge.restriction().is_some_and(|r| r as usize >= self.restrictions.len())
+                || edge.name as usize >= self.names.len()
+                || edge.shape as usize >= self.shapes.len()
+            {
+                problems.push(format!("edge {} -> {} has a dangling reference", from.0, edge.to_node.0));
+                continue;
+            }
+            let shape = &self.shapes[edge.shape as usize];
+            let ends = [from, edge.to_node];
+            if edge.shape != 0 && (!ends.contains(&shape.from) || !ends.contains(&shape.to)) {
+                problems.push(format!("edge {} -> {} has the shape of other nodes", from.0, edge.to_node.0));
+            }
+        }
+        for shape in &self.shapes {
+            if shape.start > shape.end || shape.end as usize > self.shape_points.len() {
+                problems.push(format!("a shape goes past the {} shape points", self.shape_points.len()));
+            }
+        }
+        if self.shape_offsets.len() != self.shape_points.len() {
+            problems.push(format!("{} offsets for {} shape points", self.shape_offsets.len(), self.shape_points.len()));
+        }
+        for (i, n) in self.shape_points.iter().enumerate() {
+            if self.shape_point_index.get(&n.id) != Some(&(i as u32)) {
+                problems.push(format!("shape point {:?} is not indexed", n.id));
+            }
+        }
+        problems
+    }
+
+    // the shortest edge u -> v (there may be several when two ways share both nodes)
+    pub fn find_edge(&self, u: NodeIndex, v: NodeIndex) -> Option<&Edge> {
+        self.adjacent_edges(u)?
+            .iter()
+            .filter(|e| e.to_node == v)
+            .min_by_key(|e| e.decimeters)
+    }
+
+    // seconds to drive through `edge` when entering it at `enter`, None if it's closed at that time
+    pub fn edge_travel_time(&self, edge: &Edge, enter: WeekTime) -> Option<f64> {
+        if let Some(r) = edge.restriction() {
+            if self.restrictions[r as usize].contains(enter) {
+                return None;
+            }
+        }
+        match edge.speed_profile() {
+            Some(p) => Some(self.speed_profiles[p as usize].travel_time(edge.distance(), enter)),
+            None => Some(edge.duration()),
+        }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+// what the graph is built from, see StoredNode for what it keeps
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Node {
     pub id : osm::NodeID,
     pub location : LatLon
 }
 
-#[derive(Hash, Eq, PartialEq, PartialOrd, Debug, Clone, Copy)]
-pub struct NodeIndex(pub usize); 
+#[derive(Copy, Clone, Serialize, Deserialize)]
+struct StoredNode {
+    id: osm::NodeID, // osm ids don't fit in 32 bits anymore
+    location: FixedLatLon,
+}
 
-#[derive(Clone)]
-struct EdgeID(i64);
+impl StoredNode {
+    fn to_node(self) -> Node {
+        Node { id: self.id, location: self.location.to_latlon() }
+    }
+}
 
-// only travel from <from_node> to <to_node>
-pub struct Edge {
-    // id: EdgeID,
+// a shape point, on the edge from_node -> to_node (and the reverse one)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct EdgePosition {
     pub from_node: NodeIndex,
     pub to_node: NodeIndex,
-    pub distance: f64,
+    pub shape: u32,
+    pub offset: usize,  // in the edge_geometry of from_node -> to_node
+    pub fraction: f64,  // of the edge between from_node and the point
 }
 
-#[derive(Debug, PartialEq)]
-struct PQItem {
-    id: NodeIndex,
-    distance: f64,
+// a route along an edge, or along the part of it before or after the shape point it starts or ends at
+#[derive(Clone, Debug)]
+pub struct RouteSegment {
+    pub name: u32, // see Graph::name
+    pub distance: f64,
+    pub duration: f64,
+    pub nodes: Vec<Node>, // from its start to its end
 }
 
-impl Ord for PQItem {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // will return none if comparing f64::Nan with some other f64
-        // self.distance.partial_cmp(&other.distance).unwrap() if self > other return Some(Greater)
-        other.distance.partial_cmp(&self.distance).unwrap() // unwrap() panic on the None
+impl RouteSegment {
+    // the part between 2 of its nodes (`from` after `to` goes backwards), `share` of its length
+    pub fn part(&self, from: usize, to: usize, share: f64) -> RouteSegment {
+        let mut nodes = self.nodes[from.min(to)..=from.max(to)].to_vec();
+        if from > to {
+            nodes.reverse();
+        }
+        RouteSegment {
+            name: self.name,
+            distance: self.distance * share,
+            duration: self.duration * share,
+            nodes,
+        }
     }
 }
 
-impl PartialOrd for PQItem {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        other.distance.partial_cmp(&self.distance)
+// u32: 4 billion nodes is more than the planet, and it halves the edges
+#[derive(Hash, Eq, PartialEq, PartialOrd, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NodeIndex(pub u32);
+
+impl NodeIndex {
+    pub fn new(index: usize) -> NodeIndex {
+        NodeIndex(index as u32)
     }
+
+    // for indexing the vectors of the graph and the searches
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// meters / seconds -> the u32 tenths stored in the edges, rounded (saturates, NaN gives 0)
+fn to_tenths(value: f64) -> u32 {
+    (value * 10.0).round() as u32
+}
+
+// only travel from the node whose adjacency list has it to <to_node>
+#[derive(Serialize, Deserialize)]
+pub struct Edge {
+    // id: EdgeID,
+    pub to_node: NodeIndex,
+    decimeters: u32,    // see distance()
+    deciseconds: u32,   // see duration()
+    speed_profile: u32, // time-dependent speeds replacing `duration`, see Graph::edge_travel_time
+    restriction: u32,   // closed during this time domain
+    pub name: u32,      // see Graph::get_name
+    pub shape: u32,     // the shape points between the nodes, see Graph::edge_geometry
 }
 
-impl Eq for PQItem {}
+impl Edge {
+    const NONE: u32 = u32::MAX; // no speed profile or restriction, cheaper than an Option<u32>
 
+    pub fn speed_profile(&self) -> Option<u32> {
+        (self.speed_profile != Edge::NONE).then_some(self.speed_profile)
+    }
+
+    pub fn restriction(&self) -> Option<u32> {
+        (self.restriction != Edge::NONE).then_some(self.restriction)
+    }
+
+    // meters
+    pub fn distance(&self) -> f64 {
+        self.decimeters as f64 / 10.0
+    }
+
+    // seconds, at the speed of the way
+    pub fn duration(&self) -> f64 {
+        self.deciseconds as f64 / 10.0
+    }
+}
 
 #[derive(Debug)]
 pub struct NoRouteFound; // equivalent to ()
 
-pub fn shortest_path(g:&Graph, s: NodeIndex, t: NodeIndex) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
-    // let mut dist:HashMap<NodeID, f64> = HashMap::new();
-    // let mut prev:HashMap<NodeID, NodeID> = HashMap::new();
-    let mut dist = vec![f64::INFINITY; g.get_total_nodes()]; // indexed by internal `NodeID`
-    let mut prev = vec![Option::<NodeIndex>::None; g.get_total_nodes()];
-
-    let mut pq:BinaryHeap<PQItem> = BinaryHeap::new();
-    dist.insert(s.0, 0.0);
-    pq.push(PQItem { id: s, distance: 0.0 });
-    while let Some(item) = pq.pop() { //
-        let u = item.id;
-        if u == t {
-            let mut path = Vec::new();
-            let mut current = t;
-
-            // prev: t -> v -> u -> v 
-            while current != s { // loop until None (the last node (start node) has no prev node )
-                path.push(current);
-                match prev[current.0] {
-                    Some(prev) => current = prev,
-                    None => panic!("bug happens: node {} does not have a previous node", current.0),
-                }
+// where a search starts or ends with what it costs to get there, meters or seconds: the nodes of a
+// snapped point, e.g. both ends of the edge of a shape point (see Engine::routing_between)
+pub type Seed = (NodeIndex, f64);
+
+// walk back the `prev` links from `t` to the source it was reached from
+fn build_path(prev: &[Option<NodeIndex>], t: NodeIndex) -> Vec<NodeIndex> {
+    let mut path = vec![t];
+    let mut current = t;
+
+    // prev: t -> v -> u -> v
+    while let Some(p) = prev[current.index()] { // loop until None (a source has no prev node)
+        path.push(p);
+        current = p;
+    }
+
+    path.reverse();
+    path
+}
+
+// the buffers of a search, reused by the next one. Allocating and filling vectors of the size of
+// the graph costs more than the search itself on a short trip in a big graph.
+// resetting doesn't touch the nodes either: an entry only counts if it was written by the current
+// search (its generation), the older ones read as "not reached yet".
+// Engine keeps one per thread, see Engine::with_search_context.
+pub struct SearchContext {
+    generation: u32,
+    touched: Vec<u32>, // node -> generation of the search which last reached it
+    settled: Vec<u32>, // node -> generation of the search which settled it (one_to_many)
+    dist: Vec<f64>,    // distance, or elapsed seconds for shortest_path_at
+    duration: Vec<f64>,
+    prev: Vec<Option<NodeIndex>>,
+    prev_edge: Vec<u32>, // which of prev's edges, there can be several to the same node
+    timed: bool,         // the last search was shortest_path_at_between: dist is in seconds
+    heap: IndexedHeap,
+}
+
+impl SearchContext {
+    pub fn new(nodes: usize) -> SearchContext {
+        SearchContext {
+            generation: 0,
+            touched: vec![0; nodes],
+            settled: vec![0; nodes],
+            dist: vec![f64::INFINITY; nodes],
+            duration: vec![0.0; nodes],
+            prev: vec![None; nodes],
+            prev_edge: vec![0; nodes],
+            timed: false,
+            heap: IndexedHeap::new(nodes),
+        }
+    }
+
+    // forget the previous search
+    fn reset(&mut self, nodes: usize) {
+        if self.touched.len() != nodes {
+            *self = SearchContext::new(nodes); // another graph
+        }
+        self.heap.clear();
+        self.timed = false;
+        self.generation = match self.generation.checked_add(1) {
+            Some(g) => g,
+            None => {
+                // every 4 billion searches
+                self.touched.fill(0);
+                self.settled.fill(0);
+                1
             }
+        };
+    }
 
-            path.push(s);
-            path.reverse();
+    fn dist(&self, v: NodeIndex) -> f64 {
+        if self.touched[v.index()] == self.generation {
+            self.dist[v.index()]
+        } else {
+            f64::INFINITY
+        }
+    }
 
-            // println!("=== path build finish");
+    // `prev`: the node `v` was reached from and the index of the edge in its adjacent edges
+    fn reach(&mut self, v: NodeIndex, dist: f64, prev: Option<(NodeIndex, usize)>) {
+        self.touched[v.index()] = self.generation;
+        self.dist[v.index()] = dist;
+        self.prev[v.index()] = prev.map(|(u, _)| u);
+        self.prev_edge[v.index()] = prev.map_or(0, |(_, i)| i as u32);
+    }
 
-            // dist[t]
-            return Ok(( item.distance, path )) 
+    // the segments of a path found by the last search, through the edges it took: two ways can
+    // join the same nodes, and the shortest isn't always the one the search used (closed, slower).
+    // after shortest_path_at_between the durations are the time-dependent ones.
+    pub fn path_segments(&self, g: &Graph, path: &[NodeIndex]) -> Vec<RouteSegment> {
+        path.windows(2)
+            .filter_map(|pair| {
+                let (u, v) = (pair[0], pair[1]);
+                let edge = g.adjacent_edges(u)?.get(self.prev_edge[v.index()] as usize)?;
+                let mut segment = g.segment(u, edge);
+                if self.timed {
+                    segment.duration = self.dist[v.index()] - self.dist[u.index()];
+                }
+                Some(segment)
+            })
+            .collect()
+    }
+
+    fn is_settled(&self, v: NodeIndex) -> bool {
+        self.settled[v.index()] == self.generation
+    }
+
+    pub fn shortest_path(&mut self, g:&Graph, s: NodeIndex, t: NodeIndex) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
+        self.shortest_path_between(g, &[(s, 0.0)], &[(t, 0.0)])
+    }
+
+    // from any source to any target, the seeds add their cost to the path
+    pub fn shortest_path_between(&mut self, g:&Graph, sources: &[Seed], targets: &[Seed]) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
+        // let mut dist:HashMap<NodeID, f64> = HashMap::new();
+        // let mut prev:HashMap<NodeID, NodeID> = HashMap::new();
+        self.reset(g.get_total_nodes()); // dist / prev are indexed by internal `NodeID`
+
+        let mut settled = 0; // how much work the search did
+        for &(s, cost) in sources {
+            if cost < self.dist(s) {
+                self.reach(s, cost, None);
+                self.heap.push(s, cost);
+            }
         }
+        let mut best: Option<(f64, NodeIndex)> = None; // to a target, with its cost
+        // every node is popped once: reaching it again lowers its key in the heap
+        while let Some((u, dist_to_u)) = self.heap.pop() { // dist_to_u: distance from the sources to `u`
+            if best.is_some_and(|(b, _)| dist_to_u >= b) {
+                break; // nothing left can do better
+            }
+            settled += 1;
+            for &(t, cost) in targets {
+                if t == u && best.is_none_or(|(b, _)| dist_to_u + cost < b) {
+                    best = Some((dist_to_u + cost, u));
+                }
+            }
 
-        let dist_to_u = item.distance; // distance from `s` to `u`
-        
-        // pop out node is: u
-        if let Some(edges) = g.adjacent_edges(u) {
-            for edge in edges {
-                let v = edge.to_node;
-                
-                // u's adjacent edges.
-                // for each adj edge, edge.to_node should be: v
-                // at least there is a path to v through u: s -> ... -> u -> v.
-                // for that path, the total cost/distance from start node `s` to `v` is: distance to u + edge (u -> v) distance 
-                let dist_to_v_through_u = dist_to_u + edge.distance;
-
-                // dist[&v]: distance to `v` throught some other ways/path already exist
-
-                // Two cases:
-                // 1. dist_to_v_through_u >. 
-                
-
-                if dist[v.0] < f64::INFINITY {
-                    // indeed there is already some other path to `v` (not through `u`).
-                   // compare distances of that other path (not through `u`) and current path to `v` through `u`.
-                    if dist_to_v_through_u < dist[v.0] {
-                        dist[v.0] = dist_to_v_through_u; 
-                        prev[v.0] = Option::Some(u);
-                        // dist.insert(v, dist_to_v_through_u);
-                        // prev.insert(v, u);
-                        pq.push(PQItem{ distance: dist_to_v_through_u, id: edge.to_node });
-                    } else {
-                        // do nothing here. no need to update with a worse path.
+            // pop out node is: u
+            if let Some(edges) = g.adjacent_edges(u) {
+                for (i, edge) in edges.iter().enumerate() {
+                    let v = edge.to_node;
+
+                    // u's adjacent edges.
+                    // for each adj edge, edge.to_node should be: v
+                    // at least there is a path to v through u: s -> ... -> u -> v.
+                    // for that path, the total cost/distance from start node `s` to `v` is: distance to u + edge (u -> v) distance
+                    let dist_to_v_through_u = dist_to_u + edge.distance();
+
+                    // dist(v): distance to `v` throught some other ways/path already exist, infinite if none.
+                    // compare distances of that other path (not through `u`) and current path to `v` through `u`,
+                    // no need to update with a worse path.
+                    if dist_to_v_through_u < self.dist(v) {
+                        self.reach(v, dist_to_v_through_u, Some((u, i)));
+                        self.heap.push(v, dist_to_v_through_u);
                     }
-                } else {
-                    // no existing path to `v` found yet.
-                    dist[v.0] = dist_to_v_through_u; 
-                    prev[v.0] = Option::Some(u);
+                }
+            }
+        }
+
+        monitoring::settled_nodes(settled);
+        match best {
+            Some((distance, t)) => {
+                tracing::debug!(settled, distance, "route found");
+                Ok((distance, build_path(&self.prev, t)))
+            }
+            None => {
+                tracing::debug!(settled, "no route");
+                Err(NoRouteFound)
+            }
+        }
+        // in each step: find the shortest path to some node.
+    }
+
+    pub fn shortest_path_at(&mut self, g:&Graph, s: NodeIndex, t: NodeIndex, depart: WeekTime) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
+        self.shortest_path_at_between(g, &[(s, 0.0)], &[(t, 0.0)], depart)
+    }
+
+    // time-dependent Dijkstra: the cost of an edge depends on when we enter it.
+    // the labels are the seconds elapsed since `depart`. It's still correct because every edge is FIFO
+    // (entering an edge later never makes you leave it earlier, see SpeedProfile::travel_time).
+    // Edges closed by a conditional restriction at the time we'd enter them are skipped.
+    // the seeds are in seconds.
+    pub fn shortest_path_at_between(
+        &mut self,
+        g: &Graph,
+        sources: &[Seed],
+        targets: &[Seed],
+        depart: WeekTime,
+    ) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
+        self.reset(g.get_total_nodes());
+        self.timed = true;
+
+        let mut settled = 0;
+        for &(s, cost) in sources {
+            if cost < self.dist(s) {
+                self.reach(s, cost, None);
+                self.heap.push(s, cost);
+            }
+        }
+        let mut best: Option<(f64, NodeIndex)> = None;
+        while let Some((u, elapsed_at_u)) = self.heap.pop() {
+            if best.is_some_and(|(b, _)| elapsed_at_u >= b) {
+                break;
+            }
+            for &(t, cost) in targets {
+                if t == u && best.is_none_or(|(b, _)| elapsed_at_u + cost < b) {
+                    best = Some((elapsed_at_u + cost, u));
+                }
+            }
+            settled += 1;
+
+            let arrival_at_u = depart.add(elapsed_at_u);
+            if let Some(edges) = g.adjacent_edges(u) {
+                for (i, edge) in edges.iter().enumerate() {
+                    let v = edge.to_node;
+                    let travel_time = match g.edge_travel_time(edge, arrival_at_u) {
+                        Some(x) => x,
+                        None => continue, // closed now
+                    };
 
-                    pq.push(PQItem{ distance: dist_to_v_through_u, id: edge.to_node });
+                    let elapsed_at_v = elapsed_at_u + travel_time;
+                    if elapsed_at_v < self.dist(v) {
+                        self.reach(v, elapsed_at_v, Some((u, i)));
+                        self.heap.push(v, elapsed_at_v);
+                    }
                 }
             }
         }
+
+        monitoring::settled_nodes(settled);
+        match best {
+            Some((duration, t)) => {
+                tracing::debug!(settled, duration, "route found");
+                Ok((duration, build_path(&self.prev, t)))
+            }
+            None => {
+                tracing::debug!(settled, "no route");
+                Err(NoRouteFound)
+            }
+        }
     }
 
-    
+    // Dijkstra from `s` until every target is settled, or all nodes closer than `max_distance` are.
+    // returns (distance, duration) along the shortest path to each target, None when not reached.
+    // one search instead of one per target: that's what a distance matrix or map matching needs.
+    pub fn one_to_many(&mut self, g:&Graph, s: NodeIndex, targets: &[NodeIndex], max_distance: f64) -> Vec<Option<(f64, f64)>> {
+        self.one_to_many_from(g, &[(s, 0.0, 0.0)], targets, max_distance)
+    }
 
+    // same from several sources, each with the (distance, duration) to get to it
+    pub fn one_to_many_from(
+        &mut self,
+        g: &Graph,
+        sources: &[(NodeIndex, f64, f64)],
+        targets: &[NodeIndex],
+        max_distance: f64,
+    ) -> Vec<Option<(f64, f64)>> {
+        self.reset(g.get_total_nodes());
 
+        // several targets can be the same node
+        let mut remaining: HashMap<NodeIndex, usize> = HashMap::new();
+        for t in targets {
+            *remaining.entry(*t).or_insert(0) += 1;
+        }
+        let mut remaining_count = targets.len();
 
-    // dist[start] = 0
-    // prev[start] = None
+        for &(s, distance, duration) in sources {
+            if distance < self.dist(s) {
+                self.reach(s, distance, None);
+                self.duration[s.index()] = duration;
+                self.heap.push(s, distance);
+            }
+        }
+        while let Some((u, dist_to_u)) = self.heap.pop() {
+            if dist_to_u > max_distance {
+                break;
+            }
+            self.settled[u.index()] = self.generation;
 
-    // initial:
-    // we can assume/fill the infinite as the tentative shortest distance to all other nodes (except start node)
-    // prev[x] = None
+            if let Some(count) = remaining.remove(&u) {
+                remaining_count -= count;
+                if remaining_count == 0 {
+                    break;
+                }
+            }
 
+            if let Some(edges) = g.adjacent_edges(u) {
+                for (i, edge) in edges.iter().enumerate() {
+                    let v = edge.to_node;
+                    let dist_to_v_through_u = dist_to_u + edge.distance();
+                    if dist_to_v_through_u < self.dist(v) {
+                        self.reach(v, dist_to_v_through_u, Some((u, i)));
+                        self.duration[v.index()] = self.duration[u.index()] + edge.duration();
+                        self.heap.push(v, dist_to_v_through_u);
+                    }
+                }
+            }
+        }
 
-    
+        targets
+            .iter()
+            .map(|t| if self.is_settled(*t) { Some((self.dist[t.index()], self.duration[t.index()])) } else { None })
+            .collect()
+    }
+}
 
+// the searches with fresh buffers, for one-off queries (the engine reuses them)
+pub fn shortest_path(g:&Graph, s: NodeIndex, t: NodeIndex) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
+    SearchContext::new(g.get_total_nodes()).shortest_path(g, s, t)
+}
 
-    Err(NoRouteFound)
-    // in each step: find the shortest path to some node.
+pub fn shortest_path_at(g:&Graph, s: NodeIndex, t: NodeIndex, depart: WeekTime) -> Result<(f64, Vec<NodeIndex>), NoRouteFound> {
+    SearchContext::new(g.get_total_nodes()).shortest_path_at(g, s, t, depart)
+}
+
+pub fn one_to_many(g:&Graph, s: NodeIndex, targets: &[NodeIndex], max_distance: f64) -> Vec<Option<(f64, f64)>> {
+    SearchContext::new(g.get_total_nodes()).one_to_many(g, s, targets, max_distance)
 }
 
 #[test]
@@ -294,23 +1070,236 @@ fn test_f64() {
 
 #[test]
 fn test_pq() {
-    let mut pg : BinaryHeap<PQItem> = BinaryHeap::new();
-    pg.push(PQItem{
-        id: NodeIndex(1),
-        distance: 1.0
-    });
-
-    pg.push(PQItem{
-        id: NodeIndex(2),
-        distance: 5.0
-    });
-
-    pg.push(PQItem{
-        id: NodeIndex(3),
-        distance: 2.0
-    });
-
-    println!("{:?}", pg.pop());
-    println!("{:?}", pg.pop());
-    println!("{:?}", pg.pop());
-}
\ No newline at end of file
+    let mut pg = IndexedHeap::new(4);
+    pg.push(NodeIndex(1), 1.0);
+    pg.push(NodeIndex(2), 5.0);
+    pg.push(NodeIndex(3), 2.0);
+    pg.push(NodeIndex(2), 0.5); // decrease-key
+
+    assert_eq!(pg.pop(), Some((NodeIndex(2), 0.5)));
+    assert_eq!(pg.pop(), Some((NodeIndex(1), 1.0)));
+    assert_eq!(pg.pop(), Some((NodeIndex(3), 2.0)));
+    assert_eq!(pg.pop(), None);
+}
+
+#[test]
+fn test_shortest_path_at() {
+    // 1 -> 2 -> 4 is fast but closed on Monday mornings, 1 -> 3 -> 4 is slow
+    let mut nodes = HashMap::new();
+    for id in 1..=4 {
+        let id = osm::NodeID(id);
+        nodes.insert(id, Node { id, location: LatLon { lat: 0.0, lon: 0.0 } });
+    }
+    let ways = vec![
+        osm::Way {
+            nodes: vec![osm::NodeID(1), osm::NodeID(2), osm::NodeID(4)],
+            name: "fast".to_string(),
+            speed: 100.0,
+            restriction: TimeDomain::parse("Mo 08:00-09:00").ok(),
+        }
+        .measured(|_, _| 1000.0),
+        osm::Way {
+            nodes: vec![osm::NodeID(1), osm::NodeID(3), osm::NodeID(4)],
+            name: "slow".to_string(),
+            speed: 50.0,
+            restriction: None,
+        }
+        .measured(|_, _| 1000.0),
+    ];
+    let mut g = Graph::build(nodes, ways);
+    let index = |id| g.get_node_index(osm::NodeID(id)).unwrap();
+    // both ways link 1 and 4: neither is compressed into a single edge
+    let (s, t, fast, slow) = (index(1), index(4), index(2), index(3));
+
+    let (duration, path) = shortest_path_at(&g, s, t, WeekTime(10.0 * 3600.0)).unwrap();
+    assert_eq!(path, vec![s, fast, t]);
+    assert!((duration - 72.0).abs() < 1e-6);
+
+    let (duration, path) = shortest_path_at(&g, s, t, WeekTime(8.0 * 3600.0)).unwrap();
+    assert_eq!(path, vec![s, slow, t]);
+    assert!((duration - 144.0).abs() < 1e-6);
+
+    // a traffic jam on 2 -> 4 all day long
+    let mut profiles = HashMap::new();
+    profiles.insert((osm::NodeID(2), osm::NodeID(4)), SpeedProfile::new(vec![5.0; 96]).unwrap());
+    assert_eq!(g.set_speed_profiles(profiles), 1);
+    let (_, path) = shortest_path_at(&g, s, t, WeekTime(10.0 * 3600.0)).unwrap();
+    assert_eq!(path, vec![s, slow, t]);
+
+    // the static search ignores time, both routes are 2 km
+    let found = one_to_many(&g, s, &[fast, t, s], f64::INFINITY);
+    assert_eq!(found[0], Some((1000.0, 36.0)));
+    assert_eq!(found[1].map(|(d, _)| d), Some(2000.0));
+    assert_eq!(found[2], Some((0.0, 0.0)));
+    assert_eq!(one_to_many(&g, s, &[t], 1500.0), vec![None]);
+    assert_eq!(g.get_name(g.find_edge(s, slow).unwrap()), "slow");
+}
+
+#[test]
+fn test_bounding_box() {
+    let points = [LatLon { lat: 38.5, lon: -75.5 }, LatLon { lat: 39.8, lon: -75.0 }, LatLon { lat: 39.0, lon: -75.8 }];
+    let b = BoundingBox::around(points).unwrap();
+    assert_eq!(b.min, LatLon { lat: 38.5, lon: -75.8 });
+    assert_eq!(b.max, LatLon { lat: 39.8, lon: -75.0 });
+    assert!(b.contains(LatLon { lat: 39.0, lon: -75.1 }));
+    assert!(!b.contains(LatLon { lat: 39.0, lon: -74.9 }));
+    assert!(BoundingBox::around(vec![]).is_none());
+}
+
+#[test]
+fn test_search_context() {
+    // 1 - 2 - 3, and 4 - 5 which isn't connected to them
+    let mut nodes = HashMap::new();
+    for id in 1..=5 {
+        let id = osm::NodeID(id);
+        nodes.insert(id, Node { id, location: LatLon { lat: 0.0, lon: 0.0 } });
+    }
+    let way = |ids: &[i64]| osm::Way {
+        nodes: ids.iter().map(|id| osm::NodeID(*id)).collect(),
+        name: String::new(),
+        speed: 50.0,
+        restriction: None,
+    }
+    .measured(|_, _| 1000.0);
+    let g = Graph::build(nodes, vec![way(&[1, 2, 3]), way(&[4, 5])]);
+    let index = |id| g.get_node_index(osm::NodeID(id)).unwrap();
+
+    // what the previous searches reached must not leak into the next ones
+    let check = |context: &mut SearchContext| {
+        let (distance, path) = context.shortest_path(&g, index(1), index(3)).unwrap();
+        assert_eq!((distance, path), (2000.0, vec![index(1), index(3)]));
+        assert!(context.shortest_path(&g, index(1), index(4)).is_err());
+        assert_eq!(context.one_to_many(&g, index(3), &[index(1), index(5)], f64::INFINITY), vec![Some((2000.0, 144.0)), None]);
+    };
+    let mut context = SearchContext::new(g.get_total_nodes());
+    check(&mut context);
+    context.generation = u32::MAX; // the next search wraps around
+    check(&mut context);
+    assert_eq!(context.generation, 3);
+}
+
+#[test]
+fn test_compact_representation() {
+    // they were 72 (usize indices, f64 weights) and 24 (f64 coordinates) bytes, then edges were 40
+    // (with their from_node and Option<u32> references)
+    assert_eq!(std::mem::size_of::<Edge>(), 28);
+    assert_eq!(std::mem::size_of::<StoredNode>(), 16);
+
+    let p = LatLon { lat: 38.5374731, lon: -75.0572986 };
+    let back = FixedLatLon::from_latlon(p).to_latlon();
+    assert!((back.lat - p.lat).abs() < 1e-9 && (back.lon - p.lon).abs() < 1e-9);
+    assert_eq!(FixedLatLon::from_latlon(LatLon { lat: -90.0, lon: 180.0 }), FixedLatLon { lat: -900_000_000, lon: 1_800_000_000 });
+    assert_eq!((to_tenths(1112.04), to_tenths(-1.0), to_tenths(f64::INFINITY)), (11120, 0, u32::MAX));
+}
+
+#[test]
+fn test_compressed_chains() {
+    // a loop 1 - 2 - 3 - 1, and a street 1 - 4 - 5 - 6 going east from it
+    let mut nodes = HashMap::new();
+    for (id, lat, lon) in [(1, 0.0, 0.0), (2, 0.01, 0.0), (3, 0.01, -0.01), (4, 0.0, 0.01), (5, 0.0, 0.02), (6, 0.0, 0.04)] {
+        let id = osm::NodeID(id);
+        nodes.insert(id, Node { id, location: LatLon { lat, lon } });
+    }
+    // 100 m segments, but 200 m from 5 to 6
+    let way = |ids: &[i64]| {
+        osm::Way { nodes: ids.iter().map(|id| osm::NodeID(*id)).collect(), name: String::new(), speed: 36.0, restriction: None }
+            .measured(|_, to| if to == osm::NodeID(6) { 200.0 } else { 100.0 })
+    };
+    let g = Graph::build(nodes, vec![way(&[1, 2, 3, 1]), way(&[1, 4, 5, 6])]);
+    assert!(g.validate().is_empty());
+    // the loop keeps its nodes, the street is one edge
+    assert_eq!((g.get_total_nodes(), g.get_total_shape_points(), g.get_total_edges()), (4, 2, 8));
+    let index = |id| g.get_node_index(osm::NodeID(id)).unwrap();
+    let ids = |nodes: Vec<Node>| nodes.iter().map(|n| n.id.0).collect::<Vec<_>>();
+
+    let east = g.find_edge(index(1), index(6)).unwrap();
+    assert_eq!((east.distance(), east.duration()), (400.0, 40.0));
+    assert_eq!(ids(g.edge_geometry(index(1), east)), vec![1, 4, 5, 6]);
+    assert_eq!(ids(g.edge_geometry(index(6), g.find_edge(index(6), index(1)).unwrap())), vec![6, 5, 4, 1]);
+
+    let five = g.shape_point_position(osm::NodeID(5)).unwrap();
+    assert_eq!((five.from_node, five.to_node, five.offset, five.fraction), (index(1), index(6), 2, 0.5));
+    assert!(g.shape_point_position(osm::NodeID(2)).is_none());
+    let segment = g.segment(five.from_node, g.shape_edge(&five).unwrap());
+    let back = segment.part(2, 0, five.fraction);
+    assert_eq!((ids(back.nodes), back.distance), (vec![5, 4, 1], 200.0));
+
+    // a speed profile on a segment in the middle is for the whole edge, in that direction
+    let (one, six) = (index(1), index(6));
+    let mut g = g;
+    let mut profiles = HashMap::new();
+    profiles.insert((osm::NodeID(5), osm::NodeID(4)), SpeedProfile::new(vec![5.0; 96]).unwrap());
+    assert_eq!(g.set_speed_profiles(profiles), 1);
+    assert!(g.find_edge(six, one).unwrap().speed_profile().is_some());
+    assert!(g.find_edge(one, six).unwrap().speed_profile().is_none());
+}
+
+#[test]
+fn test_no_parallel_edges() {
+    // 1 - 2 - 3 is way 10, the direct 1 - 3 is way 11 which comes after it, closed at night
+    let mut nodes = HashMap::new();
+    for (id, lat) in [(1, 0.0), (2, 0.01), (3, 0.02)] {
+        let id = osm::NodeID(id);
+        nodes.insert(id, Node { id, location: LatLon { lat, lon: 0.0 } });
+    }
+    let ways = vec![
+        osm::Way {
+            nodes: vec![osm::NodeID(1), osm::NodeID(2), osm::NodeID(3)],
+            name: "around".to_string(),
+            speed: 50.0,
+            restriction: None,
+        }
+        .measured(|_, _| 1000.0),
+        osm::Way {
+            nodes: vec![osm::NodeID(1), osm::NodeID(3)],
+            name: "direct".to_string(),
+            speed: 50.0,
+            restriction: TimeDomain::parse("00:00-06:00").ok(),
+        }
+        .measured(|_, _| 1500.0),
+    ];
+    let g = Graph::build(nodes, ways);
+    // 2 stays a node: no second edge 1 - 3 with another geometry
+    assert_eq!(g.get_total_shape_points(), 0);
+    let index = |id| g.get_node_index(osm::NodeID(id)).unwrap();
+    let mut context = SearchContext::new(g.get_total_nodes());
+    let (_, path) = context.shortest_path_at(&g, index(1), index(3), WeekTime(3.0 * 3600.0)).unwrap();
+    assert_eq!(path, vec![index(1), index(2), index(3)]);
+    let names: Vec<&str> = context.path_segments(&g, &path).iter().map(|s| g.name(s.name)).collect();
+    assert_eq!(names, vec!["around", "around"]);
+    let (_, path) = context.shortest_path_at(&g, index(1), index(3), WeekTime(12.0 * 3600.0)).unwrap();
+    assert_eq!(g.name(context.path_segments(&g, &path)[0].name), "direct");
+}
+
+#[test]
+fn test_parallel_edges() {
+    // two ways both going 1 - 3: the short one is closed at night
+    let mut nodes = HashMap::new();
+    for (id, lat) in [(1, 0.0), (3, 0.01)] {
+        let id = osm::NodeID(id);
+        nodes.insert(id, Node { id, location: LatLon { lat, lon: 0.0 } });
+    }
+    let way = |name: &str, restriction: Option<TimeDomain>, meters: f64| {
+        osm::Way { nodes: vec![osm::NodeID(1), osm::NodeID(3)], name: name.to_string(), speed: 50.0, restriction }
+            .measured(move |_, _| meters)
+    };
+    let ways = vec![way("short", TimeDomain::parse("00:00-06:00").ok(), 1000.0), way("long", None, 1500.0)];
+    let mut g = Graph::build(nodes, ways);
+    let (one, three) = (g.get_node_index(osm::NodeID(1)).unwrap(), g.get_node_index(osm::NodeID(3)).unwrap());
+    assert_eq!(g.adjacent_edges(one).unwrap().iter().filter(|e| e.to_node == three).count(), 2);
+    // 18 km/h on both, instead of the 50 of the ways
+    let mut profiles = HashMap::new();
+    profiles.insert((osm::NodeID(1), osm::NodeID(3)), SpeedProfile::new(vec![18.0; 96]).unwrap());
+    assert_eq!(g.set_speed_profiles(profiles), 2);
+
+    let mut context = SearchContext::new(g.get_total_nodes());
+    for (hour, name, distance) in [(3.0, "long", 1500.0), (12.0, "short", 1000.0)] {
+        let (duration, path) = context.shortest_path_at(&g, one, three, WeekTime(hour * 3600.0)).unwrap();
+        let segments = context.path_segments(&g, &path);
+        assert_eq!(segments.len(), 1);
+        assert_eq!((g.name(segments[0].name), segments[0].distance), (name, distance));
+        // the time-dependent duration, not the one at 50 km/h
+        assert!((segments[0].duration - distance / 5.0).abs() < 1e-6);
+        assert!((segments[0].duration - duration).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::graph::NodeIndex;
+
+// the priority queue of the searches: a min-heap of nodes keyed by their distance (or duration).
+//
+// it knows where every node sits (`positions`), so a node is in it at most once: reaching it again
+// by a shorter path lowers its key in place (decrease-key) instead of pushing a duplicate that has
+// to be skipped later. The heap is 4-ary: it's shallower than a binary one and the children of a
+// node share a cache line.
+// keys are compared with f64::total_cmp, a NaN can't panic the search. a negative NaN would sort
+// before -inf, so push makes every NaN the positive one: larger than anything, even +inf.
+
+const ARITY: usize = 4;
+const NOT_IN_HEAP: usize = usize::MAX;
+
+pub struct IndexedHeap {
+    entries: Vec<(f64, NodeIndex)>, // heap ordered
+    positions: Vec<usize>,          // node -> index in entries, NOT_IN_HEAP if it isn't in
+}
+
+impl IndexedHeap {
+    // for the nodes 0..nodes
+    pub fn new(nodes: usize) -> IndexedHeap {
+        IndexedHeap {
+            entries: Vec::new(),
+            positions: vec![NOT_IN_HEAP; nodes],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // only touches the nodes still in the heap
+    pub fn clear(&mut self) {
+        for (_, node) in self.entries.drain(..) {
+            self.positions[node.index()] = NOT_IN_HEAP;
+        }
+    }
+
+    pub fn key(&self, node: NodeIndex) -> Option<f64> {
+        match self.positions[node.index()] {
+            NOT_IN_HEAP => None,
+            i => Some(self.entries[i].0),
+        }
+    }
+
+    // inserts `node`, or lowers its key if it's already in.
+    // false, and nothing changes, when it's already in with a key which isn't larger.
+    pub fn push(&mut self, node: NodeIndex, key: f64) -> bool {
+        let key = if key.is_nan() { f64::NAN } else { key };
+        match self.positions[node.index()] {
+            NOT_IN_HEAP => {
+                self.entries.push((key, node));
+                let i = self.entries.len() - 1;
+                self.positions[node.index()] = i;
+                self.sift_up(i);
+                true
+            }
+            i if key.total_cmp(&self.entries[i].0).is_lt() => {
+                self.entries[i].0 = key;
+                self.sift_up(i);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // the node with the smallest key
+    pub fn pop(&mut self) -> Option<(NodeIndex, f64)> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let last = self.entries.len() - 1;
+        self.swap(0, last);
+        let (key, node) = self.entries.pop().unwrap();
+        self.positions[node.index()] = NOT_IN_HEAP;
+        if !self.entries.is_empty() {
+            self.sift_down(0);
+        }
+        Some((node, key))
+    }
+
+    fn less(&self, i: usize, j: usize) -> bool {
+        self.entries[i].0.total_cmp(&self.entries[j].0).is_lt()
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.entries.swap(i, j);
+        self.positions[self.entries[i].1.index()] = i;
+        self.positions[self.entries[j].1.index()] = j;
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / ARITY;
+            if !self.less(i, parent) {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let first = i * ARITY + 1;
+            if first >= self.entries.len() {
+                break;
+            }
+            let last = (first + ARITY).min(self.entries.len());
+            let child = (first + 1..last).fold(first, |best, c| if self.less(c, best) { c } else { best });
+            if !self.less(child, i) {
+                break;
+            }
+            self.swap(i, child);
+            i = child;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrease_key() {
+        let mut heap = IndexedHeap::new(10);
+        // pseudo random keys, every node pushed a few times
+        let mut best = [f64::INFINITY; 10];
+        let mut x: u64 = 42;
+        for _ in 0..50 {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let node = (x >> 33) as usize % 10;
+            let key = ((x >> 40) % 1000) as f64;
+            assert_eq!(heap.push(NodeIndex::new(node), key), key < best[node]);
+            best[node] = best[node].min(key);
+        }
+        assert_eq!(heap.len(), best.iter().filter(|k| k.is_finite()).count());
+        assert_eq!(heap.key(NodeIndex(3)), Some(best[3]).filter(|k| k.is_finite()));
+
+        let mut popped = vec![];
+        while let Some((node, key)) = heap.pop() {
+            assert_eq!(key, best[node.index()]);
+            popped.push(key);
+        }
+        assert!(popped.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(heap.key(NodeIndex(3)), None);
+    }
+
+    #[test]
+    fn test_nan_and_clear() {
+        let mut heap = IndexedHeap::new(4);
+        heap.push(NodeIndex(0), f64::NAN);
+        heap.push(NodeIndex(1), 2.0);
+        heap.push(NodeIndex(2), f64::INFINITY);
+        heap.push(NodeIndex(3), -f64::NAN); // would be the smallest key of all
+        assert_eq!(heap.pop(), Some((NodeIndex(1), 2.0)));
+        assert_eq!(heap.pop(), Some((NodeIndex(2), f64::INFINITY)));
+        assert!(heap.pop().unwrap().1.is_nan());
+        assert!(heap.pop().unwrap().1.is_nan());
+
+        heap.push(NodeIndex(0), 1.0);
+        heap.clear();
+        assert!(heap.is_empty());
+        assert!(heap.push(NodeIndex(0), 5.0)); // not in anymore
+    }
+}
@@ -0,0 +1,147 @@
+use std::{fs::File, io::Read, path::Path};
+
+use osmpbf::{Element, ElementReader};
+
+use crate::{
+    graph::{LatLon, Node},
+    o5m::O5mSource,
+    osm,
+    osmxml::XmlSource,
+    parser::ParseError,
+};
+
+// the osm files we can read: .osm.pbf, .osm (XML, e.g. saved by JOSM) and .o5m.
+//
+// every format is an ElementSource: the nodes and ways of the file, in its order (relations
+// aren't used). The parser has a faster path for pbf (parallel over the blobs), the others
+// are read with `for_each`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Pbf,
+    Xml,
+    O5m,
+}
+
+impl Format {
+    // by the extension, else by the first bytes of the file
+    pub fn detect(path: &str) -> Result<Format, ParseError> {
+        if let Some(format) = Format::from_extension(path) {
+            return Ok(format);
+        }
+        let mut head = Vec::new();
+        File::open(path)?.take(64).read_to_end(&mut head)?;
+        Format::from_magic(&head).ok_or_else(|| ParseError::Invalid(format!("{}: unknown file format", path)))
+    }
+
+    fn from_extension(path: &str) -> Option<Format> {
+        let name = Path::new(path).file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".pbf") {
+            Some(Format::Pbf)
+        } else if name.ends_with(".osm") {
+            Some(Format::Xml)
+        } else if name.ends_with(".o5m") {
+            Some(Format::O5m)
+        } else {
+            None
+        }
+    }
+
+    fn from_magic(head: &[u8]) -> Option<Format> {
+        // o5m: a reset, then the header dataset "o5m2"
+        if head.starts_with(&[0xff, 0xe0, 0x04, b'o', b'5', b'm', b'2']) {
+            return Some(Format::O5m);
+        }
+        // pbf: the size of the first blob header (4 bytes), which is an "OSMHeader" one
+        if head.len() > 4 && head[4..].windows(9).take(8).any(|w| w == b"OSMHeader") {
+            return Some(Format::Pbf);
+        }
+        let text = std::str::from_utf8(head).ok()?.trim_start_matches('\u{feff}').trim_start();
+        match text.starts_with("<?xml") || text.starts_with("<osm") {
+            true => Some(Format::Xml),
+            false => None,
+        }
+    }
+}
+
+// a way with everything the import looks at
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsmWay {
+    pub id: i64,
+    pub nodes: Vec<osm::NodeID>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl OsmWay {
+    pub fn tags(&self) -> impl Iterator<Item = (&str, &str)> + Clone {
+        self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OsmElement {
+    Node(Node),
+    Way(OsmWay),
+}
+
+pub trait ElementSource {
+    // every node and way of the file, in its order. Can be called again, it reads the file again.
+    fn for_each(&self, f: &mut dyn FnMut(OsmElement)) -> Result<(), ParseError>;
+}
+
+// the source of `path`, whatever its format
+pub fn open(path: &str) -> Result<Box<dyn ElementSource>, ParseError> {
+    Ok(match Format::detect(path)? {
+        Format::Pbf => Box::new(PbfSource::from_path(path)),
+        Format::Xml => Box::new(XmlSource::from_path(path)),
+        Format::O5m => Box::new(O5mSource::from_path(path)),
+    })
+}
+
+pub struct PbfSource {
+    path: String,
+}
+
+impl PbfSource {
+    pub fn from_path(path: &str) -> PbfSource {
+        PbfSource { path: path.to_string() }
+    }
+}
+
+impl ElementSource for PbfSource {
+    fn for_each(&self, f: &mut dyn FnMut(OsmElement)) -> Result<(), ParseError> {
+        let node = |id, lat, lon| OsmElement::Node(Node { id: osm::NodeID(id), location: LatLon { lat, lon } });
+        ElementReader::from_path(&self.path)?.for_each(|element| match element {
+            Element::DenseNode(n) => f(node(n.id(), n.lat(), n.lon())),
+            Element::Node(n) => f(node(n.id(), n.lat(), n.lon())),
+            Element::Way(w) => f(OsmElement::Way(OsmWay {
+                id: w.id(),
+                nodes: w.refs().map(osm::NodeID).collect(),
+                tags: w.tags().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            })),
+            Element::Relation(_) => {}
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(Format::from_extension("data/delaware-latest.osm.pbf"), Some(Format::Pbf));
+        assert_eq!(Format::from_extension("bug-1234.OSM"), Some(Format::Xml));
+        assert_eq!(Format::from_extension("/tmp/x.o5m"), Some(Format::O5m));
+        assert_eq!(Format::from_extension("/tmp/extract"), None);
+
+        let mut pbf = vec![0, 0, 0, 13, 10, 9];
+        pbf.extend_from_slice(b"OSMHeader");
+        assert_eq!(Format::from_magic(&pbf), Some(Format::Pbf));
+        assert_eq!(Format::from_magic(b"\xff\xe0\x04o5m2\xdc"), Some(Format::O5m));
+        assert_eq!(Format::from_magic(b"  <?xml version='1.0'?>\n<osm>"), Some(Format::Xml));
+        assert_eq!(Format::from_magic(b"<osm version='0.6'>"), Some(Format::Xml));
+        assert_eq!(Format::from_magic(b"GRAPH\0\0\0"), None);
+    }
+}
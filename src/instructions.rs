@@ -0,0 +1,240 @@
+use geo::{Bearing, Haversine, Point};
+
+use crate::graph::{Graph, LatLon, RouteSegment};
+
+// turn-by-turn instructions: a route is cut into steps, a new step starts when the road name changes.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ManeuverType {
+    Depart,
+    Turn,
+    NewName, // the road changes its name but we go (more or less) straight
+    Arrive,
+}
+
+impl ManeuverType {
+    // the OSRM names
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManeuverType::Depart => "depart",
+            ManeuverType::Turn => "turn",
+            ManeuverType::NewName => "new name",
+            ManeuverType::Arrive => "arrive",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Modifier {
+    UTurn,
+    SharpRight,
+    Right,
+    SlightRight,
+    Straight,
+    SlightLeft,
+    Left,
+    SharpLeft,
+}
+
+impl Modifier {
+    // `turn` is the change of bearing in degrees, in (-180, 180], positive is to the right
+    pub fn from_turn_angle(turn: f64) -> Modifier {
+        match turn {
+            t if t.abs() <= 20.0 => Modifier::Straight,
+            t if t.abs() >= 170.0 => Modifier::UTurn,
+            t if t > 0.0 && t <= 60.0 => Modifier::SlightRight,
+            t if t > 0.0 && t <= 140.0 => Modifier::Right,
+            t if t > 0.0 => Modifier::SharpRight,
+            t if t >= -60.0 => Modifier::SlightLeft,
+            t if t >= -140.0 => Modifier::Left,
+            _ => Modifier::SharpLeft,
+        }
+    }
+
+    // the OSRM names
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Modifier::UTurn => "uturn",
+            Modifier::SharpRight => "sharp right",
+            Modifier::Right => "right",
+            Modifier::SlightRight => "slight right",
+            Modifier::Straight => "straight",
+            Modifier::SlightLeft => "slight left",
+            Modifier::Left => "left",
+            Modifier::SharpLeft => "sharp left",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Maneuver {
+    pub location: LatLon,
+    pub bearing_before: f64, // degrees clockwise from north, 0 for the departure
+    pub bearing_after: f64,  // 0 for the arrival
+    pub kind: ManeuverType,
+    pub modifier: Option<Modifier>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub name: String,
+    pub distance: f64,
+    pub duration: f64,
+    pub geometry: Vec<LatLon>, // from the maneuver to the next one
+    pub maneuver: Maneuver,
+}
+
+impl Step {
+    // human readable instruction, e.g. "Turn left onto Main Street"
+    pub fn instruction(&self) -> String {
+        let onto = if self.name.is_empty() {
+            String::new()
+        } else {
+            format!(" onto {}", self.name)
+        };
+
+        match (self.maneuver.kind, self.maneuver.modifier) {
+            (ManeuverType::Depart, _) => {
+                let on = if self.name.is_empty() {
+                    String::new()
+                } else {
+                    format!(" on {}", self.name)
+                };
+                format!("Head {}{}", compass(self.maneuver.bearing_after), on)
+            }
+            (ManeuverType::Arrive, _) => "You have arrived at your destination".to_string(),
+            (_, Some(Modifier::UTurn)) => format!("Make a U-turn{}", onto),
+            (_, Some(Modifier::Straight)) | (_, None) => format!("Continue{}", onto),
+            (_, Some(Modifier::SlightLeft)) => format!("Make a slight left{}", onto),
+            (_, Some(Modifier::SlightRight)) => format!("Make a slight right{}", onto),
+            (_, Some(Modifier::SharpLeft)) => format!("Make a sharp left{}", onto),
+            (_, Some(Modifier::SharpRight)) => format!("Make a sharp right{}", onto),
+            (_, Some(Modifier::Left)) => format!("Turn left{}", onto),
+            (_, Some(Modifier::Right)) => format!("Turn right{}", onto),
+        }
+    }
+}
+
+fn compass(bearing: f64) -> &'static str {
+    const DIRECTIONS: [&str; 8] = [
+        "north",
+        "northeast",
+        "east",
+        "southeast",
+        "south",
+        "southwest",
+        "west",
+        "northwest",
+    ];
+    DIRECTIONS[((bearing + 22.5) / 45.0) as usize % 8]
+}
+
+pub fn bearing(from: LatLon, to: LatLon) -> f64 {
+    Haversine::bearing(Point::new(from.lon, from.lat), Point::new(to.lon, to.lat))
+}
+
+// `segments` are those of a route from Engine::routing
+pub fn build_steps(graph: &Graph, segments: &[RouteSegment]) -> Vec<Step> {
+    // a route starting and ending at the same shape point has an empty one
+    let segments: Vec<&RouteSegment> = segments.iter().filter(|s| s.nodes.len() >= 2).collect();
+    if segments.is_empty() {
+        return Vec::new();
+    }
+
+    // leaving its first node, arriving at its last one
+    let bearing_after = |s: &RouteSegment| bearing(s.nodes[0].location, s.nodes[1].location);
+    let bearing_before = |s: &RouteSegment| bearing(s.nodes[s.nodes.len() - 2].location, s.nodes[s.nodes.len() - 1].location);
+
+    let mut steps: Vec<Step> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let same_road = i > 0 && segments[i - 1].name == segment.name;
+        if same_road {
+            let step = steps.last_mut().unwrap();
+            step.distance += segment.distance;
+            step.duration += segment.duration;
+            step.geometry.extend(segment.nodes[1..].iter().map(|n| n.location));
+            continue;
+        }
+
+        let maneuver = if i == 0 {
+            Maneuver {
+                location: segment.nodes[0].location,
+                bearing_before: 0.0,
+                bearing_after: bearing_after(segment),
+                kind: ManeuverType::Depart,
+                modifier: None,
+            }
+        } else {
+            let before = bearing_before(segments[i - 1]);
+            let after = bearing_after(segment);
+            // -180..180, positive to the right
+            let turn = (after - before + 540.0) % 360.0 - 180.0;
+            let modifier = Modifier::from_turn_angle(turn);
+            Maneuver {
+                location: segment.nodes[0].location,
+                bearing_before: before,
+                bearing_after: after,
+                kind: if modifier == Modifier::Straight {
+                    ManeuverType::NewName
+                } else {
+                    ManeuverType::Turn
+                },
+                modifier: Some(modifier),
+            }
+        };
+
+        steps.push(Step {
+            name: graph.name(segment.name).to_string(),
+            distance: segment.distance,
+            duration: segment.duration,
+            geometry: segment.nodes.iter().map(|n| n.location).collect(),
+            maneuver,
+        });
+    }
+
+    let last = segments[segments.len() - 1];
+    let arrival = last.nodes[last.nodes.len() - 1].location;
+    steps.push(Step {
+        name: graph.name(last.name).to_string(),
+        distance: 0.0,
+        duration: 0.0,
+        geometry: vec![arrival, arrival],
+        maneuver: Maneuver {
+            location: arrival,
+            bearing_before: bearing_before(last),
+            bearing_after: 0.0,
+            kind: ManeuverType::Arrive,
+            modifier: None,
+        },
+    });
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::SearchContext, osm};
+
+    #[test]
+    fn test_build_steps() {
+        // 1 -> 2 north on "Main Street", then 2 -> 3 east on "Oak Avenue"
+        let (mut nodes, main) = osm::street("Main Street", &[(1, 38.0, -75.0), (2, 38.01, -75.0)]);
+        let (oak_nodes, mut oak) = osm::street("Oak Avenue", &[(2, 38.01, -75.0), (3, 38.01, -74.99)]);
+        nodes.extend(oak_nodes);
+        oak.speed = 36.0; // 10 m/s
+        let graph = Graph::build(nodes, vec![main, oak]);
+        let index = |id| graph.get_node_index(osm::NodeID(id)).unwrap();
+        let mut context = SearchContext::new(graph.get_total_nodes());
+        let (_, path) = context.shortest_path(&graph, index(1), index(3)).unwrap();
+        assert_eq!(path, vec![index(1), index(2), index(3)]);
+
+        let steps = build_steps(&graph, &context.path_segments(&graph, &path));
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].instruction(), "Head north on Main Street");
+        assert_eq!(steps[1].maneuver.modifier, Some(Modifier::Right));
+        assert_eq!(steps[1].instruction(), "Turn right onto Oak Avenue");
+        assert!((steps[1].duration - steps[1].distance / 10.0).abs() < 0.1);
+        assert!((steps[1].distance - 876.0).abs() < 1.0);
+        assert_eq!(steps[2].maneuver.kind, ManeuverType::Arrive);
+    }
+}
@@ -1,12 +1,32 @@
+#[cfg(test)]
 use engine::Engine;
+#[cfg(test)]
 use graph::LatLon;
 
+pub mod changes;
+pub mod clip;
+pub mod conditional;
+pub mod dataset;
+pub mod config;
 pub mod engine;
+pub mod formats;
 pub mod graph;
+pub mod heap;
+pub mod input;
+pub mod instructions;
+pub mod matching;
+pub mod monitoring;
+pub mod o5m;
 pub mod parser;
+pub mod queries;
+pub mod reload;
+pub mod report;
 pub mod server;
 pub mod osm;
+pub mod osmxml;
+pub mod osrm;
 pub mod spatialindex;
+pub mod traffic;
 
 #[test]
 fn benchtest() {
@@ -22,7 +42,7 @@ fn benchtest() {
     };
 
     let start_time = std::time::Instant::now();
-    for i in 0..100000 {
+    for _ in 0..100000 {
         let result = engine.routing(orig, dest);
         result.unwrap();
     }
@@ -1,21 +1,609 @@
-use simple_nav::server;
+use std::{error::Error, process::ExitCode, sync::Arc, time::Instant};
 
-// tokio::main expects main() returns nothing -> ()
-#[tokio::main]
-async fn main() {
-    let args: Vec<_> = std::env::args().collect();
+use chrono::DateTime;
+use clap::{Args, Parser, Subcommand};
+use serde_json::json;
+use simple_nav::{
+    config::{Config, ProfileConfig, DEFAULT_REGION},
+    dataset::Dataset,
+    engine::Engine,
+    graph::LatLon,
+    monitoring,
+    server::{self, Loader, ServerOptions},
+};
 
-    if args.len() < 2 {
-        println!("need map pbf parameter");
-        return;
+/// A simple navigation engine for OpenStreetMap data
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// TOML config file, see config.example.toml (flags win over the config)
+    #[arg(long, global = true)]
+    config: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the HTTP server
+    Serve(ServeArgs),
+    /// Parse an OSM extract once and write a prepared graph file, which loads much faster
+    Preprocess {
+        /// OSM extracts (.osm.pbf, .osm or .o5m), several are merged into one graph
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<String>,
+        /// Prepared graph file to write
+        output: String,
+        /// Profile whose speeds are baked into the graph (default: the default profile)
+        #[arg(long)]
+        profile: Option<String>,
+        #[command(flatten)]
+        clip: ClipArgs,
+        /// Write the import report (dropped ways, data problems) to this JSON file
+        #[arg(long)]
+        report: Option<String>,
+    },
+    /// Apply OSM change files (.osc or .osc.gz, oldest first) to a prepared graph file
+    ApplyChanges {
+        /// Prepared graph file, updated in place unless --output is given
+        graph: String,
+        /// Change files, e.g. the daily diffs of the extract
+        #[arg(required = true, num_args = 1..)]
+        changes: Vec<String>,
+        /// Write the updated graph there instead
+        #[arg(long)]
+        output: Option<String>,
+        /// The profile the graph was prepared with
+        #[arg(long)]
+        profile: Option<String>,
+        /// The clipping the graph was prepared with
+        #[command(flatten)]
+        clip: ClipArgs,
+    },
+    /// Compute a single route and print it
+    Route(RouteArgs),
+    /// Print node, edge and connected component counts
+    Stats(DataArgs),
+    /// Check that the data can be loaded and the graph is consistent
+    Validate(DataArgs),
+}
+
+#[derive(Args, Clone)]
+struct DataArgs {
+    /// OSM extract (.osm.pbf, .osm or .o5m) or prepared graph file (default: data.path of the config)
+    #[arg(long)]
+    data: Option<String>,
+    /// Weekly speed profiles (CSV), for time-dependent routing
+    #[arg(long)]
+    speed_profiles: Option<String>,
+    /// Routing profile of the config (default: the default profile)
+    #[arg(long)]
+    profile: Option<String>,
+    /// Region of the config (required when there are several)
+    #[arg(long)]
+    region: Option<String>,
+}
+
+/// Only import the roads in an area (default: import.clip of the config)
+#[derive(Args)]
+struct ClipArgs {
+    /// Bounding box as min_lon,min_lat,max_lon,max_lat
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true, conflicts_with = "polygon")]
+    bbox: Option<Vec<f64>>,
+    /// GeoJSON or .poly file of the area
+    #[arg(long)]
+    polygon: Option<String>,
+    /// Keep the ways crossing the border whole instead of cutting them
+    #[arg(long)]
+    keep_border_ways: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    data: DataArgs,
+    /// Address to listen on [default: 0.0.0.0]
+    #[arg(long)]
+    bind: Option<String>,
+    /// [default: 3000]
+    #[arg(long)]
+    port: Option<u16>,
+    /// Worker threads (default: one per CPU core)
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Allowed CORS origin, can be repeated (default: any origin)
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+    /// Directory of the web page [default: web]
+    #[arg(long)]
+    web_dir: Option<String>,
+    /// Reload the data when the files change
+    #[arg(long)]
+    watch: bool,
+}
+
+#[derive(Args)]
+struct RouteArgs {
+    #[command(flatten)]
+    data: DataArgs,
+    /// Origin as lat,lon
+    #[arg(long, value_parser = LatLon::parse)]
+    orig: LatLon,
+    /// Destination as lat,lon
+    #[arg(long, value_parser = LatLon::parse)]
+    dest: LatLon,
+    /// Departure time (RFC 3339) for time-dependent routing
+    #[arg(long)]
+    depart_at: Option<String>,
+    /// Output: latlon, polyline, polyline6, geojson or gpx
+    #[arg(long, default_value = "latlon")]
+    format: String,
+}
+
+fn profile_config<'a>(config: &'a Config, profile: &str) -> Result<&'a ProfileConfig, Box<dyn Error>> {
+    config
+        .profiles
+        .get(profile)
+        .filter(|p| p.enabled)
+        .ok_or(format!("unknown or disabled profile: {}", profile).into())
+}
+
+// --data, else the region's prepared graph or extract when there are regions,
+// else the prepared graph of the profile, else data.path of the config
+fn data_path<'a>(
+    config: &'a Config,
+    args: &'a DataArgs,
+    region: &str,
+    profile: &str,
+) -> Result<&'a str, Box<dyn Error>> {
+    let profile_config = profile_config(config, profile)?;
+    if let Some(path) = &args.data {
+        return Ok(path);
+    }
+    if !config.regions.is_empty() {
+        let region = config
+            .regions
+            .get(region)
+            .ok_or(format!("unknown region: {}", region))?;
+        return Ok(region.prepared.get(profile).unwrap_or(&region.data));
+    }
+    let path = profile_config
+        .data
+        .as_ref()
+        .or(config.data.path.as_ref())
+        .ok_or("no data: use --data or set data.path in the config")?;
+    Ok(path)
+}
+
+// the regions to load: a single one with --data or without `regions` in the config
+fn regions(config: &Config, args: &DataArgs) -> Vec<String> {
+    match &args.region {
+        Some(r) => vec![r.clone()],
+        None if args.data.is_some() || config.regions.is_empty() => vec![DEFAULT_REGION.to_string()],
+        None => config.regions.keys().cloned().collect(),
+    }
+}
+
+// for the commands working on one engine
+fn selected_region(config: &Config, args: &DataArgs) -> Result<String, Box<dyn Error>> {
+    match regions(config, args).as_slice() {
+        [region] => Ok(region.clone()),
+        _ => Err("several regions in the config, use --region".into()),
     }
+}
+
+// the engine of one profile in one region
+fn open_engine(config: &Config, args: &DataArgs, region: &str, profile: &str) -> Result<Engine, Box<dyn Error>> {
+    let profile_config = profile_config(config, profile)?;
+    let path = data_path(config, args, region, profile)?;
 
-    // args: Vec<String>
-    let map_pbf = &args[1]; // the second positional parameter when running our program
+    tracing::info!("loading {} for region {}, profile {}", path, region, profile);
+    let started = Instant::now();
+    let mut engine = Engine::open_with(path, &config.import, profile_config)?;
+    engine.set_max_snap_distance(config.snapping.max_distance);
+    if let Some(path) = args.speed_profiles.as_ref().or(config.data.speed_profiles.as_ref()) {
+        let matched = engine.load_speed_profiles(path)?;
+        tracing::info!("speed profiles: {} edges", matched);
+    }
+    monitoring::engine_loaded(region, profile, &engine, started.elapsed());
+    Ok(engine)
+}
 
-    // server::server_start("0.0.0.0:3000")
+fn selected_profile<'a>(config: &'a Config, args: &'a DataArgs) -> &'a str {
+    args.profile.as_deref().unwrap_or(config.default_profile())
+}
+
+fn serve(config: &Config, args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    // tokio::main can't be configured at run-time, build the runtime ourselves
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = args.threads.or(config.server.threads) {
+        if threads == 0 {
+            return Err("--threads must be at least 1".into());
+        }
+        runtime.worker_threads(threads);
+    }
+    let runtime = runtime.enable_all().build()?;
+    let metrics = monitoring::install()?; // before loading, to record the load times
+
+    // --profile serves a single profile, all the enabled ones otherwise.
+    // --data is used by the profiles without their own prepared graph.
+    let profiles: Vec<String> = match &args.data.profile {
+        Some(p) => vec![p.clone()],
+        None => config.enabled_profiles().iter().map(|p| p.to_string()).collect(),
+    };
+    let regions = regions(config, &args.data);
+    // mistakes in the settings are reported now, not after the server started
+    for region in &regions {
+        for profile in &profiles {
+            data_path(config, &args.data, region, profile)?;
+        }
+    }
+
+    let default_profile = match &args.data.profile {
+        Some(p) => p.clone(),
+        None => config.default_profile().to_string(),
+    };
+    // called again for every reload: the files are read again, with the same settings
+    let load: Loader = {
+        let config = config.clone();
+        let data = args.data.clone();
+        Arc::new(move || {
+            let mut dataset = Dataset::new(&default_profile);
+            for region in &regions {
+                for profile in &profiles {
+                    let engine = open_engine(&config, &data, region, profile)
+                        .map_err(|e| format!("region {}, profile {}: {}", region, profile, e))?;
+                    let source = data_path(&config, &data, region, profile).map_err(|e| e.to_string())?;
+                    dataset.add(region, profile, engine, source);
+                }
+            }
+            Ok(dataset)
+        })
+    };
+    let cors_origins = if args.cors_origins.is_empty() {
+        config.server.cors_origins.clone()
+    } else {
+        args.cors_origins
+    };
+    let options = ServerOptions {
+        address: format!(
+            "{}:{}",
+            args.bind.as_deref().unwrap_or(&config.server.bind),
+            args.port.unwrap_or(config.server.port)
+        ),
+        cors_origins,
+        limits: config.limits.clone(),
+        web_dir: args.web_dir.unwrap_or(config.server.web_dir.clone()),
+        metrics: Some(metrics),
+        admin_token: config.server.admin_token.clone(),
+        watch: args.watch || config.server.watch,
+    };
 
     // Future: related async
-    server::server_start("0.0.0.0:3000", map_pbf).await // constructed a future object
-                                                        // x.await; // future object is lazy, it's not executed.
+    runtime.block_on(server::server_start(options, load)) // future object is lazy, it's not executed until block_on/await
+}
+
+// the config with the clip flags of preprocess / apply-changes
+fn with_clip(config: &Config, clip: ClipArgs) -> Result<Config, Box<dyn Error>> {
+    let mut config = config.clone();
+    if let Some(bbox) = clip.bbox {
+        let bbox: [f64; 4] = bbox.try_into().map_err(|_| "--bbox needs 4 numbers: min_lon,min_lat,max_lon,max_lat")?;
+        config.import.clip.bbox = Some(bbox);
+        config.import.clip.polygon = None;
+    }
+    if let Some(polygon) = clip.polygon {
+        config.import.clip.polygon = Some(polygon);
+        config.import.clip.bbox = None;
+    }
+    config.import.clip.keep_border_ways |= clip.keep_border_ways;
+    config.validate()?;
+    Ok(config)
+}
+
+fn preprocess(
+    config: &Config,
+    inputs: &[String],
+    output: &str,
+    profile: Option<&str>,
+    clip: ClipArgs,
+    report: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let config = &with_clip(config, clip)?;
+    let profile = profile.unwrap_or(config.default_profile());
+    let inputs: Vec<&str> = inputs.iter().map(|i| i.as_str()).collect();
+    tracing::info!("reading {} for profile {}", inputs.join(", "), profile);
+    let engine = Engine::prepare(&inputs, &config.import, profile_config(config, profile)?)?;
+    engine.save(output)?;
+    tracing::info!(
+        "wrote {}: {} nodes, {} edges",
+        output,
+        engine.graph().get_total_nodes(),
+        engine.graph().get_total_edges()
+    );
+    if let Some(import_report) = engine.import_report() {
+        println!("{}", import_report);
+        if let Some(path) = report {
+            std::fs::write(path, serde_json::to_string_pretty(import_report)?)?;
+            println!("report written to {}", path);
+        }
+    }
+    Ok(())
+}
+
+fn apply_changes(
+    config: &Config,
+    graph: &str,
+    changes: &[String],
+    output: Option<&str>,
+    profile: Option<&str>,
+    clip: ClipArgs,
+) -> Result<(), Box<dyn Error>> {
+    let config = &with_clip(config, clip)?;
+    let profile = profile.unwrap_or(config.default_profile());
+    let mut engine = Engine::load_with_roads(graph)?;
+    let changes: Vec<&str> = changes.iter().map(|c| c.as_str()).collect();
+    let stats = engine.apply_changes(&changes, &config.import, profile_config(config, profile)?)?;
+
+    // a server watching the file never sees half of it
+    let output = output.unwrap_or(graph);
+    let tmp = format!("{}.tmp", output);
+    engine.save(&tmp)?;
+    std::fs::rename(&tmp, output)?;
+    tracing::info!(
+        "wrote {}: {} nodes, {} edges ({:?})",
+        output,
+        engine.graph().get_total_nodes(),
+        engine.graph().get_total_edges(),
+        stats
+    );
+    Ok(())
+}
+
+fn route(config: &Config, args: RouteArgs) -> Result<(), Box<dyn Error>> {
+    if !matches!(args.format.as_str(), "latlon" | "polyline" | "polyline6" | "geojson" | "gpx") {
+        return Err(format!("unknown format: {}", args.format).into());
+    }
+    let depart_at = match &args.depart_at {
+        Some(t) => Some(DateTime::parse_from_rfc3339(t)?),
+        None => None,
+    };
+
+    let region = selected_region(config, &args.data)?;
+    let engine = open_engine(config, &args.data, &region, selected_profile(config, &args.data))?;
+    let result = match depart_at {
+        Some(t) => engine.routing_at(args.orig, args.dest, t)?,
+        None => engine.routing(args.orig, args.dest)?,
+    };
+
+    let path = match args.format.as_str() {
+        "gpx" => {
+            print!("{}", result.to_gpx("route"));
+            return Ok(());
+        }
+        "polyline" => json!(result.to_polyline(5)),
+        "polyline6" => json!(result.to_polyline(6)),
+        "geojson" => result.to_geojson_linestring(),
+        _ => json!(result.route_path),
+    };
+    let mut output = json!({
+        "distance": result.total_distance,
+        "duration": result.total_duration,
+        "path": path,
+    });
+    if let (Some(depart_at), Some(arrival)) = (depart_at, result.arrival) {
+        output["depart_at"] = json!(depart_at.to_rfc3339());
+        output["eta"] = json!(arrival.to_rfc3339());
+    }
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+fn stats(config: &Config, args: &DataArgs) -> Result<(), Box<dyn Error>> {
+    let engine = open_engine(config, args, &selected_region(config, args)?, selected_profile(config, args))?;
+    let graph = engine.graph();
+    let components = graph.components();
+    println!("nodes: {}", graph.get_total_nodes());
+    println!("shape points: {}", graph.get_total_shape_points());
+    println!("edges: {}", graph.get_total_edges());
+    println!("components: {}", components.len());
+    println!("largest component: {} nodes", components.first().copied().unwrap_or(0));
+    // per osm node (graph nodes and shape points), the first layout had no shape points
+    let (bytes, before) = (graph.memory_usage(), graph.uncompacted_memory_usage());
+    let osm_nodes = (graph.get_total_nodes() + graph.get_total_shape_points()).max(1);
+    println!("memory: {:.1} MB, {} bytes per osm node", bytes as f64 / 1e6, bytes / osm_nodes);
+    println!(
+        "before the compact layout (estimate): {:.1} MB, {} bytes per osm node",
+        before as f64 / 1e6,
+        before / osm_nodes
+    );
+    Ok(())
+}
+
+fn validate(config: &Config, args: &DataArgs) -> Result<(), Box<dyn Error>> {
+    let engine = open_engine(config, args, &selected_region(config, args)?, selected_profile(config, args))?;
+    let problems = engine.graph().validate();
+    for p in &problems {
+        eprintln!("{}", p);
+    }
+    if !problems.is_empty() {
+        return Err(format!("{} problems found", problems.len()).into());
+    }
+    println!("ok");
+    Ok(())
+}
+
+// RUST_LOG (e.g. simple_nav=debug) wins over logging.level.
+// logs go to stderr, stdout is for the output of route / stats.
+fn init_logging(config: &Config) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.logging.level));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if config.logging.format == "json" {
+        // the fields of the current span (e.g. request_id) are in every line
+        subscriber.json().with_current_span(true).with_span_list(false).init();
+    } else {
+        subscriber.init();
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse(); // prints the help / usage errors and exits by itself
+
+    let result = Config::load(cli.config.as_deref())
+        .map_err(|e| e.into())
+        .and_then(|config| {
+            init_logging(&config);
+            match cli.command {
+                Command::Serve(args) => serve(&config, args),
+                Command::Preprocess { inputs, output, profile, clip, report } => {
+                    preprocess(&config, &inputs, &output, profile.as_deref(), clip, report.as_deref())
+                }
+                Command::ApplyChanges { graph, changes, output, profile, clip } => {
+                    apply_changes(&config, &graph, &changes, output.as_deref(), profile.as_deref(), clip)
+                }
+                Command::Route(args) => route(&config, args),
+                Command::Stats(args) => stats(&config, &args),
+                Command::Validate(args) => validate(&config, &args),
+            }
+        });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    // 1 - 2 - 3 along a residential street
+    const MAP: &str = r#"<osm version='0.6'>
+  <node id='1' lat='38.00' lon='-75.0' />
+  <node id='2' lat='38.01' lon='-75.0' />
+  <node id='3' lat='38.02' lon='-75.0' />
+  <way id='10'> <nd ref='1' /> <nd ref='2' /> <nd ref='3' /> <tag k='highway' v='residential' /> </way>
+</osm>"#;
+
+    fn parse(args: &str) -> Result<Command, clap::Error> {
+        Cli::try_parse_from(["simple-nav"].into_iter().chain(args.split_whitespace())).map(|cli| cli.command)
+    }
+
+    fn data(path: &str) -> DataArgs {
+        DataArgs { data: Some(path.to_string()), speed_profiles: None, profile: None, region: None }
+    }
+
+    fn no_clip() -> ClipArgs {
+        ClipArgs { bbox: None, polygon: None, keep_border_ways: false }
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        Cli::command().debug_assert();
+
+        match parse("preprocess a.osm.pbf b.osm out.graph --bbox -75.6,39.7,-75.5,39.8").unwrap() {
+            Command::Preprocess { inputs, output, clip, .. } => {
+                assert_eq!((inputs, output), (vec!["a.osm.pbf".to_string(), "b.osm".to_string()], "out.graph".to_string()));
+                assert_eq!(clip.bbox, Some(vec![-75.6, 39.7, -75.5, 39.8]));
+            }
+            _ => panic!("not preprocess"),
+        }
+        match parse("apply-changes a.graph 1.osc 2.osc.gz --output b.graph").unwrap() {
+            Command::ApplyChanges { graph, changes, output, .. } => {
+                assert_eq!((graph.as_str(), changes.len(), output.as_deref()), ("a.graph", 2, Some("b.graph")));
+            }
+            _ => panic!("not apply-changes"),
+        }
+        match parse("route --orig 38.0,-75.0 --dest 38.02,-75.0 --profile bike --format gpx").unwrap() {
+            Command::Route(args) => {
+                assert_eq!(args.dest, LatLon { lat: 38.02, lon: -75.0 });
+                assert_eq!((args.data.profile.as_deref(), args.format.as_str()), (Some("bike"), "gpx"));
+            }
+            _ => panic!("not route"),
+        }
+        match parse("serve --port 8080 --cors-origin https://a.org --cors-origin https://b.org --watch").unwrap() {
+            Command::Serve(args) => assert_eq!((args.port, args.cors_origins.len(), args.watch), (Some(8080), 2, true)),
+            _ => panic!("not serve"),
+        }
+        assert!(matches!(parse("stats --region north").unwrap(), Command::Stats(DataArgs { region: Some(_), .. })));
+        assert!(matches!(parse("validate --data a.graph").unwrap(), Command::Validate(_)));
+
+        assert!(parse("preprocess out.graph").is_err()); // no input
+        assert!(parse("route --orig nowhere --dest 38.02,-75.0").is_err());
+        assert!(parse("preprocess a.osm out.graph --bbox 1,2,3,4 --polygon a.poly").is_err());
+        assert!(parse("fly").is_err());
+    }
+
+    #[test]
+    fn test_data_selection() {
+        let content = r#"
+            [regions.north]
+            data = "north.osm.pbf"
+            prepared = { car = "north-car.graph" }
+
+            [regions.south]
+            data = "south.osm.pbf"
+        "#;
+        let config = Config::parse(content, std::iter::empty()).unwrap();
+        let args = DataArgs { data: None, speed_profiles: None, profile: None, region: None };
+        assert_eq!(regions(&config, &args), vec!["north", "south"]);
+        assert!(selected_region(&config, &args).is_err());
+        assert_eq!(data_path(&config, &args, "north", "car").unwrap(), "north-car.graph");
+        assert_eq!(data_path(&config, &args, "south", "car").unwrap(), "south.osm.pbf");
+        assert!(data_path(&config, &args, "west", "car").is_err());
+        assert!(data_path(&config, &args, "north", "boat").is_err());
+
+        // --data is a single region, whatever the config
+        let args = data("other.graph");
+        assert_eq!(selected_region(&config, &args).unwrap(), DEFAULT_REGION);
+        assert_eq!(data_path(&config, &args, DEFAULT_REGION, "car").unwrap(), "other.graph");
+    }
+
+    #[test]
+    fn test_commands() {
+        let dir = std::env::temp_dir();
+        let file = |name: &str| dir.join(format!("simple-nav-cli-{}-{}", std::process::id(), name)).to_str().unwrap().to_string();
+        let (map, graph, changed, osc) = (file("map.osm"), file("map.graph"), file("changed.graph"), file("changes.osc"));
+        std::fs::write(&map, MAP).unwrap();
+        // a street from 3 going east
+        std::fs::write(
+            &osc,
+            r#"<osmChange version='0.6'><create>
+  <node id='4' lat='38.02' lon='-74.99' />
+  <way id='11'> <nd ref='3' /> <nd ref='4' /> <tag k='highway' v='residential' /> </way>
+</create></osmChange>"#,
+        )
+        .unwrap();
+        let config = Config::parse("", std::iter::empty()).unwrap();
+
+        preprocess(&config, std::slice::from_ref(&map), &graph, None, no_clip(), None).unwrap();
+        assert_eq!(Engine::load(&graph).unwrap().graph().get_total_nodes(), 2);
+        validate(&config, &data(&graph)).unwrap();
+        stats(&config, &data(&map)).unwrap(); // an extract works too
+        let route_args = |format: &str| RouteArgs {
+            data: data(&graph),
+            orig: LatLon { lat: 38.0, lon: -75.0 },
+            dest: LatLon { lat: 38.02, lon: -75.0 },
+            depart_at: None,
+            format: format.to_string(),
+        };
+        route(&config, route_args("geojson")).unwrap();
+        assert!(route(&config, route_args("svg")).is_err());
+
+        apply_changes(&config, &graph, std::slice::from_ref(&osc), Some(&changed), None, no_clip()).unwrap();
+        assert_eq!(Engine::load(&changed).unwrap().graph().get_total_nodes(), 3);
+        assert_eq!(Engine::load(&graph).unwrap().graph().get_total_nodes(), 2); // not in place
+        // only a prepared graph has the roads to change
+        assert!(apply_changes(&config, &map, std::slice::from_ref(&osc), None, None, no_clip()).is_err());
+
+        for path in [map, graph, changed, osc] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
 }
@@ -0,0 +1,247 @@
+use std::error::Error;
+
+use geo::{Distance, Haversine, Point};
+
+use crate::{
+    engine::{Engine, RouteResult, Snapped},
+    graph::LatLon,
+};
+
+// map matching: which roads did a (noisy) gps trace drive on?
+//
+// hidden markov model (Newson & Krumm, "Hidden Markov Map Matching Through Noise and Sparseness"):
+// - hidden states: the candidate nodes (graph nodes and shape points) close to each gps point
+// - emission: how likely a gps point is measured at that distance from the candidate (gaussian)
+// - transition: between two consecutive points, the route distance should be close to the
+//   straight line distance (exponential on the difference)
+// Viterbi finds the most likely sequence of candidates. When no candidate of a point can be
+// reached from the previous point, the trace is split and a new matching starts.
+
+const CANDIDATES: usize = 8;
+const DEFAULT_GPS_ACCURACY: f64 = 10.0; // meters, sigma of the emission
+const MIN_SEARCH_RADIUS: f64 = 50.0; // meters, candidates are nodes, not points on edges
+const BETA: f64 = 20.0; // meters, transition: tolerance for |route distance - straight distance|
+
+pub struct MatchedPoint {
+    pub trace_index: usize, // index in the input trace
+    pub snapped: Snapped,
+}
+
+pub struct Matching {
+    pub points: Vec<MatchedPoint>,
+    pub legs: Vec<RouteResult>, // between consecutive matched points
+    pub confidence: f64,        // 0..1, how well the routes follow the straight lines
+}
+
+fn straight_distance(a: LatLon, b: LatLon) -> f64 {
+    Haversine::distance(Point::new(a.lon, a.lat), Point::new(b.lon, b.lat))
+}
+
+impl Engine {
+    // `accuracies`: gps accuracy (meters) of each point, empty for the default
+    pub fn map_match(
+        &self,
+        trace: &[LatLon],
+        accuracies: &[f64],
+    ) -> Result<Vec<Matching>, Box<dyn Error>> {
+        // a zero (or NaN) accuracy would make every candidate impossible
+        let accuracy = |i: usize| {
+            accuracies.get(i).copied().filter(|a| a.is_finite() && *a > 0.0).unwrap_or(DEFAULT_GPS_ACCURACY)
+        };
+
+        // candidates and their emission log-probabilities
+        let candidates: Vec<Vec<Snapped>> = trace
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let radius = (accuracy(i) * 3.0).max(MIN_SEARCH_RADIUS);
+                self.within_radius(*p, radius)
+                    .into_iter()
+                    .take(CANDIDATES)
+                    .collect()
+            })
+            .collect();
+        let emission = |i: usize, c: &Snapped| -0.5 * (c.distance / accuracy(i)).powi(2);
+
+        let mut matchings = Vec::new();
+        // the sequence currently being matched: (trace index, candidate scores, back pointers)
+        let mut sequence: Vec<(usize, Vec<f64>, Vec<usize>)> = Vec::new();
+        let mut transitions: Vec<f64> = Vec::new(); // log prob of the chosen transitions
+
+        for i in 0..trace.len() {
+            if candidates[i].is_empty() {
+                continue; // not matched, too far from any road
+            }
+
+            let previous = match sequence.last() {
+                Some(x) => x,
+                None => {
+                    let scores = candidates[i].iter().map(|c| emission(i, c)).collect();
+                    sequence.push((i, scores, vec![]));
+                    continue;
+                }
+            };
+
+            let (prev_i, prev_scores, _) = previous;
+            let straight = straight_distance(trace[*prev_i], trace[i]);
+            let targets = &candidates[i];
+
+            let mut scores = vec![f64::NEG_INFINITY; targets.len()];
+            let mut back = vec![0; targets.len()];
+            let mut best_transition = vec![f64::NEG_INFINITY; targets.len()];
+            for (a, from) in candidates[*prev_i].iter().enumerate() {
+                // a route much longer than the straight line is very unlikely anyway
+                let max_distance = straight * 3.0 + 500.0;
+                let routes = self.distances(from, targets, max_distance);
+                for (b, route) in routes.iter().enumerate() {
+                    if let Some((route_distance, _)) = route {
+                        let transition = -(route_distance - straight).abs() / BETA;
+                        let score = prev_scores[a] + transition;
+                        if score > scores[b] {
+                            scores[b] = score;
+                            back[b] = a;
+                            best_transition[b] = transition;
+                        }
+                    }
+                }
+            }
+
+            if scores.iter().all(|s| s.is_infinite()) {
+                // can't get there from the previous point: close the matching, start a new one here
+                matchings.extend(self.finish_matching(&candidates, &sequence, &transitions)?);
+                sequence.clear();
+                transitions.clear();
+                let scores = candidates[i].iter().map(|c| emission(i, c)).collect();
+                sequence.push((i, scores, vec![]));
+                continue;
+            }
+
+            let best = (0..scores.len())
+                .max_by(|x, y| scores[*x].total_cmp(&scores[*y]))
+                .unwrap();
+            transitions.push(best_transition[best]);
+            for (b, score) in scores.iter_mut().enumerate() {
+                *score += emission(i, &candidates[i][b]);
+            }
+            sequence.push((i, scores, back));
+        }
+        matchings.extend(self.finish_matching(&candidates, &sequence, &transitions)?);
+
+        Ok(matchings)
+    }
+
+    // Viterbi backtracking + the routes between the chosen candidates.
+    // a single point is not a matching.
+    fn finish_matching(
+        &self,
+        candidates: &[Vec<Snapped>],
+        sequence: &[(usize, Vec<f64>, Vec<usize>)],
+        transitions: &[f64],
+    ) -> Result<Option<Matching>, Box<dyn Error>> {
+        if sequence.len() < 2 {
+            return Ok(None);
+        }
+
+        let (_, last_scores, _) = &sequence[sequence.len() - 1];
+        let mut chosen = (0..last_scores.len())
+            .max_by(|x, y| last_scores[*x].total_cmp(&last_scores[*y]))
+            .unwrap();
+
+        let mut points = Vec::new();
+        for (trace_index, _, back) in sequence.iter().rev() {
+            points.push(MatchedPoint {
+                trace_index: *trace_index,
+                snapped: candidates[*trace_index][chosen],
+            });
+            if !back.is_empty() {
+                chosen = back[chosen];
+            }
+        }
+        points.reverse();
+
+        let legs = points
+            .windows(2)
+            .map(|pair| self.routing_between(&pair[0].snapped, &pair[1].snapped))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mean_transition = transitions.iter().sum::<f64>() / transitions.len().max(1) as f64;
+
+        Ok(Some(Matching {
+            points,
+            legs,
+            confidence: mean_transition.exp(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::Graph, osm};
+
+    // Main Street north from 1 to 5 (111 m between its nodes), then Oak Avenue east to 7.
+    // Side Street runs along Main Street 39 m west of it, Lonely Road is 2 km north, neither is connected.
+    fn test_engine() -> Engine {
+        let streets = [
+            ("Main Street", (1..=5).map(|i| (i, 38.0 + (i - 1) as f64 * 0.001, -75.0)).collect::<Vec<_>>()),
+            ("Oak Avenue", vec![(5, 38.004, -75.0), (6, 38.004, -74.9987), (7, 38.004, -74.9974)]),
+            ("Side Street", (11..=13).map(|i| (i, 38.0 + (i - 10) as f64 * 0.001, -75.00045)).collect()),
+            ("Lonely Road", vec![(21, 38.02, -75.0), (22, 38.021, -75.0)]),
+        ];
+        let mut nodes = std::collections::HashMap::new();
+        let mut ways = Vec::new();
+        for (name, points) in streets {
+            let (street_nodes, way) = osm::street(name, &points);
+            nodes.extend(street_nodes);
+            ways.push(way);
+        }
+        Engine::from_graph(Graph::build(nodes, ways))
+    }
+
+    fn matched_ids(matching: &Matching) -> Vec<(usize, i64)> {
+        matching.points.iter().map(|p| (p.trace_index, p.snapped.osm_id.0)).collect()
+    }
+
+    #[test]
+    fn test_map_match() {
+        let engine = test_engine();
+        // a few meters off the road on both sides, the 4th point is nowhere near a road
+        let trace = [
+            LatLon { lat: 38.0, lon: -74.99992 },
+            LatLon { lat: 38.00105, lon: -75.00008 },
+            LatLon { lat: 38.002, lon: -74.99991 },
+            LatLon { lat: 38.5, lon: -75.0 },
+            LatLon { lat: 38.00296, lon: -75.00007 },
+            LatLon { lat: 38.00406, lon: -74.99993 },
+            LatLon { lat: 38.00393, lon: -74.99871 },
+            LatLon { lat: 38.00408, lon: -74.99738 },
+        ];
+        let matchings = engine.map_match(&trace, &[]).unwrap();
+        assert_eq!(matchings.len(), 1);
+        let matching = &matchings[0];
+        assert_eq!(matched_ids(matching), vec![(0, 1), (1, 2), (2, 3), (4, 4), (5, 5), (6, 6), (7, 7)]);
+        assert_eq!(matching.legs.len(), 6);
+        // along the roads: 4 * 111 m then 2 * 114 m
+        let distance: f64 = matching.legs.iter().map(|l| l.total_distance).sum();
+        assert!((distance - 673.0).abs() < 5.0, "{}", distance);
+        assert!(matching.confidence > 0.0 && matching.confidence <= 1.0);
+    }
+
+    #[test]
+    fn test_map_match_split() {
+        let engine = test_engine();
+        // Lonely Road can't be reached from Main Street: two matchings
+        let trace = [
+            LatLon { lat: 38.001, lon: -74.99995 },
+            LatLon { lat: 38.002, lon: -74.99995 },
+            LatLon { lat: 38.02, lon: -74.99995 },
+            LatLon { lat: 38.021, lon: -74.99995 },
+        ];
+        let matchings = engine.map_match(&trace, &[5.0; 4]).unwrap();
+        let ids: Vec<_> = matchings.iter().map(matched_ids).collect();
+        assert_eq!(ids, vec![vec![(0, 2), (1, 3)], vec![(2, 21), (3, 22)]]);
+
+        // a single point is not a matching
+        assert!(engine.map_match(&trace[1..3], &[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::{error::Error, time::Duration};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::engine::Engine;
+
+// Prometheus metrics, served at /metrics.
+// everything is recorded with the `metrics` macros, which do nothing until `install` is called:
+// the command line tools and the tests don't pay for them.
+//
+//   http_requests_total{endpoint, method, status}          counter
+//   http_request_duration_seconds{endpoint, status}        histogram
+//   routing_outcomes_total{outcome}                         counter, success / no_route / snap_failure
+//   search_settled_nodes                                    histogram, work done by one search
+//   graph_nodes{region, profile}, graph_edges{...}          gauges
+//   engine_load_seconds{region, profile}                    gauge, time to build or load the graph
+//   dataset_version                                         gauge, +1 for every reload
+//   dataset_reloads_total{outcome}                          counter, success / failure
+//   query_rejections_total{reason}                          counter, overloaded / timeout
+
+const HTTP_REQUESTS: &str = "http_requests_total";
+const HTTP_DURATION: &str = "http_request_duration_seconds";
+const ROUTING_OUTCOMES: &str = "routing_outcomes_total";
+const SETTLED_NODES: &str = "search_settled_nodes";
+
+pub fn install() -> Result<PrometheusHandle, Box<dyn Error>> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(HTTP_DURATION.to_string()),
+            &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
+        )?
+        .set_buckets_for_metric(
+            Matcher::Full(SETTLED_NODES.to_string()),
+            &[10.0, 100.0, 1e3, 1e4, 1e5, 1e6, 1e7],
+        )?
+        .install_recorder()?;
+    Ok(handle)
+}
+
+// the histograms are buffered, they have to be drained now and then
+pub async fn run_upkeep(handle: PrometheusHandle) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        handle.run_upkeep();
+    }
+}
+
+// middleware: count and time every request.
+// the endpoint is the route (/route/v1/{profile}/{coordinates}), not the path, so that
+// every coordinate doesn't become a new time series.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let endpoint = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "other".to_string()); // static files, 404
+    let method = request.method().to_string();
+
+    let started = tokio::time::Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+
+    counter!(HTTP_REQUESTS, "endpoint" => endpoint.clone(), "method" => method, "status" => status.clone())
+        .increment(1);
+    histogram!(HTTP_DURATION, "endpoint" => endpoint, "status" => status)
+        .record(started.elapsed().as_secs_f64());
+    response
+}
+
+pub(crate) fn routing_outcome(outcome: &'static str) {
+    counter!(ROUTING_OUTCOMES, "outcome" => outcome).increment(1);
+}
+
+pub(crate) fn settled_nodes(settled: usize) {
+    histogram!(SETTLED_NODES).record(settled as f64);
+}
+
+pub(crate) fn dataset_version(version: u64) {
+    gauge!("dataset_version").set(version as f64);
+}
+
+pub(crate) fn dataset_reload(outcome: &'static str) {
+    counter!("dataset_reloads_total", "outcome" => outcome).increment(1);
+}
+
+pub(crate) fn query_rejected(reason: &'static str) {
+    counter!("query_rejections_total", "reason" => reason).increment(1);
+}
+
+pub fn engine_loaded(region: &str, profile: &str, engine: &Engine, elapsed: Duration) {
+    let graph = engine.graph();
+    let labels = [("region", region.to_string()), ("profile", profile.to_string())];
+    gauge!("graph_nodes", &labels).set(graph.get_total_nodes() as f64);
+    gauge!("graph_edges", &labels).set(graph.get_total_edges() as f64);
+    gauge!("engine_load_seconds", &labels).set(elapsed.as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_metrics() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Full(SETTLED_NODES.to_string()), &[10.0, 100.0])
+            .unwrap()
+            .build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            routing_outcome("success");
+            routing_outcome("success");
+            routing_outcome("no_route");
+            settled_nodes(42);
+        });
+
+        let output = handle.render();
+        assert!(output.contains("routing_outcomes_total{outcome=\"success\"} 2"));
+        assert!(output.contains("routing_outcomes_total{outcome=\"no_route\"} 1"));
+        assert!(output.contains("search_settled_nodes_bucket{le=\"100\"} 1"));
+    }
+}
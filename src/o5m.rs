@@ -0,0 +1,393 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+};
+
+use crate::{
+    graph::{LatLon, Node},
+    input::{ElementSource, OsmElement, OsmWay},
+    osm,
+    parser::ParseError,
+};
+
+// the o5m format (https://wiki.openstreetmap.org/wiki/O5m), written by osmconvert.
+//
+// a file is a list of datasets: a type byte, the length of the data (varint), the data.
+// Most numbers are deltas from the same field of the previous object; strings are either
+// inline (0x00 ... 0x00) or a reference to one of the last 15000 inline strings.
+// A reset dataset (0xff) clears the deltas and the string table.
+
+const NODE: u8 = 0x10;
+const WAY: u8 = 0x11;
+const RELATION: u8 = 0x12;
+const HEADER: u8 = 0xe0;
+const END_OF_FILE: u8 = 0xfe;
+const RESET: u8 = 0xff;
+
+const STRING_TABLE_SIZE: usize = 15000;
+const MAX_TABLE_ENTRY: usize = 252; // the bytes of a string (pair) with its 0 terminators
+
+pub struct O5mSource {
+    path: String,
+}
+
+impl O5mSource {
+    pub fn from_path(path: &str) -> O5mSource {
+        O5mSource { path: path.to_string() }
+    }
+}
+
+impl ElementSource for O5mSource {
+    fn for_each(&self, f: &mut dyn FnMut(OsmElement)) -> Result<(), ParseError> {
+        read(BufReader::new(File::open(&self.path)?), f)
+    }
+}
+
+fn invalid(what: &str) -> ParseError {
+    ParseError::Invalid(format!("o5m: {}", what))
+}
+
+fn read<R: Read>(mut input: R, f: &mut dyn FnMut(OsmElement)) -> Result<(), ParseError> {
+    let mut state = State::default();
+    let mut data = Vec::new();
+    loop {
+        let mut kind = [0_u8];
+        match input.read_exact(&mut kind) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break, // the end of file dataset is optional
+            Err(e) => return Err(e.into()),
+        }
+        match kind[0] {
+            RESET => {
+                state = State::default();
+                continue;
+            }
+            END_OF_FILE => break,
+            0xf0..=0xff => continue, // other datasets without a length
+            _ => {}
+        }
+        // the length may be garbage: read what's there instead of allocating it first
+        let length = read_length(&mut input)?;
+        data.clear();
+        if (&mut input).take(length).read_to_end(&mut data)? as u64 != length {
+            return Err(invalid("truncated dataset"));
+        }
+        let mut cursor = Cursor { data: &data, position: 0 };
+        match kind[0] {
+            NODE => {
+                if let Some(node) = state.node(&mut cursor)? {
+                    f(OsmElement::Node(node));
+                }
+            }
+            WAY => {
+                if let Some(way) = state.way(&mut cursor)? {
+                    f(OsmElement::Way(way));
+                }
+            }
+            RELATION => state.relation(&mut cursor)?, // only for its strings
+            HEADER if !data.starts_with(b"o5m2") => return Err(invalid("not an o5m file")),
+            _ => {} // bounding box, timestamp...
+        }
+    }
+    Ok(())
+}
+
+fn read_length<R: Read>(input: &mut R) -> Result<u64, ParseError> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0_u8];
+        input.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("length too long"))
+}
+
+// a delta added to its field, a corrupt file could overflow it
+fn add(field: &mut i64, delta: i64) -> Result<(), ParseError> {
+    *field = field.checked_add(delta).ok_or_else(|| invalid("delta out of range"))?;
+    Ok(())
+}
+
+// the data of one dataset
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Cursor<'_> {
+    fn at_end(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, ParseError> {
+        let byte = *self.data.get(self.position).ok_or_else(|| invalid("truncated dataset"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<u64, ParseError> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("number too long"))
+    }
+
+    // the lowest bit is the sign
+    fn signed(&mut self) -> Result<i64, ParseError> {
+        let value = self.unsigned()?;
+        Ok(match value & 1 {
+            0 => (value >> 1) as i64,
+            _ => -((value >> 1) as i64) - 1,
+        })
+    }
+
+    // where the list starting with its length in bytes ends
+    fn list_end(&mut self) -> Result<usize, ParseError> {
+        let length = self.unsigned()?;
+        usize::try_from(length)
+            .ok()
+            .and_then(|length| self.position.checked_add(length))
+            .ok_or_else(|| invalid("bad length"))
+    }
+
+    // `count` strings ending with a 0, from the data or the string table.
+    // Inline ones are added to the table.
+    fn strings(&mut self, count: usize, table: &mut StringTable) -> Result<Vec<u8>, ParseError> {
+        if self.byte()? != 0 {
+            self.position -= 1;
+            let back = self.unsigned()? as usize;
+            return table.get(back).map(|s| s.to_vec()).ok_or_else(|| invalid("bad string reference"));
+        }
+        let start = self.position;
+        for _ in 0..count {
+            while self.byte()? != 0 {}
+        }
+        let strings = self.data[start..self.position].to_vec();
+        table.add(&strings);
+        Ok(strings)
+    }
+
+    fn pair(&mut self, table: &mut StringTable) -> Result<(String, String), ParseError> {
+        let strings = self.strings(2, table)?;
+        let mut parts = strings.split(|b| *b == 0);
+        let mut next = || String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+        Ok((next(), next()))
+    }
+}
+
+#[derive(Default)]
+struct StringTable {
+    entries: VecDeque<Vec<u8>>, // the latest first
+}
+
+impl StringTable {
+    fn add(&mut self, strings: &[u8]) {
+        if strings.len() > MAX_TABLE_ENTRY {
+            return;
+        }
+        if self.entries.len() == STRING_TABLE_SIZE {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(strings.to_vec());
+    }
+
+    // 1 is the latest
+    fn get(&self, back: usize) -> Option<&[u8]> {
+        self.entries.get(back.checked_sub(1)?).map(|s| s.as_slice())
+    }
+}
+
+// everything which is delta coded, and the strings, since the last reset
+#[derive(Default)]
+struct State {
+    node_id: i64,
+    way_id: i64,
+    relation_id: i64,
+    timestamp: i64,
+    changeset: i64,
+    lon: i64, // 1e-7 degrees
+    lat: i64,
+    way_node: i64,
+    members: [i64; 3], // node, way, relation
+    strings: StringTable,
+}
+
+impl State {
+    // None for a deleted node (.o5c)
+    fn node(&mut self, data: &mut Cursor) -> Result<Option<Node>, ParseError> {
+        add(&mut self.node_id, data.signed()?)?;
+        self.info(data)?;
+        if data.at_end() {
+            return Ok(None);
+        }
+        add(&mut self.lon, data.signed()?)?;
+        add(&mut self.lat, data.signed()?)?;
+        // the tags of nodes aren't used, but their strings go into the table
+        while !data.at_end() {
+            data.pair(&mut self.strings)?;
+        }
+        Ok(Some(Node {
+            id: osm::NodeID(self.node_id),
+            location: LatLon { lat: self.lat as f64 * 1e-7, lon: self.lon as f64 * 1e-7 },
+        }))
+    }
+
+    fn way(&mut self, data: &mut Cursor) -> Result<Option<OsmWay>, ParseError> {
+        add(&mut self.way_id, data.signed()?)?;
+        self.info(data)?;
+        if data.at_end() {
+            return Ok(None);
+        }
+        let end = data.list_end()?;
+        let mut nodes = Vec::new();
+        while data.position < end {
+            add(&mut self.way_node, data.signed()?)?;
+            nodes.push(osm::NodeID(self.way_node));
+        }
+        let mut tags = Vec::new();
+        while !data.at_end() {
+            tags.push(data.pair(&mut self.strings)?);
+        }
+        Ok(Some(OsmWay { id: self.way_id, nodes, tags }))
+    }
+
+    fn relation(&mut self, data: &mut Cursor) -> Result<(), ParseError> {
+        add(&mut self.relation_id, data.signed()?)?;
+        self.info(data)?;
+        if data.at_end() {
+            return Ok(());
+        }
+        let end = data.list_end()?;
+        while data.position < end {
+            let delta = data.signed()?;
+            // the member type ('0' node, '1' way, '2' relation) followed by its role
+            let member = data.strings(1, &mut self.strings)?;
+            let kind = member.first().map_or(0, |t| t.wrapping_sub(b'0') as usize);
+            let id = self.members.get_mut(kind).ok_or_else(|| invalid("bad member type"))?;
+            add(id, delta)?;
+        }
+        while !data.at_end() {
+            data.pair(&mut self.strings)?;
+        }
+        Ok(())
+    }
+
+    // version, timestamp, changeset and author: read for the deltas and the strings
+    fn info(&mut self, data: &mut Cursor) -> Result<(), ParseError> {
+        if data.unsigned()? == 0 {
+            return Ok(()); // no version, nothing else
+        }
+        add(&mut self.timestamp, data.signed()?)?;
+        // no changeset and author when there's no timestamp: the running one, a delta of 0 is
+        // just the same timestamp as the previous object
+        if self.timestamp != 0 {
+            add(&mut self.changeset, data.signed()?)?;
+            data.strings(2, &mut self.strings)?; // uid (a varint) and user name
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsigned(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        while value >= 0x80 {
+            bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    fn signed(value: i64) -> Vec<u8> {
+        unsigned(if value < 0 { ((-value - 1) as u64) << 1 | 1 } else { (value as u64) << 1 })
+    }
+
+    fn dataset(kind: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let data = parts.concat();
+        [&[kind][..], &unsigned(data.len() as u64), &data].concat()
+    }
+
+    #[test]
+    fn test_read_o5m() {
+        let file = [
+            vec![RESET],
+            dataset(HEADER, &[b"o5m2"]),
+            // node 10 at 38.5, -75.1: version 1, timestamp 2, changeset 3, uid 5 user "a", tag x=y
+            dataset(NODE, &[&signed(10), &[1], &signed(2), &signed(3), b"\0\x05\0a\0",
+                &signed(-751_000_000), &signed(385_000_000), b"\0x\0y\0"]),
+            // node 11: the same author and tag, as references to the string table
+            dataset(NODE, &[&signed(1), &[1], &signed(1), &signed(0), &unsigned(2), &signed(0), &signed(1), &unsigned(1)]),
+            // deleted node 12, no coordinates (a change file)
+            dataset(NODE, &[&signed(1), &[0]]),
+            vec![RESET],
+            // way 5: nodes 10 and 11, tags highway=residential and x=y (now inline again)
+            dataset(WAY, &[&signed(5), &[0], &unsigned(2), &signed(10), &signed(1), b"\0highway\0residential\0\0x\0y\0"]),
+            // relation 7 with way 5 as outer, it adds "1outer" to the table
+            dataset(RELATION, &[&signed(7), &[0], &unsigned(9), &signed(5), b"\x001outer\0"]),
+            // way 6: node 10, tags by reference (3 is highway=residential)
+            dataset(WAY, &[&signed(1), &[0], &unsigned(1), &signed(-1), &unsigned(3), &unsigned(2)]),
+            vec![END_OF_FILE],
+        ]
+        .concat();
+
+        let mut elements = vec![];
+        read(file.as_slice(), &mut |e| elements.push(e)).unwrap();
+        assert_eq!(elements.len(), 4);
+        let OsmElement::Node(node) = elements[0] else { panic!("not a node: {:?}", elements[0]) };
+        assert_eq!(node.id, osm::NodeID(10));
+        assert!((node.location.lat - 38.5).abs() < 1e-9 && (node.location.lon + 75.1).abs() < 1e-9);
+        let OsmElement::Node(node) = elements[1] else { panic!("not a node: {:?}", elements[1]) };
+        assert_eq!(node.id, osm::NodeID(11));
+        assert!((node.location.lat - 38.5000001).abs() < 1e-9);
+
+        let tags = vec![("highway".to_string(), "residential".to_string()), ("x".to_string(), "y".to_string())];
+        let way = |id, nodes: &[i64]| {
+            OsmElement::Way(OsmWay { id, nodes: nodes.iter().map(|n| osm::NodeID(*n)).collect(), tags: tags.clone() })
+        };
+        assert_eq!(elements[2], way(5, &[10, 11]));
+        assert_eq!(elements[3], way(6, &[10]));
+
+        // a change file isn't one
+        assert!(read([vec![RESET], dataset(HEADER, &[b"o5c2"])].concat().as_slice(), &mut |_| {}).is_err());
+        // truncated
+        assert!(read(&file[..file.len() - 4], &mut |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_same_timestamp() {
+        // node 11 has the timestamp of node 10 (a delta of 0), it still has a changeset and an author
+        let file = [
+            dataset(NODE, &[&signed(10), &[1], &signed(2), &signed(3), b"\0\x05\0a\0", &signed(1), &signed(2)]),
+            dataset(NODE, &[&signed(1), &[1], &signed(0), &signed(0), &unsigned(1), &signed(3), &signed(4)]),
+        ]
+        .concat();
+        let mut nodes = vec![];
+        read(file.as_slice(), &mut |e| nodes.push(e)).unwrap();
+        let OsmElement::Node(node) = nodes[1] else { panic!("not a node: {:?}", nodes[1]) };
+        assert_eq!((node.id, node.location.lon, node.location.lat), (osm::NodeID(11), 4e-7, 6e-7));
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        // a dataset of 2^63 bytes, a way whose node list goes past the end of memory, an id overflowing
+        let huge = [vec![NODE], unsigned(1 << 63)].concat();
+        assert!(read(huge.as_slice(), &mut |_| {}).is_err());
+        let way = dataset(WAY, &[&signed(5), &[0], &unsigned(u64::MAX >> 1), &signed(1)]);
+        assert!(read(way.as_slice(), &mut |_| {}).is_err());
+        let ids = [dataset(NODE, &[&signed(i64::MAX), &[0]]), dataset(NODE, &[&signed(1), &[0]])].concat();
+        assert!(read(ids.as_slice(), &mut |_| {}).is_err());
+    }
+}
@@ -1,12 +1,142 @@
-#[derive(Hash, Eq, PartialEq, PartialOrd, Debug, Clone, Copy)]
-pub struct NodeID(pub i64); 
+use crate::{conditional::TimeDomain, graph::LatLon};
+#[cfg(test)]
+use crate::{config::DistanceMetric, graph::Node, parser::distance};
+#[cfg(test)]
+use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
 
+#[derive(Hash, Eq, PartialEq, PartialOrd, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NodeID(pub i64);
 
-// a -> b -> c -> d
-//   d1   d2   d3
+
+
+// a road as read from osm: its nodes, but not where they are yet
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Way {
     pub nodes: Vec<NodeID>,
-    pub distances: Vec<f64>,
+    pub name: String, // `name`, or `ref` for unnamed roads (e.g. "US 13"), may be empty
+    pub speed: f64, // km/h, from `maxspeed` or the default speed of the `highway` type
+    pub restriction: Option<TimeDomain>, // `access:conditional=no @ (...)`: when the way is closed
+}
+
+// a way with the length of each of its segments, what the graph is built from:
+// a -> b -> c -> d
+//   d1   d2   d3
+// only built by measuring a Way, so there is always one less distance than nodes
+#[derive(Clone, Debug)]
+pub struct MeasuredWay {
+    nodes: Vec<NodeID>,
+    distances: Vec<f64>,
+    pub name: String,
+    pub speed: f64,
+    pub restriction: Option<TimeDomain>,
+}
+
+impl MeasuredWay {
+    pub fn nodes(&self) -> &[NodeID] {
+        &self.nodes
+    }
+
+    // meters, distances()[i] is from nodes()[i] to nodes()[i + 1]
+    pub fn distances(&self) -> &[f64] {
+        &self.distances
+    }
+}
+
+impl Way {
+    // the way with its distances, cut around the nodes `locate` can't find (a truncated extract):
+    // the parts in between are still roads. distances are computed for each part after the cut.
+    pub fn measure(&self, locate: impl Fn(NodeID) -> Option<LatLon>, distance: impl Fn(LatLon, LatLon) -> f64) -> Vec<MeasuredWay> {
+        self.split(|n| locate(n).is_some())
+            .into_iter()
+            .map(|part| {
+                let locations: Vec<LatLon> = part.nodes.iter().filter_map(|n| locate(*n)).collect();
+                let distances = locations.windows(2).map(|pair| distance(pair[0], pair[1])).collect();
+                part.with_distances(distances)
+            })
+            .collect()
+    }
+
+    // the way as it is, with the distance between its consecutive nodes given by their ids
+    pub fn measured(self, distance: impl Fn(NodeID, NodeID) -> f64) -> MeasuredWay {
+        let distances = self.nodes.windows(2).map(|pair| distance(pair[0], pair[1])).collect();
+        self.with_distances(distances)
+    }
+
+    fn with_distances(self, distances: Vec<f64>) -> MeasuredWay {
+        debug_assert_eq!(distances.len() + 1, self.nodes.len());
+        MeasuredWay {
+            nodes: self.nodes,
+            distances,
+            name: self.name,
+            speed: self.speed,
+            restriction: self.restriction,
+        }
+    }
+
+    // the runs of at least 2 consecutive nodes which `keep` keeps, as separate ways
+    pub fn split(&self, keep: impl Fn(NodeID) -> bool) -> Vec<Way> {
+        if self.nodes.iter().all(|n| keep(*n)) {
+            return vec![self.clone()];
+        }
+        let mut parts = Vec::new();
+        let mut start = 0;
+        for end in 0..=self.nodes.len() {
+            if end < self.nodes.len() && keep(self.nodes[end]) {
+                continue;
+            }
+            if end - start >= 2 {
+                parts.push(Way {
+                    nodes: self.nodes[start..end].to_vec(),
+                    name: self.name.clone(),
+                    speed: self.speed,
+                    restriction: self.restriction.clone(),
+                });
+            }
+            start = end + 1;
+        }
+        parts
+    }
+}
+
+// a street through `points` (osm id, lat, lon) at 50 km/h, with its nodes and the distances
+// an import would give it, to build small graphs in tests
+#[cfg(test)]
+pub(crate) fn street(name: &str, points: &[(i64, f64, f64)]) -> (HashMap<NodeID, Node>, MeasuredWay) {
+    let nodes: HashMap<NodeID, Node> = points
+        .iter()
+        .map(|&(id, lat, lon)| (NodeID(id), Node { id: NodeID(id), location: LatLon { lat, lon } }))
+        .collect();
+    let way = Way {
+        nodes: points.iter().map(|&(id, _, _)| NodeID(id)).collect(),
+        name: name.to_string(),
+        speed: 50.0,
+        restriction: None,
+    };
+    let locate = |id: NodeID| nodes.get(&id).map(|n| n.location);
+    let way = way.measure(locate, |from, to| distance(DistanceMetric::default(), from, to)).remove(0);
+    (nodes, way)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        // 2 and 5 aren't in the extract
+        let way = Way {
+            nodes: [1, 2, 3, 4, 5, 6].into_iter().map(NodeID).collect(),
+            name: "Main Street".to_string(),
+            speed: 50.0,
+            restriction: None,
+        };
+        let locate = |id: NodeID| (id.0 != 2 && id.0 != 5).then_some(LatLon { lat: 0.0, lon: id.0 as f64 });
+        let parts = way.measure(locate, |a, b| b.lon - a.lon);
+        assert_eq!(parts.len(), 1); // 1 and 6 alone aren't a road
+        assert_eq!(parts[0].nodes(), [NodeID(3), NodeID(4)]);
+        assert_eq!(parts[0].distances(), [1.0]);
+        assert_eq!(parts[0].name, "Main Street");
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+use flate2::read::MultiGzDecoder;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader, XmlVersion,
+};
+
+use crate::{
+    changes::Action,
+    graph::{LatLon, Node},
+    input::{ElementSource, OsmElement, OsmWay},
+    osm,
+    parser::ParseError,
+};
+
+// the OSM XML format, as saved by JOSM or the osm.org export:
+//   <node id='1' lat='38.5' lon='-75.0' />
+//   <way id='10'> <nd ref='1' /> ... <tag k='highway' v='residential' /> </way>
+// JOSM keeps the objects deleted in the editor with action='delete', they are skipped
+// (as the ones with visible='false').
+//
+// a small XML document is also the easiest way to write a map for a test:
+//   parse_elements(&XmlSource::from_string(xml), &import, &profile)
+//
+// change files (.osc, often gzipped: .osc.gz) are the same elements in <create>, <modify>
+// and <delete> sections, read with `for_each_change`.
+
+enum XmlInput {
+    Path(String),
+    Text(String),
+}
+
+pub struct XmlSource {
+    input: XmlInput,
+}
+
+impl XmlSource {
+    pub fn from_path(path: &str) -> XmlSource {
+        XmlSource { input: XmlInput::Path(path.to_string()) }
+    }
+
+    pub fn from_string(xml: impl Into<String>) -> XmlSource {
+        XmlSource { input: XmlInput::Text(xml.into()) }
+    }
+}
+
+impl XmlSource {
+    // the elements with the section they're in, None outside of <create>, <modify> and <delete>.
+    // In <delete> only the ids are meaningful.
+    pub fn for_each_change(&self, f: &mut dyn FnMut(Option<Action>, OsmElement)) -> Result<(), ParseError> {
+        match &self.input {
+            XmlInput::Path(path) if path.ends_with(".gz") => {
+                let file = File::open(path)?;
+                read(Reader::from_reader(BufReader::new(MultiGzDecoder::new(file))), f)
+            }
+            XmlInput::Path(path) => read(Reader::from_file(path)?, f),
+            XmlInput::Text(xml) => read(Reader::from_str(xml), f),
+        }
+    }
+}
+
+impl ElementSource for XmlSource {
+    fn for_each(&self, f: &mut dyn FnMut(OsmElement)) -> Result<(), ParseError> {
+        self.for_each_change(&mut |_, element| f(element))
+    }
+}
+
+impl From<quick_xml::Error> for ParseError {
+    fn from(value: quick_xml::Error) -> Self {
+        ParseError::Invalid(format!("xml: {}", value))
+    }
+}
+
+impl From<quick_xml::events::attributes::AttrError> for ParseError {
+    fn from(value: quick_xml::events::attributes::AttrError) -> Self {
+        ParseError::Invalid(format!("xml: {}", value))
+    }
+}
+
+fn read<R: BufRead>(mut reader: Reader<R>, f: &mut dyn FnMut(Option<Action>, OsmElement)) -> Result<(), ParseError> {
+    let mut buf = Vec::new();
+    let mut action = None; // the section of a change file
+    let mut way: Option<OsmWay> = None; // between <way> and </way>
+    let mut skipped_way = false; // a deleted one
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                "create" => action = Some(Action::Create),
+                "modify" => action = Some(Action::Modify),
+                "delete" => action = Some(Action::Delete),
+                "node" => {
+                    let attributes = Attributes::of(&e)?;
+                    // a deleted node may come without its coordinates
+                    let coordinate = |key| match action {
+                        Some(Action::Delete) => Ok(attributes.number(key).unwrap_or(0.0)),
+                        _ => attributes.number(key),
+                    };
+                    if !attributes.deleted() {
+                        let location = LatLon { lat: coordinate("lat")?, lon: coordinate("lon")? };
+                        f(action, OsmElement::Node(Node { id: osm::NodeID(attributes.number("id")?), location }));
+                    }
+                }
+                "way" => {
+                    let attributes = Attributes::of(&e)?;
+                    skipped_way = attributes.deleted();
+                    let started = OsmWay { id: attributes.number("id")?, nodes: vec![], tags: vec![] };
+                    match (empty, skipped_way) {
+                        (_, true) => {}
+                        (true, false) => f(action, OsmElement::Way(started)), // <way id='1'/>: no nodes
+                        (false, false) => way = Some(started),
+                    }
+                }
+                "nd" => {
+                    if let Some(way) = way.as_mut() {
+                        way.nodes.push(osm::NodeID(Attributes::of(&e)?.number("ref")?));
+                    }
+                }
+                "tag" => {
+                    if let Some(way) = way.as_mut() {
+                        let attributes = Attributes::of(&e)?;
+                        way.tags.push((attributes.text("k")?.to_string(), attributes.text("v")?.to_string()));
+                    }
+                }
+                _ => {}
+            },
+            Event::End(e) => match e.name().as_ref() {
+                "way" => {
+                    if let Some(way) = way.take() {
+                        f(action, OsmElement::Way(way));
+                    }
+                    skipped_way = false;
+                }
+                "create" | "modify" | "delete" => action = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    if way.is_some() || skipped_way {
+        return Err(ParseError::Invalid("xml: unterminated <way>".to_string()));
+    }
+    Ok(())
+}
+
+// the attributes of an element, unescaped
+struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    fn of(element: &BytesStart) -> Result<Attributes, ParseError> {
+        let mut attributes = Vec::new();
+        for attribute in element.attributes() {
+            let attribute = attribute?;
+            let key = attribute.key.as_ref().to_string();
+            attributes.push((key, attribute.normalized_value(XmlVersion::Implicit1_0)?.into_owned()));
+        }
+        Ok(Attributes(attributes))
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn text(&self, key: &str) -> Result<&str, ParseError> {
+        self.get(key)
+            .ok_or_else(|| ParseError::Invalid(format!("xml: missing attribute {}", key)))
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &str) -> Result<T, ParseError> {
+        let value = self.text(key)?;
+        value
+            .parse()
+            .map_err(|_| ParseError::Invalid(format!("xml: {}='{}' isn't a number", key, value)))
+    }
+
+    fn deleted(&self) -> bool {
+        self.get("action") == Some("delete") || self.get("visible") == Some("false")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(xml: &str) -> Result<Vec<OsmElement>, ParseError> {
+        let mut elements = vec![];
+        XmlSource::from_string(xml).for_each(&mut |e| elements.push(e))?;
+        Ok(elements)
+    }
+
+    #[test]
+    fn test_read_josm_file() {
+        let xml = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version='0.6' upload='false' generator='JOSM'>
+  <node id='-1' action='modify' visible='true' lat='38.5' lon='-75.1' />
+  <node id='2' version='3' lat='38.6' lon='-75.2'>
+    <tag k='highway' v='traffic_signals' />
+  </node>
+  <node id='3' action='delete' lat='38.7' lon='-75.3' />
+  <way id='10' action='modify'>
+    <nd ref='-1' />
+    <nd ref='2' />
+    <tag k='highway' v='residential' />
+    <tag k='name' v='Front &amp; Main' />
+  </way>
+  <way id='11' action='delete'>
+    <nd ref='2' />
+    <nd ref='3' />
+  </way>
+  <relation id='20'>
+    <member type='way' ref='10' role='' />
+    <tag k='type' v='route' />
+  </relation>
+</osm>"#;
+        let elements = elements(xml).unwrap();
+        assert_eq!(elements.len(), 3);
+        assert_eq!(
+            elements[0],
+            OsmElement::Node(Node { id: osm::NodeID(-1), location: LatLon { lat: 38.5, lon: -75.1 } })
+        );
+        let OsmElement::Way(way) = &elements[2] else { panic!("not a way: {:?}", elements[2]) };
+        assert_eq!(way.id, 10);
+        assert_eq!(way.nodes, vec![osm::NodeID(-1), osm::NodeID(2)]);
+        assert_eq!(way.tags().collect::<Vec<_>>(), vec![("highway", "residential"), ("name", "Front & Main")]);
+    }
+
+    #[test]
+    fn test_read_change_file() {
+        let osc = r#"<osmChange version="0.6" generator="osmium">
+<create>
+  <node id="5" version="1" lat="38.5" lon="-75.1"/>
+</create>
+<modify>
+  <way id="10" version="2"><nd ref="1"/><nd ref="5"/><tag k="highway" v="primary"/></way>
+</modify>
+<delete>
+  <node id="3" version="4"/>
+  <way id="11" version="3"/>
+</delete>
+</osmChange>"#;
+        let mut changes = vec![];
+        XmlSource::from_string(osc)
+            .for_each_change(&mut |action, element| {
+                let id = match element {
+                    OsmElement::Node(n) => n.id.0,
+                    OsmElement::Way(w) => w.id,
+                };
+                changes.push((action, id));
+            })
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (Some(Action::Create), 5),
+                (Some(Action::Modify), 10),
+                (Some(Action::Delete), 3),
+                (Some(Action::Delete), 11)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_xml() {
+        assert!(elements("<osm><node id='1' lat='x' lon='0'/></osm>").is_err());
+        assert!(elements("<osm><node id='1' lon='0'/></osm>").is_err());
+        assert!(elements("<osm><way id='1'><nd ref='1'/>").is_err());
+        assert_eq!(elements("<osm></osm>").unwrap(), vec![]);
+    }
+}
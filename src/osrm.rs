@@ -0,0 +1,854 @@
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use geo::{LineString, Simplify};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    config::LimitsConfig,
+    engine::{Engine, EngineErrors, RouteResult, Snapped, SnappedAt},
+    formats::{decode_polyline, encode_polyline, geojson_linestring},
+    graph::LatLon,
+    instructions::{ManeuverType, Step},
+    dataset::{Dataset, DatasetError},
+    server::{AppState, Ready},
+};
+
+// OSRM compatible http api (https://project-osrm.org/docs/v5.24.0/api/), so that existing clients
+// (leaflet-routing-machine, osrm-text-instructions, python notebooks...) can talk to us.
+//
+//   /route/v1/{profile}/{coordinates}
+//   /table/v1/{profile}/{coordinates}
+//   /nearest/v1/{profile}/{coordinates}
+//   /match/v1/{profile}/{coordinates}
+//
+// `profile` is one of the profiles of the config file. The usual OSRM names (driving, cycling,
+// walking) are aliases of car, bike and foot, and with a single profile anything is accepted,
+// like osrm-routed which serves one profile per process.
+// With several regions, `?region=` picks one, by default it's the one containing the coordinates.
+
+pub(crate) fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/route/v1/{profile}/{coordinates}", get(route))
+        .route("/table/v1/{profile}/{coordinates}", get(table))
+        .route("/nearest/v1/{profile}/{coordinates}", get(nearest))
+        .route("/match/v1/{profile}/{coordinates}", get(matching))
+}
+
+#[derive(Debug, Serialize)]
+struct OsrmError {
+    code: &'static str,
+    message: String,
+}
+
+impl OsrmError {
+    fn new(code: &'static str, message: impl Into<String>) -> OsrmError {
+        OsrmError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    // EngineErrors -> OSRM error codes
+    fn from_engine(e: Box<dyn Error>) -> OsrmError {
+        match e.downcast_ref::<EngineErrors>() {
+            Some(EngineErrors::CantFindNearestNode) => {
+                OsrmError::new("NoSegment", "Could not find a matching segment for any coordinate")
+            }
+            Some(EngineErrors::CantFindRoute) => OsrmError::new("NoRoute", "Impossible route between points"),
+            _ => OsrmError::new("InternalError", e.to_string()),
+        }
+    }
+}
+
+// {profile} path segment and `region` option -> engine
+fn profile_engine<'a>(
+    dataset: &'a Dataset,
+    profile: &str,
+    region: Option<&str>,
+    coordinates: &[LatLon],
+) -> Result<&'a Engine, OsrmError> {
+    let region = dataset.region(region, coordinates).map_err(|e| match e {
+        DatasetError::OutsideRegions => OsrmError::new("NoSegment", "No region contains all the coordinates"),
+        e => OsrmError::new("InvalidOptions", e.to_string()),
+    })?;
+    let alias = match profile {
+        "driving" => "car",
+        "cycling" => "bike",
+        "walking" => "foot",
+        other => other,
+    };
+    let profiles = region.profiles();
+    let name = if profiles.contains_key(profile) {
+        profile
+    } else if profiles.contains_key(alias) {
+        alias
+    } else if profiles.len() == 1 {
+        profiles.keys().next().unwrap()
+    } else {
+        return Err(OsrmError::new("InvalidOptions", format!("Unknown profile: {}", profile)));
+    };
+    region.engine(name).map_err(|e| OsrmError::new("InvalidOptions", e.to_string()))
+}
+
+impl IntoResponse for OsrmError {
+    fn into_response(self) -> Response {
+        let status = if self.code == "InternalError" {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::BAD_REQUEST // like osrm-routed, also for NoRoute / NoSegment
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+// `lon,lat;lon,lat`, `polyline(...)` or `polyline6(...)`, with an optional `.json` suffix
+fn parse_coordinates(input: &str) -> Result<Vec<LatLon>, OsrmError> {
+    let input = input.strip_suffix(".json").unwrap_or(input);
+    let invalid = || OsrmError::new("InvalidQuery", "Query string malformed close to position 0");
+
+    for (prefix, precision) in [("polyline6(", 6), ("polyline(", 5)] {
+        if let Some(encoded) = input.strip_prefix(prefix) {
+            let encoded = encoded.strip_suffix(')').ok_or_else(invalid)?;
+            return decode_polyline(encoded, precision).map_err(|_| invalid());
+        }
+    }
+
+    input
+        .split(';')
+        .map(|pair| match pair.split(',').collect::<Vec<_>>().as_slice() {
+            [lon, lat] => {
+                let lon: f64 = lon.parse().map_err(|_| invalid())?;
+                let lat: f64 = lat.parse().map_err(|_| invalid())?;
+                if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+                    return Err(OsrmError::new("InvalidValue", "Invalid coordinate value"));
+                }
+                Ok(LatLon { lat, lon })
+            }
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+// `5;10;unlimited` (empty items are allowed: use the default)
+fn parse_list<T: std::str::FromStr>(input: &Option<String>, name: &str) -> Result<Vec<Option<T>>, OsrmError> {
+    match input {
+        None => Ok(vec![]),
+        Some(s) => s
+            .split(';')
+            .map(|item| {
+                if item.is_empty() || item == "unlimited" {
+                    Ok(None)
+                } else {
+                    item.parse()
+                        .map(Some)
+                        .map_err(|_| OsrmError::new("InvalidOptions", format!("{} is malformed", name)))
+                }
+            })
+            .collect(),
+    }
+}
+
+// a radius of 0 (or NaN) would make every candidate impossible, a huge one scan the whole graph
+fn check_radiuses(radiuses: &[Option<f64>], max_radius: f64) -> Result<(), OsrmError> {
+    for r in radiuses.iter().flatten() {
+        if !r.is_finite() || *r <= 0.0 {
+            return Err(OsrmError::new("InvalidOptions", "radiuses must be positive"));
+        }
+        if *r > max_radius {
+            return Err(OsrmError::new("InvalidOptions", format!("radiuses must be at most {} meters", max_radius)));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct RouteOptions {
+    steps: Option<bool>,
+    annotations: Option<String>, // true, false or a list: nodes,distance,duration,speed
+    geometries: Option<String>,  // polyline (default), polyline6, geojson
+    overview: Option<String>,    // simplified (default), full, false
+    alternatives: Option<String>, // accepted, we never return alternatives
+    region: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Waypoint {
+    hint: String,
+    distance: f64,
+    name: String,
+    location: [f64; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nodes: Option<[i64; 2]>, // only for /nearest
+}
+
+struct GeometryOptions<'a> {
+    geometries: &'a str,
+    overview: &'a str,
+    steps: bool,
+    annotations: Vec<&'a str>,
+}
+
+impl<'a> GeometryOptions<'a> {
+    fn parse(
+        geometries: &'a Option<String>,
+        overview: &'a Option<String>,
+        steps: Option<bool>,
+        annotations: &'a Option<String>,
+    ) -> Result<GeometryOptions<'a>, OsrmError> {
+        let geometries = geometries.as_deref().unwrap_or("polyline");
+        if !matches!(geometries, "polyline" | "polyline6" | "geojson") {
+            return Err(OsrmError::new("InvalidOptions", "geometries must be polyline, polyline6 or geojson"));
+        }
+        let overview = overview.as_deref().unwrap_or("simplified");
+        if !matches!(overview, "simplified" | "full" | "false") {
+            return Err(OsrmError::new("InvalidOptions", "overview must be simplified, full or false"));
+        }
+        let annotations = match annotations.as_deref() {
+            None | Some("false") => vec![],
+            Some("true") => vec!["nodes", "distance", "duration", "speed"],
+            Some(list) => {
+                let list: Vec<&str> = list.split(',').collect();
+                if list
+                    .iter()
+                    .any(|a| !matches!(*a, "nodes" | "distance" | "duration" | "speed"))
+                {
+                    return Err(OsrmError::new("InvalidOptions", "unsupported annotations"));
+                }
+                list
+            }
+        };
+        Ok(GeometryOptions {
+            geometries,
+            overview,
+            steps: steps.unwrap_or(false),
+            annotations,
+        })
+    }
+
+    fn encode(&self, points: &[LatLon]) -> Value {
+        match self.geometries {
+            "polyline6" => json!(encode_polyline(points, 6)),
+            "geojson" => geojson_linestring(points),
+            _ => json!(encode_polyline(points, 5)),
+        }
+    }
+}
+
+fn waypoint(engine: &Engine, snapped: &Snapped) -> Waypoint {
+    Waypoint {
+        hint: String::new(),
+        distance: snapped.distance,
+        name: node_name(engine, snapped),
+        location: [snapped.location.lon, snapped.location.lat],
+        nodes: None,
+    }
+}
+
+// the name of any road going through the node
+fn node_name(engine: &Engine, snapped: &Snapped) -> String {
+    engine
+        .road_names(snapped)
+        .first()
+        .map(|n| n.to_string())
+        .unwrap_or_default()
+}
+
+// the osm ids at both ends of an edge going through the snapped point (OSRM gives the segment's).
+// a node is the start of any of its edges, a node without edges has none
+fn segment_nodes(engine: &Engine, snapped: &Snapped) -> Option<[i64; 2]> {
+    let graph = engine.graph();
+    let (from, to) = match snapped.at {
+        SnappedAt::Node(node) => (node, graph.adjacent_edges(node)?.first()?.to_node),
+        SnappedAt::ShapePoint(position) => (position.from_node, position.to_node),
+    };
+    Some([graph.get_osm_id(from)?.0, graph.get_osm_id(to)?.0])
+}
+
+fn snap(engine: &Engine, location: LatLon) -> Result<Snapped, OsrmError> {
+    engine
+        .snap(location)
+        .map_err(|_| OsrmError::new("NoSegment", "Could not find a matching segment for any coordinate"))
+}
+
+fn step_json(step: &Step, options: &GeometryOptions) -> Value {
+    let m = &step.maneuver;
+    let location = [m.location.lon, m.location.lat];
+    let mut maneuver = json!({
+        "location": location,
+        "bearing_before": m.bearing_before.round(),
+        "bearing_after": m.bearing_after.round(),
+        "type": m.kind.as_str(),
+    });
+    if let Some(modifier) = m.modifier {
+        maneuver["modifier"] = json!(modifier.as_str());
+    }
+
+    let intersection = match m.kind {
+        ManeuverType::Depart => json!({ "location": location, "bearings": [m.bearing_after.round()], "entry": [true], "out": 0 }),
+        ManeuverType::Arrive => json!({ "location": location, "bearings": [((m.bearing_before + 180.0) % 360.0).round()], "entry": [true], "in": 0 }),
+        _ => json!({
+            "location": location,
+            "bearings": [((m.bearing_before + 180.0) % 360.0).round(), m.bearing_after.round()],
+            "entry": [true, true],
+            "in": 0,
+            "out": 1,
+        }),
+    };
+
+    json!({
+        "distance": step.distance,
+        "duration": step.duration,
+        "weight": step.distance,
+        "name": step.name,
+        "mode": "driving",
+        "driving_side": "right",
+        "geometry": options.encode(&step.geometry),
+        "maneuver": maneuver,
+        "intersections": [intersection],
+        "instruction": step.instruction(),
+    })
+}
+
+fn leg_json(engine: &Engine, leg: &RouteResult, options: &GeometryOptions) -> Value {
+    let graph = engine.graph();
+    let segments = &leg.segments;
+
+    // the two longest roads, like OSRM
+    let mut by_name: HashMap<&str, f64> = HashMap::new();
+    for s in segments {
+        *by_name.entry(graph.name(s.name)).or_insert(0.0) += s.distance;
+    }
+    let mut names: Vec<(&str, f64)> = by_name.into_iter().filter(|(n, _)| !n.is_empty()).collect();
+    names.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let summary = names.iter().take(2).map(|(n, _)| *n).collect::<Vec<_>>().join(", ");
+
+    let steps: Vec<Value> = if options.steps {
+        engine.steps(leg).iter().map(|s| step_json(s, options)).collect()
+    } else {
+        vec![]
+    };
+
+    let mut result = json!({
+        "distance": leg.total_distance,
+        "duration": leg.total_duration,
+        "weight": leg.total_distance,
+        "summary": summary,
+        "steps": steps,
+    });
+
+    if !options.annotations.is_empty() {
+        let mut annotation = json!({});
+        for a in &options.annotations {
+            annotation[*a] = match *a {
+                "nodes" => {
+                    // every osm node, the segments share their ends
+                    let mut nodes: Vec<i64> = Vec::new();
+                    for s in segments {
+                        let skip = if nodes.is_empty() { 0 } else { 1 };
+                        nodes.extend(s.nodes.iter().skip(skip).map(|n| n.id.0));
+                    }
+                    json!(nodes)
+                }
+                "distance" => json!(segments.iter().map(|s| s.distance).collect::<Vec<_>>()),
+                "duration" => json!(segments.iter().map(|s| s.duration).collect::<Vec<_>>()),
+                _ => json!(segments.iter().map(|s| s.distance / s.duration).collect::<Vec<_>>()), // speed, m/s
+            };
+        }
+        result["annotation"] = annotation;
+    }
+    result
+}
+
+// legs -> one OSRM route object
+fn route_json(engine: &Engine, legs: &[RouteResult], options: &GeometryOptions) -> Value {
+    let distance: f64 = legs.iter().map(|l| l.total_distance).sum();
+    let duration: f64 = legs.iter().map(|l| l.total_duration).sum();
+
+    let mut route = json!({
+        "distance": distance,
+        "duration": duration,
+        "weight": distance,
+        "weight_name": "distance", // we route on the shortest distance
+        "legs": legs.iter().map(|l| leg_json(engine, l, options)).collect::<Vec<_>>(),
+    });
+
+    if options.overview != "false" {
+        // the end of a leg is the start of the next one
+        let mut points: Vec<LatLon> = Vec::new();
+        for leg in legs {
+            let skip = if points.is_empty() { 0 } else { 1 };
+            points.extend(leg.route_path.iter().skip(skip));
+        }
+        if options.overview == "simplified" {
+            let line: LineString = points.iter().map(|p| (p.lon, p.lat)).collect();
+            points = line
+                .simplify(&1e-5) // degrees, about a meter
+                .points()
+                .map(|p| LatLon { lat: p.y(), lon: p.x() })
+                .collect();
+        }
+        route["geometry"] = options.encode(&points);
+    }
+    route
+}
+
+async fn route(
+    Path((profile, coordinates)): Path<(String, String)>,
+    Query(options): Query<RouteOptions>,
+    Ready(dataset): Ready,
+    State(app_state): State<Arc<AppState>>,
+) -> Response {
+    let limits = app_state.limits.clone();
+    app_state
+        .queries
+        .respond(move || route_query(&profile, &coordinates, options, &dataset, &limits))
+        .await
+}
+
+// the queries run on the blocking pool, see queries.rs
+fn route_query(
+    profile: &str,
+    coordinates: &str,
+    options: RouteOptions,
+    dataset: &Dataset,
+    limits: &LimitsConfig,
+) -> Result<Json<Value>, OsrmError> {
+    let coordinates = parse_coordinates(coordinates)?;
+    if coordinates.len() < 2 {
+        return Err(OsrmError::new("InvalidOptions", "Number of coordinates needs to be at least two"));
+    }
+    if coordinates.len() > limits.max_coordinates {
+        return Err(OsrmError::new("TooBig", "Too many trace coordinates"));
+    }
+    let engine = profile_engine(dataset, profile, options.region.as_deref(), &coordinates)?;
+    if let Some(alternatives) = &options.alternatives {
+        if alternatives != "false" && alternatives.parse::<u32>().is_err() && alternatives != "true" {
+            return Err(OsrmError::new("InvalidOptions", "alternatives must be true, false or a number"));
+        }
+    }
+    let geometry_options = GeometryOptions::parse(
+        &options.geometries,
+        &options.overview,
+        options.steps,
+        &options.annotations,
+    )?;
+
+    let waypoints = coordinates
+        .iter()
+        .map(|c| snap(engine, *c))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let legs = waypoints
+        .windows(2)
+        .map(|pair| engine.routing_between(&pair[0], &pair[1]))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(OsrmError::from_engine)?;
+
+    Ok(Json(json!({
+        "code": "Ok",
+        "routes": [route_json(engine, &legs, &geometry_options)],
+        "waypoints": waypoints.iter().map(|w| waypoint(engine, w)).collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct TableOptions {
+    sources: Option<String>,      // `0;2` or `all`
+    destinations: Option<String>, // `1;3` or `all`
+    annotations: Option<String>,  // duration (default), distance, or duration,distance
+    region: Option<String>,
+}
+
+// `all` or indices into the coordinates
+fn parse_indices(input: &Option<String>, len: usize, name: &str) -> Result<Vec<usize>, OsrmError> {
+    match input.as_deref() {
+        None | Some("all") => Ok((0..len).collect()),
+        Some(list) => list
+            .split(';')
+            .map(|i| match i.parse::<usize>() {
+                Ok(i) if i < len => Ok(i),
+                _ => Err(OsrmError::new("InvalidOptions", format!("{} is malformed", name))),
+            })
+            .collect(),
+    }
+}
+
+async fn table(
+    Path((profile, coordinates)): Path<(String, String)>,
+    Query(options): Query<TableOptions>,
+    Ready(dataset): Ready,
+    State(app_state): State<Arc<AppState>>,
+) -> Response {
+    let limits = app_state.limits.clone();
+    app_state
+        .queries
+        .respond(move || table_query(&profile, &coordinates, options, &dataset, &limits))
+        .await
+}
+
+fn table_query(
+    profile: &str,
+    coordinates: &str,
+    options: TableOptions,
+    dataset: &Dataset,
+    limits: &LimitsConfig,
+) -> Result<Json<Value>, OsrmError> {
+    let coordinates = parse_coordinates(coordinates)?;
+    if coordinates.len() > limits.max_table_size {
+        return Err(OsrmError::new("TooBig", "Too many table coordinates"));
+    }
+    let engine = profile_engine(dataset, profile, options.region.as_deref(), &coordinates)?;
+    let sources = parse_indices(&options.sources, coordinates.len(), "sources")?;
+    let destinations = parse_indices(&options.destinations, coordinates.len(), "destinations")?;
+
+    let annotations: Vec<&str> = options.annotations.as_deref().unwrap_or("duration").split(',').collect();
+    if annotations.iter().any(|a| !matches!(*a, "duration" | "distance")) {
+        return Err(OsrmError::new("InvalidOptions", "annotations must be duration and/or distance"));
+    }
+
+    // each coordinate is snapped once, for the matrix and the waypoints
+    let mut snapped: Vec<Option<Snapped>> = vec![None; coordinates.len()];
+    for i in sources.iter().chain(&destinations) {
+        if snapped[*i].is_none() {
+            snapped[*i] = Some(snap(engine, coordinates[*i])?);
+        }
+    }
+    let pick = |indices: &[usize]| indices.iter().filter_map(|i| snapped[*i]).collect::<Vec<_>>();
+    let (sources, destinations) = (pick(&sources), pick(&destinations));
+    let matrix = engine.table_between(&sources, &destinations);
+
+    let waypoints = |points: &[Snapped]| points.iter().map(|s| waypoint(engine, s)).collect::<Vec<_>>();
+    let mut response = json!({
+        "code": "Ok",
+        "sources": waypoints(&sources),
+        "destinations": waypoints(&destinations),
+    });
+    // null when there is no route
+    if annotations.contains(&"duration") {
+        response["durations"] = json!(matrix
+            .iter()
+            .map(|row| row.iter().map(|c| c.map(|(_, duration)| duration)).collect::<Vec<_>>())
+            .collect::<Vec<_>>());
+    }
+    if annotations.contains(&"distance") {
+        response["distances"] = json!(matrix
+            .iter()
+            .map(|row| row.iter().map(|c| c.map(|(distance, _)| distance)).collect::<Vec<_>>())
+            .collect::<Vec<_>>());
+    }
+    Ok(Json(response))
+}
+
+#[derive(Debug, Deserialize)]
+struct NearestOptions {
+    number: Option<usize>,
+    region: Option<String>,
+}
+
+async fn nearest(
+    Path((profile, coordinates)): Path<(String, String)>,
+    Query(options): Query<NearestOptions>,
+    Ready(dataset): Ready,
+    State(app_state): State<Arc<AppState>>,
+) -> Response {
+    let limits = app_state.limits.clone();
+    app_state
+        .queries
+        .respond(move || nearest_query(&profile, &coordinates, options, &dataset, &limits))
+        .await
+}
+
+fn nearest_query(
+    profile: &str,
+    coordinates: &str,
+    options: NearestOptions,
+    dataset: &Dataset,
+    limits: &LimitsConfig,
+) -> Result<Json<Value>, OsrmError> {
+    let coordinates = parse_coordinates(coordinates)?;
+    if coordinates.len() != 1 {
+        return Err(OsrmError::new("InvalidOptions", "Only one input coordinate is supported"));
+    }
+    let engine = profile_engine(dataset, profile, options.region.as_deref(), &coordinates)?;
+    let number = options.number.unwrap_or(1);
+    if number == 0 || number > limits.max_nearest {
+        return Err(OsrmError::new(
+            "InvalidOptions",
+            format!("Number of results must be between 1 and {}", limits.max_nearest),
+        ));
+    }
+
+    let found = engine.nearest(coordinates[0], number);
+    if found.is_empty() {
+        return Err(OsrmError::new("NoSegment", "Could not find a matching segment for coordinate"));
+    }
+
+    let waypoints: Vec<Waypoint> = found
+        .iter()
+        .map(|s| Waypoint {
+            nodes: segment_nodes(engine, s),
+            ..waypoint(engine, s)
+        })
+        .collect();
+    Ok(Json(json!({ "code": "Ok", "waypoints": waypoints })))
+}
+
+#[derive(Debug, Deserialize)]
+struct MatchOptions {
+    steps: Option<bool>,
+    annotations: Option<String>,
+    geometries: Option<String>,
+    overview: Option<String>,
+    radiuses: Option<String>,   // gps accuracy of each point, meters
+    timestamps: Option<String>, // accepted, only the number of items is checked
+    region: Option<String>,
+}
+
+async fn matching(
+    Path((profile, coordinates)): Path<(String, String)>,
+    Query(options): Query<MatchOptions>,
+    Ready(dataset): Ready,
+    State(app_state): State<Arc<AppState>>,
+) -> Response {
+    let limits = app_state.limits.clone();
+    app_state
+        .queries
+        .respond(move || match_query(&profile, &coordinates, options, &dataset, &limits))
+        .await
+}
+
+fn match_query(
+    profile: &str,
+    coordinates: &str,
+    options: MatchOptions,
+    dataset: &Dataset,
+    limits: &LimitsConfig,
+) -> Result<Json<Value>, OsrmError> {
+    let trace = parse_coordinates(coordinates)?;
+    if trace.len() < 2 {
+        return Err(OsrmError::new("InvalidOptions", "Number of coordinates needs to be at least two"));
+    }
+    if trace.len() > limits.max_coordinates {
+        return Err(OsrmError::new("TooBig", "Too many trace coordinates"));
+    }
+    let radiuses: Vec<Option<f64>> = parse_list(&options.radiuses, "radiuses")?;
+    let timestamps: Vec<Option<i64>> = parse_list(&options.timestamps, "timestamps")?;
+    if (!radiuses.is_empty() && radiuses.len() != trace.len())
+        || (!timestamps.is_empty() && timestamps.len() != trace.len())
+    {
+        return Err(OsrmError::new("InvalidOptions", "Number of radiuses/timestamps does not match number of coordinates"));
+    }
+    check_radiuses(&radiuses, limits.max_radius)?;
+    let engine = profile_engine(dataset, profile, options.region.as_deref(), &trace)?;
+    let geometry_options = GeometryOptions::parse(
+        &options.geometries,
+        &options.overview,
+        options.steps,
+        &options.annotations,
+    )?;
+
+    // an empty radius means the default accuracy
+    let accuracies: Vec<f64> = radiuses.iter().map(|r| r.unwrap_or(10.0)).collect();
+    let matchings = engine
+        .map_match(&trace, &accuracies)
+        .map_err(OsrmError::from_engine)?;
+    if matchings.is_empty() {
+        return Err(OsrmError::new("NoMatch", "Could not match the trace."));
+    }
+
+    let mut tracepoints: Vec<Value> = vec![Value::Null; trace.len()];
+    for (matching_index, m) in matchings.iter().enumerate() {
+        for (waypoint_index, p) in m.points.iter().enumerate() {
+            let w = waypoint(engine, &p.snapped);
+            tracepoints[p.trace_index] = json!({
+                "hint": w.hint,
+                "distance": w.distance,
+                "name": w.name,
+                "location": w.location,
+                "matchings_index": matching_index,
+                "waypoint_index": waypoint_index,
+                "alternatives_count": 0,
+            });
+        }
+    }
+
+    let matchings: Vec<Value> = matchings
+        .iter()
+        .map(|m| {
+            let mut route = route_json(engine, &m.legs, &geometry_options);
+            route["confidence"] = json!(m.confidence);
+            route
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "code": "Ok",
+        "matchings": matchings,
+        "tracepoints": tracepoints,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use crate::{graph::Graph, osm};
+
+    // Main Street north from 1 to 5, then Oak Avenue east to 7: a right turn at 5
+    fn test_app() -> Router {
+        let main: Vec<_> = (1..=5).map(|i| (i, 38.0 + (i - 1) as f64 * 0.001, -75.0)).collect();
+        let (mut nodes, main) = osm::street("Main Street", &main);
+        let (oak_nodes, oak) = osm::street("Oak Avenue", &[(5, 38.004, -75.0), (6, 38.004, -74.9987), (7, 38.004, -74.9974)]);
+        nodes.extend(oak_nodes);
+        let mut dataset = Dataset::new("car");
+        dataset.add("default", "car", Engine::from_graph(Graph::build(nodes, vec![main, oak])), "test");
+
+        let state = AppState::for_tests(Arc::new(|| Err("no reload in this test".to_string())));
+        state.set_dataset(dataset);
+        routes().with_state(state)
+    }
+
+    async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+        let response = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        let c = parse_coordinates("-75.1,38.5;-75.2,38.6.json").unwrap();
+        assert_eq!(c, vec![LatLon { lat: 38.5, lon: -75.1 }, LatLon { lat: 38.6, lon: -75.2 }]);
+
+        let c = parse_coordinates("polyline(_p~iF~ps|U_ulLnnqC)").unwrap();
+        assert_eq!(c[1], LatLon { lat: 40.7, lon: -120.95 });
+
+        assert_eq!(parse_coordinates("1,2;3").unwrap_err().code, "InvalidQuery");
+        assert_eq!(parse_coordinates("1,200").unwrap_err().code, "InvalidValue");
+    }
+
+    #[test]
+    fn test_parse_lists() {
+        let r: Vec<Option<f64>> = parse_list(&Some("5;;unlimited".to_string()), "radiuses").unwrap();
+        assert_eq!(r, vec![Some(5.0), None, None]);
+        assert!(check_radiuses(&r, 1000.0).is_ok());
+        for bad in ["0", "-5", "NaN", "inf", "1e9"] {
+            let r: Vec<Option<f64>> = parse_list(&Some(bad.to_string()), "radiuses").unwrap();
+            assert!(check_radiuses(&r, 1000.0).is_err(), "{}", bad);
+        }
+        assert_eq!(parse_indices(&Some("0;2".to_string()), 3, "sources").unwrap(), vec![0, 2]);
+        assert!(parse_indices(&Some("3".to_string()), 3, "sources").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let app = test_app();
+        let (status, body) = get_json(
+            &app,
+            "/route/v1/driving/-75.0,38.0;-74.9974,38.004?steps=true&annotations=nodes,distance&geometries=geojson",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["code"], "Ok");
+        let route = &body["routes"][0];
+        // 4 * 111 m then 2 * 114 m
+        assert!((route["distance"].as_f64().unwrap() - 673.0).abs() < 5.0);
+        assert_eq!(route["geometry"]["type"], "LineString");
+        let leg = &route["legs"][0];
+        assert_eq!(leg["summary"], "Main Street, Oak Avenue");
+        assert_eq!(leg["annotation"]["nodes"], json!([1, 2, 3, 4, 5, 6, 7]));
+        let steps = leg["steps"].as_array().unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[1]["maneuver"]["modifier"], "right");
+        assert_eq!(steps[1]["name"], "Oak Avenue");
+        let names: Vec<&Value> = body["waypoints"].as_array().unwrap().iter().map(|w| &w["name"]).collect();
+        assert_eq!(names, vec!["Main Street", "Oak Avenue"]);
+
+        for (uri, code) in [
+            ("/route/v1/driving/-75.0,38.0", "InvalidOptions"),
+            ("/route/v1/driving/-75.0,38.0;-75.0,200", "InvalidValue"),
+            ("/route/v1/driving/-75.0,38.0;nowhere", "InvalidQuery"),
+            ("/route/v1/driving/-75.0,38.0;-75.0,38.002?geometries=svg", "InvalidOptions"),
+            ("/route/v1/driving/-75.0,38.0;-75.0,38.002?region=mars", "InvalidOptions"),
+        ] {
+            let (status, body) = get_json(&app, uri).await;
+            assert_eq!((status, body["code"].as_str().unwrap()), (StatusCode::BAD_REQUEST, code), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table() {
+        let app = test_app();
+        let (status, body) = get_json(
+            &app,
+            "/table/v1/driving/-75.0,38.0;-75.0,38.002;-74.9974,38.004?sources=0&annotations=distance,duration",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["sources"].as_array().unwrap().len(), 1);
+        assert_eq!(body["destinations"].as_array().unwrap().len(), 3);
+        assert_eq!(body["destinations"][1]["location"], json!([-75.0, 38.002]));
+        let distances = body["distances"][0].as_array().unwrap();
+        assert_eq!(distances[0], 0.0);
+        assert!((distances[1].as_f64().unwrap() - 222.4).abs() < 1.0);
+        assert_eq!(body["durations"][0].as_array().unwrap().len(), 3);
+
+        let (status, body) = get_json(&app, "/table/v1/driving/-75.0,38.0;-75.0,38.002?sources=2").await;
+        assert_eq!((status, body["code"].as_str().unwrap()), (StatusCode::BAD_REQUEST, "InvalidOptions"));
+        let (_, body) = get_json(&app, "/table/v1/driving/-75.0,38.0?annotations=speed").await;
+        assert_eq!(body["code"], "InvalidOptions");
+    }
+
+    #[tokio::test]
+    async fn test_nearest() {
+        let app = test_app();
+        let (status, body) = get_json(&app, "/nearest/v1/driving/-75.0001,38.002?number=2").await;
+        assert_eq!(status, StatusCode::OK);
+        let waypoints = body["waypoints"].as_array().unwrap();
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0]["location"], json!([-75.0, 38.002]));
+        assert_eq!(waypoints[0]["name"], "Main Street");
+        // 3 is on the edge between 1 and 5, the ends of its edge are real osm nodes
+        let mut ends: Vec<i64> = serde_json::from_value(waypoints[0]["nodes"].clone()).unwrap();
+        ends.sort();
+        assert_eq!(ends, vec![1, 5]);
+
+        let (status, body) = get_json(&app, "/nearest/v1/driving/-75.0001,38.002?number=0").await;
+        assert_eq!((status, body["code"].as_str().unwrap()), (StatusCode::BAD_REQUEST, "InvalidOptions"));
+        let (_, body) = get_json(&app, "/nearest/v1/driving/-75.0,38.0;-75.0,38.002").await;
+        assert_eq!(body["code"], "InvalidOptions");
+    }
+
+    #[tokio::test]
+    async fn test_match() {
+        let app = test_app();
+        // a few meters off Main Street, then on Oak Avenue
+        let trace = "-74.99992,38.0;-75.00008,38.00105;-74.99993,38.00406;-74.99871,38.00393";
+        let (status, body) = get_json(&app, &format!("/match/v1/driving/{}?radiuses=5;5;5;5", trace)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["code"], "Ok");
+        let matching = &body["matchings"][0];
+        assert!(matching["confidence"].as_f64().unwrap() > 0.0);
+        assert_eq!(matching["legs"].as_array().unwrap().len(), 3);
+        let tracepoints = body["tracepoints"].as_array().unwrap();
+        let indices: Vec<&Value> = tracepoints.iter().map(|t| &t["waypoint_index"]).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+        assert_eq!(tracepoints[1]["location"], json!([-75.0, 38.001]));
+
+        let (_, body) = get_json(&app, &format!("/match/v1/driving/{}?radiuses=5;5", trace)).await;
+        assert_eq!(body["code"], "InvalidOptions");
+        let (status, body) = get_json(&app, "/match/v1/driving/-70.0,30.0;-70.0,30.001").await;
+        assert_eq!((status, body["code"].as_str().unwrap()), (StatusCode::BAD_REQUEST, "NoMatch"));
+    }
+}
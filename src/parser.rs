@@ -1,14 +1,26 @@
 use std::{
-    collections::{HashMap, HashSet},
-    error::{self, Error},
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
     fmt::Debug,
-    ptr::read,
+    time::Instant,
 };
 
-use crate::{graph::{Edge, Graph, LatLon, Node}, osm, spatialindex::{NodeLocation, SpatialIndex}};
-use geo::{Distance, Haversine, Point};
-use osmpbf::{Element, ElementReader};
-use rstar::{primitives::GeomWithData, RTree};
+use crate::{
+    clip::Clip,
+    conditional::parse_conditional_restriction,
+    config::{DistanceMetric, ImportConfig, ProfileConfig},
+    graph::{Graph, LatLon, Node},
+    input::{ElementSource, Format, OsmElement},
+    o5m::O5mSource,
+    osm,
+    osmxml::XmlSource,
+    report::{ImportReport, LongEdge, LONG_EDGE},
+    spatialindex::SpatialIndex,
+};
+use geo::{Distance, Geodesic, Haversine, Point, VincentyDistance};
+use osmpbf::{Blob, BlobDecode, BlobReader, Element, ElementReader};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use rstar::primitives::GeomWithData;
 
 pub struct HighlevelError;
 
@@ -16,12 +28,14 @@ pub struct HighlevelError;
 pub enum ParseError {
     Simple,
     OSMPBFError(osmpbf::Error),
+    Io(std::io::Error),
+    Invalid(String), // an unknown format, a malformed xml or o5m file
 }
 
 // correct usage for From
 // when you do this, the compiler will convert ParseError to HighlevelError automatically in ? context.
 impl From<ParseError> for HighlevelError {
-    fn from(value: ParseError) -> Self {
+    fn from(_value: ParseError) -> Self {
         HighlevelError
     }
 }
@@ -31,6 +45,8 @@ impl std::fmt::Display for ParseError {
         match self {
             ParseError::Simple => f.write_str("some simple error"),
             ParseError::OSMPBFError(e) => f.write_str(&format!("underlying osmbpf error: {}", e)),
+            ParseError::Io(e) => f.write_str(&format!("can't read the file: {}", e)),
+            ParseError::Invalid(e) => f.write_str(e),
         }
     }
 }
@@ -43,116 +59,440 @@ impl From<osmpbf::Error> for ParseError {
     }
 }
 
+impl From<std::io::Error> for ParseError {
+    fn from(value: std::io::Error) -> Self {
+        ParseError::Io(value)
+    }
+}
+
 // external type
 
 
 // our defined type
 // newtype pattern
-pub struct NodeLocation2(GeomWithData<[f64; 2], osm::NodeID>);
+pub struct NodeLocation2(pub GeomWithData<[f64; 2], osm::NodeID>);
+
+// "50" (km/h), "30 mph", "none" (german autobahn)... -> km/h
+pub fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(mph) = value.strip_suffix("mph") {
+        return mph.trim().parse::<f64>().ok().filter(|s| *s > 0.0 && s.is_finite()).map(|s| s * 1.609344);
+    }
+    value.parse::<f64>().ok().filter(|s| *s > 0.0 && s.is_finite())
+}
 
+// with the default import settings and speeds
 pub fn parse_map(map_file: &str) -> Result<(Graph, SpatialIndex), ParseError> {
-    let mut all_nodes: HashMap<osm::NodeID, Node> = HashMap::new();
-    let mut adj_edges: HashMap<osm::NodeID, Vec<Edge>> = HashMap::new();
-    let ways: HashMap<i64, Vec<i64>> = HashMap::new();
-    let mut node_count = 0;
-
-    // 2 pass of pbf parse
-    // 1st pass is to collect all locations of nodes
-    // 2nd pass : generate edges from ways
-
-    let reader = ElementReader::from_path(map_file).unwrap();
-    reader.for_each(|element| match element {
-        Element::DenseNode(node) => {
-            let node_object = Node {
-                id: osm::NodeID(node.id()),
-                location: LatLon {
-                    lat: node.lat(),
-                    lon: node.lon(),
-                },
+    parse_map_with(map_file, &ImportConfig::default(), &ProfileConfig::default())
+}
+
+// .osm.pbf, .osm (XML) or .o5m
+pub fn parse_map_with(map_file: &str, import: &ImportConfig, profile: &ProfileConfig) -> Result<(Graph, SpatialIndex), ParseError> {
+    parse_maps_with(&[map_file], import, profile)
+}
+
+// several extracts (e.g. neighbouring states) into one graph. The nodes and ways in more than
+// one of them (along the borders) are only imported once, by their osm id.
+pub fn parse_maps_with(
+    map_files: &[&str],
+    import: &ImportConfig,
+    profile: &ProfileConfig,
+) -> Result<(Graph, SpatialIndex), ParseError> {
+    let clip = Clip::from_config(&import.clip)?; // before reading, a bad polygon fails early
+    build_graph(&read_roads_with(map_files, import, profile)?, clip.as_ref(), import.distance)
+}
+
+// a source which isn't a file, e.g. the xml of a test
+pub fn parse_elements(
+    source: &dyn ElementSource,
+    import: &ImportConfig,
+    profile: &ProfileConfig,
+) -> Result<(Graph, SpatialIndex), ParseError> {
+    let clip = Clip::from_config(&import.clip)?;
+    build_graph(&read_inputs(&[Input::Elements(source)], import, profile)?, clip.as_ref(), import.distance)
+}
+
+// the road ways of an import and their nodes, what the graph is built from.
+// A prepared graph keeps it to apply change files without the extracts (see changes.rs).
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Roads {
+    pub ways: BTreeMap<i64, osm::Way>,     // by osm id (the same order whatever the inputs)
+    pub nodes: HashMap<osm::NodeID, Node>, // the nodes they reference, if the extracts have them
+    #[serde(skip)]
+    pub report: ImportReport, // what the reading skipped, build_graph_reported adds the rest
+}
+
+pub fn read_roads_with(map_files: &[&str], import: &ImportConfig, profile: &ProfileConfig) -> Result<Roads, ParseError> {
+    let _span = tracing::info_span!("parse_map", files = map_files.join(",")).entered();
+    // pbf is read by the parser itself, in parallel
+    let mut sources: Vec<Option<Box<dyn ElementSource>>> = Vec::new();
+    for map_file in map_files {
+        sources.push(match Format::detect(map_file)? {
+            Format::Pbf => None,
+            Format::Xml => Some(Box::new(XmlSource::from_path(map_file))),
+            Format::O5m => Some(Box::new(O5mSource::from_path(map_file))),
+        });
+    }
+    let inputs: Vec<Input> = map_files
+        .iter()
+        .zip(&sources)
+        .map(|(map_file, source)| match source {
+            Some(source) => Input::Elements(source.as_ref()),
+            None => Input::Pbf(map_file),
+        })
+        .collect();
+    read_inputs(&inputs, import, profile)
+}
+
+enum Input<'a> {
+    Pbf(&'a str), // read in parallel over its blobs
+    Elements(&'a dyn ElementSource),
+}
+
+fn read_inputs(inputs: &[Input], import: &ImportConfig, profile: &ProfileConfig) -> Result<Roads, ParseError> {
+    // 2 passes over every input
+    // 1st pass: the ways we route on, and so the ids of the nodes they reference
+    // 2nd pass: the locations of those nodes only, the rest of the extract (buildings, ...) is skipped
+    // so the memory depends on the size of the road network, not of the whole file.
+    let phase = tracing::info_span!("read_ways").entered();
+    let started = Instant::now();
+    let mut report = ImportReport::default();
+    let mut ways: BTreeMap<i64, osm::Way> = BTreeMap::new();
+    for input in inputs {
+        let read = match input {
+            Input::Pbf(map_file) => pbf_ways(map_file, import, profile)?,
+            Input::Elements(source) => source_ways(*source, import, profile)?,
+        };
+        report.ways_read += read.total;
+        report.dropped_ways.excluded += read.excluded;
+        report.dropped_ways.not_routable += read.not_routable;
+        for (value, count) in read.unknown_highways {
+            *report.unknown_highways.entry(value).or_default() += count;
+        }
+        for (id, way) in read.ways {
+            match ways.entry(id) {
+                Entry::Vacant(e) => {
+                    e.insert(way);
+                }
+                Entry::Occupied(_) => report.dropped_ways.duplicates += 1,
+            }
+        }
+    }
+    let referenced = referenced_nodes(&ways);
+    tracing::info!(
+        ways = report.ways_read,
+        used_ways = ways.len(),
+        duplicates = report.dropped_ways.duplicates,
+        referenced_nodes = referenced.len(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "ways read"
+    );
+    drop(phase);
+
+    let phase = tracing::info_span!("read_nodes").entered();
+    let started = Instant::now();
+    let mut nodes: HashMap<osm::NodeID, Node> = HashMap::with_capacity(referenced.len());
+    for input in inputs {
+        let read = match input {
+            Input::Pbf(map_file) => pbf_nodes(map_file, &referenced)?,
+            Input::Elements(source) => source_nodes(*source, &referenced)?,
+        };
+        // the first extract wins, they should agree anyway
+        for node in read {
+            nodes.entry(node.id).or_insert(node);
+        }
+    }
+    tracing::info!(nodes = nodes.len(), elapsed_ms = started.elapsed().as_millis() as u64, "nodes read");
+    drop(phase);
+
+    Ok(Roads { ways, nodes, report })
+}
+
+pub(crate) fn referenced_nodes(ways: &BTreeMap<i64, osm::Way>) -> HashSet<osm::NodeID> {
+    ways.values().flat_map(|w| w.nodes.iter().copied()).collect()
+}
+
+fn pbf_ways(map_file: &str, import: &ImportConfig, profile: &ProfileConfig) -> Result<RoadWays, ParseError> {
+    let ways = ElementReader::from_path(map_file)?.par_map_reduce(
+        |element| match element {
+            Element::Way(way) => {
+                let mut ways = RoadWays::default();
+                ways.add(way.id(), way.tags(), way.refs(), import, profile);
+                ways
+            }
+            _ => RoadWays::default(),
+        },
+        RoadWays::default,
+        RoadWays::merge,
+    )?;
+    Ok(ways)
+}
+
+fn pbf_nodes(map_file: &str, referenced: &HashSet<osm::NodeID>) -> Result<Vec<Node>, ParseError> {
+    // one vector per blob (thousands of nodes), not one per node
+    let blob_nodes = |blob: Result<Blob, osmpbf::Error>| -> Result<Vec<Node>, osmpbf::Error> {
+        let block = match blob?.decode()? {
+            BlobDecode::OsmData(block) => block,
+            _ => return Ok(Vec::new()), // the header
+        };
+        let mut nodes = Vec::new();
+        for element in block.elements() {
+            let (id, lat, lon) = match element {
+                Element::DenseNode(n) => (n.id(), n.lat(), n.lon()),
+                // small extracts (e.g. exported from JOSM) don't always use dense nodes
+                Element::Node(n) => (n.id(), n.lat(), n.lon()),
+                _ => continue,
             };
+            let id = osm::NodeID(id);
+            if referenced.contains(&id) {
+                nodes.push(Node { id, location: LatLon { lat, lon } });
+            }
+        }
+        Ok(nodes)
+    };
+    let nodes = BlobReader::from_path(map_file)?
+        .par_bridge()
+        .map(blob_nodes)
+        .try_reduce(Vec::new, |a, b| Ok(concat(a, b)))?;
+    Ok(nodes)
+}
 
-            all_nodes.insert(node_object.id, node_object);
+fn source_ways(source: &dyn ElementSource, import: &ImportConfig, profile: &ProfileConfig) -> Result<RoadWays, ParseError> {
+    let mut ways = RoadWays::default();
+    source.for_each(&mut |element| {
+        if let OsmElement::Way(way) = element {
+            ways.add(way.id, way.tags(), way.nodes.iter().map(|n| n.0), import, profile);
         }
+    })?;
+    Ok(ways)
+}
 
-        _ => {}
+fn source_nodes(source: &dyn ElementSource, referenced: &HashSet<osm::NodeID>) -> Result<Vec<Node>, ParseError> {
+    let mut nodes = Vec::new();
+    source.for_each(&mut |element| {
+        if let OsmElement::Node(node) = element {
+            if referenced.contains(&node.id) {
+                nodes.push(node);
+            }
+        }
     })?;
+    Ok(nodes)
+}
 
-    let mut all_way_count = 0;
-    let mut highway_count = 0;
-    let mut used_node_ids = HashSet::new(); // only care about node id
-    let mut used_ways:Vec<osm::Way> = Vec::new();
-
-    let reader2 = ElementReader::from_path(map_file).unwrap();
-    reader2
-        .for_each(|element| {
-            match element {
-                Element::Way(way) => {
-                    all_way_count += 1;
-                    // quality of osm map data is not very high. sometime, because road properties (wrong tags) will cause the map divied into muliple parts.
-
-                    // filter out all ways without "highway" tag
-                    if way.tags().find(|(k, _)| *k == "highway").is_none() {
-                        return; // skip this way
-                    }
-
-                    highway_count += 1;
-
-                    // a -> b -> c -> d
-                    //   d1   d2   d3
-                    let mut distances = Vec::new(); // distance for each concecuitive pair of nodes in this way
-
-                    let all_way_nodes: Vec<_> = way.refs().map(|node_id| osm::NodeID(node_id)).collect();
-                    for curr in 0..all_way_nodes.len() - 1 {
-                        let next = curr + 1;
-                        let curr_node_id = all_way_nodes[curr];
-                        let next_node_id = all_way_nodes[next];
-
-                        let (from_location, to_location) =
-                            match (all_nodes.get(&curr_node_id), all_nodes.get(&next_node_id)) {
-                                (Some(from), Some(to)) => (from, to),
-                                _ => return,
-                            };
-
-                        // let from_location = all_nodes.get(&curr_node_id).unwrap(); // risk!
-                        // let to_location = all_nodes.get(&next_node_id).unwrap(); //
-
-                        let from_point =
-                            Point::new(from_location.location.lon, from_location.location.lat);
-                        let to_point =
-                            Point::new(to_location.location.lon, from_location.location.lat);
-                        let distance = Haversine::distance(from_point, to_point);
-
-                        distances.push(distance);
-                    }
-
-                    for n in way.refs() {
-                        used_node_ids.insert(osm::NodeID (n));
-                    }
-
-                    used_ways.push(osm::Way {
-                        nodes: way.refs().map(|n| osm::NodeID(n)).collect(),
-                        distances,
-                    });
+// the graph of the road ways, only inside `clip` if there's one
+pub fn build_graph(roads: &Roads, clip: Option<&Clip>, metric: DistanceMetric) -> Result<(Graph, SpatialIndex), ParseError> {
+    let (graph, tree, _) = build_graph_reported(roads, clip, metric)?;
+    Ok((graph, tree))
+}
+
+// build_graph with the report of the import: roads.report, what the building drops and what
+// looks wrong in the graph. the roads are only read (not copied), the engine may keep them.
+pub fn build_graph_reported(
+    roads: &Roads,
+    clip: Option<&Clip>,
+    metric: DistanceMetric,
+) -> Result<(Graph, SpatialIndex, ImportReport), ParseError> {
+    let _phase = tracing::info_span!("build_graph").entered();
+    let started = Instant::now();
+    let Roads { ways, nodes: all_nodes, report } = roads;
+    let mut report = report.clone();
+
+    let clipped: Vec<osm::Way>;
+    let ways: Vec<&osm::Way> = match clip {
+        Some(clip) => {
+            let mut parts = Vec::with_capacity(ways.len());
+            for way in ways.values() {
+                let inside = clip.way(way, all_nodes);
+                if inside.is_empty() {
+                    report.dropped_ways.outside_clip += 1;
                 }
+                parts.extend(inside);
+            }
+            tracing::info!(before = ways.len(), after = parts.len(), keep_border_ways = clip.keep_border_ways, "ways clipped");
+            clipped = parts;
+            clipped.iter().collect()
+        }
+        None => ways.values().collect(),
+    };
+
+    // ways with nodes missing from the extract (cut at its border, or a truncated file) are cut
+    // around them, and dropped if no 2 consecutive nodes are left
+    let mut missing = HashSet::new();
+    let mut used_ways: Vec<osm::MeasuredWay> = Vec::with_capacity(ways.len());
+    let locate = |id: osm::NodeID| all_nodes.get(&id).map(|n| n.location);
+    for way in ways {
+        let absent: Vec<i64> = way.nodes.iter().filter(|n| !all_nodes.contains_key(n)).map(|n| n.0).collect();
+        let parts = way.measure(locate, |from, to| distance(metric, from, to));
+        match (absent.is_empty(), parts.is_empty()) {
+            (true, _) => {}
+            (false, true) => report.dropped_ways.missing_nodes += 1,
+            (false, false) => report.ways_split += 1,
+        }
+        missing.extend(absent);
+        used_ways.extend(parts);
+    }
+    let mut missing: Vec<i64> = missing.into_iter().collect();
+    missing.sort();
+    for id in missing {
+        report.missing_nodes.add(id);
+    }
+    report.ways_imported = used_ways.len();
+    for way in &used_ways {
+        for (pair, meters) in way.nodes().windows(2).zip(way.distances()) {
+            let (from, to) = (pair[0].0, pair[1].0);
+            if from == to {
+                report.duplicate_nodes.add(from);
+            } else if *meters == 0.0 {
+                report.zero_length_edges.add([from, to]);
+            } else if *meters > LONG_EDGE {
+                report.long_edges.add(LongEdge { from, to, meters: *meters });
+            }
+        }
+    }
+
+    let used_nodes: HashMap<osm::NodeID, Node> = used_ways
+        .iter()
+        .flat_map(|w| w.nodes().iter())
+        .map(|id| (*id, all_nodes[id]))
+        .collect();
+
+    let tree = SpatialIndex::build(&used_nodes);
+    let graph = Graph::build(used_nodes, used_ways);
+    report.add_graph(&graph);
+    tracing::info!(
+        nodes = graph.get_total_nodes(),
+        edges = graph.get_total_edges(),
+        islands = report.islands.count,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "graph built"
+    );
+    Ok((graph, tree, report))
+}
+
+// what the blobs of the 1st pass reduce to
+#[derive(Default)]
+struct RoadWays {
+    ways: Vec<(i64, osm::Way)>, // with their ids, without their distances yet
+    total: u64,                 // all the ways of the file
+    excluded: u64,
+    not_routable: u64,
+    unknown_highways: BTreeMap<String, u64>, // see ImportReport
+}
 
-                _ => {}
+impl RoadWays {
+    // one way of the file
+    fn add<'a>(
+        &mut self,
+        id: i64,
+        tags: impl Iterator<Item = (&'a str, &'a str)> + Clone,
+        refs: impl Iterator<Item = i64>,
+        import: &ImportConfig,
+        profile: &ProfileConfig,
+    ) {
+        self.total += 1;
+        let unknown = tags
+            .clone()
+            .find(|(k, _)| *k == import.way_tag)
+            .map(|(_, value)| value)
+            .filter(|value| !profile.speeds.contains_key(*value));
+        match road(tags, refs, import, profile) {
+            Ok(way) => self.ways.push((id, way)),
+            Err(Skipped::NotARoad) => return,
+            Err(Skipped::Excluded) => {
+                self.excluded += 1;
+                return;
             }
-        })
-        .map_err(|e| ParseError::OSMPBFError(e))?;
+            Err(Skipped::NotRoutable) => self.not_routable += 1,
+        }
+        if let Some(value) = unknown {
+            *self.unknown_highways.entry(value.to_string()).or_default() += 1;
+        }
+    }
 
-    println!("all ways: {}, highway: {}", all_way_count, highway_count);
+    fn merge(mut a: RoadWays, b: RoadWays) -> RoadWays {
+        for (value, count) in b.unknown_highways {
+            *a.unknown_highways.entry(value).or_default() += count;
+        }
+        RoadWays {
+            ways: concat(a.ways, b.ways),
+            total: a.total + b.total,
+            excluded: a.excluded + b.excluded,
+            not_routable: a.not_routable + b.not_routable,
+            unknown_highways: a.unknown_highways,
+        }
+    }
+}
+
+// appends the smaller one to the larger one
+fn concat<T>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    if a.len() < b.len() {
+        std::mem::swap(&mut a, &mut b);
+    }
+    a.append(&mut b);
+    a
+}
 
-    let used_nodes:HashMap<osm::NodeID, Node> = used_node_ids
-    .into_iter()
-    .map(|nodeId| {
-        let node = all_nodes.get(&nodeId).unwrap(); // safe!
-        (nodeId, node.clone()) // iteration step
+// why `road` skips a way
+#[derive(Debug, PartialEq)]
+pub(crate) enum Skipped {
+    NotARoad,    // no way tag (a building, a river...)
+    Excluded,    // import.exclude
+    NotRoutable, // the profile has no speed for it
+}
+
+// the way if the profile can use it
+pub(crate) fn road<'a>(
+    tags: impl Iterator<Item = (&'a str, &'a str)> + Clone,
+    refs: impl Iterator<Item = i64>,
+    import: &ImportConfig,
+    profile: &ProfileConfig,
+) -> Result<osm::Way, Skipped> {
+    // quality of osm map data is not very high. sometime, because road properties (wrong tags) will cause the map divied into muliple parts.
+
+    // filter out all ways without "highway" tag (or whatever the config says)
+    let (_, highway) = tags.clone().find(|(k, _)| *k == import.way_tag).ok_or(Skipped::NotARoad)?;
+    let excluded = tags.clone().any(|(k, v)| {
+        import.exclude.get(k).is_some_and(|values| values.iter().any(|x| x == v))
+    });
+    if excluded {
+        return Err(Skipped::Excluded);
+    }
+
+    // the profile may not be allowed on that kind of road (e.g. bicycles on a motorway)
+    let maxspeed = tags.clone().find(|(k, _)| *k == "maxspeed").and_then(|(_, v)| parse_maxspeed(v));
+    let speed = profile.speed(highway, maxspeed).ok_or(Skipped::NotRoutable)?;
+
+    // restrictions we can't understand are ignored: better a route than no route
+    let restriction = tags
+        .clone()
+        .filter(|(k, _)| matches!(*k, "access:conditional" | "vehicle:conditional" | "motor_vehicle:conditional"))
+        .find_map(|(_, v)| parse_conditional_restriction(v));
+
+    let name = tags
+        .clone()
+        .find(|(k, _)| *k == "name")
+        .or(tags.clone().find(|(k, _)| *k == "ref"))
+        .map(|(_, v)| v.to_string())
+        .unwrap_or_default();
+
+    Ok(osm::Way {
+        nodes: refs.map(osm::NodeID).collect(),
+        name,
+        speed,
+        restriction,
     })
-    .collect(); // generic
+}
 
-    let tree = SpatialIndex::build(&used_nodes);
-    let graph = Graph::build(used_nodes, used_ways);
-    Ok((graph, tree))
+// meters
+pub fn distance(metric: DistanceMetric, from: LatLon, to: LatLon) -> f64 {
+    let from = Point::new(from.lon, from.lat);
+    let to = Point::new(to.lon, to.lat);
+    match metric {
+        DistanceMetric::Haversine => Haversine::distance(from, to),
+        // vincenty doesn't converge for nearly antipodal points, not a road edge anyway
+        DistanceMetric::Vincenty => from.vincenty_distance(&to).unwrap_or_else(|_| Geodesic::distance(from, to)),
+        DistanceMetric::Geodesic => Geodesic::distance(from, to),
+    }
 }
 
 #[cfg(test)] // conditional compilation
@@ -161,7 +501,14 @@ mod tests {
 
     use crate::graph::shortest_path;
 
-    use super::parse_map;
+    use super::{build_graph, build_graph_reported, distance, parse_elements, parse_map, parse_maxspeed, read_inputs, Input};
+    use crate::{
+        config::{DistanceMetric, ImportConfig, ProfileConfig},
+        engine::Engine,
+        graph::LatLon,
+        osm,
+        osmxml::XmlSource,
+    };
 
     #[test]
     fn test_parse() {
@@ -179,6 +526,208 @@ mod tests {
         println!("Number of ways: {ways}");
     }
 
+    #[test]
+    fn test_parse_xml_fixture() {
+        // a -- b -- c, and a fence b -- d which isn't a road
+        let xml = r#"<osm version='0.6'>
+  <node id='1' lat='38.70' lon='-75.40' />
+  <node id='2' lat='38.70' lon='-75.39' />
+  <node id='3' lat='38.70' lon='-75.38' />
+  <node id='4' lat='38.71' lon='-75.39' />
+  <node id='5' lat='38.80' lon='-75.30' />
+  <way id='10'>
+    <nd ref='1' /><nd ref='2' /><nd ref='3' />
+    <tag k='highway' v='residential' /><tag k='name' v='Main Street' />
+  </way>
+  <way id='11'>
+    <nd ref='2' /><nd ref='4' />
+    <tag k='barrier' v='fence' />
+  </way>
+</osm>"#;
+        let (graph, _) = parse_elements(&XmlSource::from_string(xml), &ImportConfig::default(), &ProfileConfig::default()).unwrap();
+        // 4 is only on the fence, 5 on nothing, and b is a shape point of the edge a -- c
+        assert_eq!(graph.get_total_nodes(), 2);
+        assert_eq!(graph.get_total_shape_points(), 1);
+        let a = graph.get_node_index(osm::NodeID(1)).unwrap();
+        let c = graph.get_node_index(osm::NodeID(3)).unwrap();
+        let (_, path) = shortest_path(&graph, a, c).unwrap();
+        assert_eq!(path.len(), 2);
+        let geometry = graph.edge_geometry(a, graph.find_edge(a, c).unwrap());
+        assert_eq!(geometry.iter().map(|n| n.id.0).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_distance_metrics() {
+        let at = |lat: f64, lon: f64| LatLon { lat, lon };
+        let close = |metric, from, to, expected: f64, tolerance: f64| {
+            let d = distance(metric, from, to);
+            assert!((d - expected).abs() <= tolerance, "{:?}: {} m instead of {} m", metric, d, expected);
+        };
+        // 1 degree north from the equator: the mean radius, then the meridian of the ellipsoid
+        close(DistanceMetric::Haversine, at(0.0, 0.0), at(1.0, 0.0), 111_195.080, 0.001);
+        close(DistanceMetric::Vincenty, at(0.0, 0.0), at(1.0, 0.0), 110_574.389, 0.001);
+        close(DistanceMetric::Geodesic, at(0.0, 0.0), at(1.0, 0.0), 110_574.389, 0.001);
+        // 1 degree along the equator: the equatorial radius
+        close(DistanceMetric::Geodesic, at(0.0, 0.0), at(0.0, 1.0), 111_319.491, 0.001);
+        // Flinders Peak to Buninyong, the example of Vincenty's paper
+        let flinders = at(-(37.0 + 57.0 / 60.0 + 3.72030 / 3600.0), 144.0 + 25.0 / 60.0 + 29.52440 / 3600.0);
+        let buninyong = at(-(37.0 + 39.0 / 60.0 + 10.15610 / 3600.0), 143.0 + 55.0 / 60.0 + 35.38390 / 3600.0);
+        close(DistanceMetric::Vincenty, flinders, buninyong, 54_972.271, 0.001);
+        close(DistanceMetric::Geodesic, flinders, buninyong, 54_972.271, 0.001);
+        close(DistanceMetric::Haversine, flinders, buninyong, 54_972.271, 0.005 * 54_972.271);
+    }
+
+    #[test]
+    fn test_route_lengths() {
+        // north along a meridian from 38.70 to 38.80 (nodes 1 to 11), then east along the
+        // parallel to -75.30 (11 to 21), every 0.01 degree.
+        let mut xml = String::from("<osm version='0.6'>");
+        for i in 0..=10 {
+            xml += &format!("<node id='{}' lat='{:.2}' lon='-75.40' />", i + 1, 38.70 + i as f64 * 0.01);
+            xml += &format!("<node id='{}' lat='38.80' lon='{:.2}' />", i + 11, -75.40 + i as f64 * 0.01);
+        }
+        let refs: String = (1..=21).map(|id| format!("<nd ref='{}' />", id)).collect();
+        xml += &format!("<way id='1'>{}<tag k='highway' v='residential' /></way></osm>", refs);
+
+        // 11 is a shape point of the single edge 1 - 21
+        let location = |id: i64| match id {
+            ..=11 => LatLon { lat: 38.70 + (id - 1) as f64 * 0.01, lon: -75.40 },
+            _ => LatLon { lat: 38.80, lon: -75.40 + (id - 11) as f64 * 0.01 },
+        };
+        let route = |metric, from: i64, to: i64| {
+            let import = ImportConfig { distance: metric, ..ImportConfig::default() };
+            let (graph, _) = parse_elements(&XmlSource::from_string(xml.as_str()), &import, &ProfileConfig::default()).unwrap();
+            Engine::from_graph(graph).routing(location(from), location(to)).unwrap().total_distance
+        };
+        // references: the haversine formula, and the integral of the meridian radius of curvature
+        // and the parallel of WGS84. The edges are stored in decimeters.
+        let close = |length: f64, expected: f64| assert!((length - expected).abs() < 1.0, "{} m instead of {} m", length, expected);
+        close(route(DistanceMetric::Haversine, 1, 11), 11_119.508);
+        close(route(DistanceMetric::Haversine, 11, 21), 8_665.855);
+        close(route(DistanceMetric::Haversine, 1, 21), 19_785.363);
+        close(route(DistanceMetric::Geodesic, 1, 11), 11_101.072);
+        close(route(DistanceMetric::Geodesic, 11, 21), 8_686.975);
+        close(route(DistanceMetric::Vincenty, 1, 21), 19_788.046);
+    }
+
+    #[test]
+    fn test_merge_extracts() {
+        // two neighbouring extracts, both have the way 11 crossing their border and its nodes
+        let node = |id: i64| format!("<node id='{}' lat='38.70' lon='{}' />", id, -75.40 + id as f64 * 0.01);
+        let way = |id: i64, a: i64, b: i64| {
+            format!("<way id='{}'><nd ref='{}' /><nd ref='{}' /><tag k='highway' v='residential' /></way>", id, a, b)
+        };
+        let extract = |nodes: &[i64], ways: &[(i64, i64, i64)]| {
+            let nodes: String = nodes.iter().map(|n| node(*n)).collect();
+            let ways: String = ways.iter().map(|(id, a, b)| way(*id, *a, *b)).collect();
+            XmlSource::from_string(format!("<osm version='0.6'>{}{}</osm>", nodes, ways))
+        };
+        let west = extract(&[1, 2, 3], &[(10, 1, 2), (11, 2, 3)]);
+        let east = extract(&[2, 3, 4], &[(11, 2, 3), (12, 3, 4)]);
+        let whole = extract(&[1, 2, 3, 4], &[(10, 1, 2), (11, 2, 3), (12, 3, 4)]);
+
+        let parse = |inputs: &[Input]| {
+            let roads = read_inputs(inputs, &ImportConfig::default(), &ProfileConfig::default()).unwrap();
+            build_graph(&roads, None, DistanceMetric::Haversine).unwrap().0
+        };
+        let merged = parse(&[Input::Elements(&west), Input::Elements(&east)]);
+        let expected = parse(&[Input::Elements(&whole)]);
+        assert_eq!(merged.get_total_nodes(), 4);
+        assert_eq!(merged.get_total_edges(), expected.get_total_edges()); // way 11 only once
+        assert_eq!(merged.components(), vec![4]);
+    }
+
+    #[test]
+    fn test_truncated_extract() {
+        // the extract lost the nodes 3 and 6 (a file cut short, or the border of a region)
+        let xml = r#"<osm version='0.6'>
+  <node id='1' lat='38.70' lon='-75.40' />
+  <node id='2' lat='38.70' lon='-75.39' />
+  <node id='4' lat='38.70' lon='-75.37' />
+  <node id='5' lat='38.70' lon='-75.36' />
+  <node id='7' lat='38.71' lon='-75.36' />
+  <node id='8' lat='38.72' lon='-75.35' />
+  <way id='10'>
+    <nd ref='1' /><nd ref='2' /><nd ref='3' /><nd ref='4' /><nd ref='5' /><nd ref='6' />
+    <tag k='highway' v='residential' />
+  </way>
+  <way id='11'> <nd ref='5' /> <nd ref='7' /> <tag k='highway' v='residential' /> </way>
+  <way id='12'> <nd ref='6' /> <nd ref='8' /> <tag k='highway' v='residential' /> </way>
+</osm>"#;
+        let source = XmlSource::from_string(xml);
+        let roads = read_inputs(&[Input::Elements(&source)], &ImportConfig::default(), &ProfileConfig::default()).unwrap();
+        let (graph, _, report) = build_graph_reported(&roads, None, DistanceMetric::Haversine).unwrap();
+
+        // 1 - 2 and 4 - 5 - 7 are left, 8 alone isn't a road
+        assert_eq!(graph.get_total_nodes(), 5);
+        assert_eq!(graph.get_total_edges(), 6);
+        assert!(graph.validate().is_empty());
+        assert_eq!(graph.components(), vec![3, 2]);
+        assert_eq!(report.ways_split, 1);
+        assert_eq!(report.dropped_ways.missing_nodes, 1);
+        assert_eq!(report.missing_nodes.first, vec![3, 6]);
+
+        let node = |id| graph.get_node_index(osm::NodeID(id)).unwrap();
+        let (_, path) = shortest_path(&graph, node(4), node(7)).unwrap();
+        let length: f64 = path.windows(2).map(|pair| graph.find_edge(pair[0], pair[1]).unwrap().distance()).sum();
+        let expected = distance(DistanceMetric::Haversine, LatLon { lat: 38.70, lon: -75.37 }, LatLon { lat: 38.70, lon: -75.36 })
+            + distance(DistanceMetric::Haversine, LatLon { lat: 38.70, lon: -75.36 }, LatLon { lat: 38.71, lon: -75.36 });
+        assert!((length - expected).abs() < 0.2);
+        assert!(shortest_path(&graph, node(1), node(4)).is_err());
+    }
+
+    #[test]
+    fn test_parse_maxspeed() {
+        assert_eq!(parse_maxspeed("50"), Some(50.0));
+        assert_eq!(parse_maxspeed(" 30 mph"), Some(30.0 * 1.609344));
+        assert_eq!(parse_maxspeed("none"), None);
+        // a zero or negative speed would make the road impossible (or free) to drive
+        for bad in ["0", "-5", "NaN", "inf", "0 mph", "-5 mph", "NaN mph", "inf mph"] {
+            assert_eq!(parse_maxspeed(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_import_report() {
+        let xml = r#"<osm version='0.6'>
+  <node id='1' lat='39.0' lon='-75.0' />
+  <node id='2' lat='39.0' lon='-74.99' />
+  <node id='3' lat='39.0' lon='-74.99' />
+  <node id='4' lat='39.0' lon='-74.9' />
+  <node id='5' lat='39.5' lon='-75.5' />
+  <node id='6' lat='39.5' lon='-75.49' />
+  <way id='10'> <nd ref='1' /> <nd ref='2' /> <nd ref='2' /> <nd ref='3' /> <tag k='highway' v='residential' /> </way>
+  <way id='11'> <nd ref='1' /> <nd ref='99' /> <tag k='highway' v='residential' /> </way>
+  <way id='12'> <nd ref='5' /> <nd ref='6' /> <tag k='highway' v='footway' /> </way>
+  <way id='13'> <nd ref='1' /> <nd ref='4' /> <tag k='highway' v='primary' /> </way>
+  <way id='14'> <nd ref='3' /> <nd ref='4' /> <tag k='highway' v='proposed' /> </way>
+  <way id='15'> <nd ref='5' /> <nd ref='6' /> <tag k='building' v='yes' /> </way>
+</osm>"#;
+        let mut import = ImportConfig::default();
+        import.exclude.insert("highway".to_string(), vec!["proposed".to_string()]);
+        let source = XmlSource::from_string(xml);
+        let roads = read_inputs(&[Input::Elements(&source)], &import, &ProfileConfig::default()).unwrap();
+        let (_, _, report) = build_graph_reported(&roads, None, DistanceMetric::Haversine).unwrap();
+
+        assert_eq!(report.ways_read, 6);
+        assert_eq!(report.ways_imported, 3);
+        assert_eq!(report.dropped_ways.excluded, 1);
+        assert_eq!(report.dropped_ways.missing_nodes, 1);
+        assert_eq!(report.missing_nodes.first, vec![99]);
+        assert_eq!(report.unknown_highways.into_iter().collect::<Vec<_>>(), vec![("footway".to_string(), 1)]);
+        assert_eq!(report.duplicate_nodes.first, vec![2]);
+        assert_eq!(report.zero_length_edges.first, vec![[2, 3]]);
+        assert_eq!(report.long_edges.count, 1); // 1 - 4, 8.6 km
+        assert_eq!((report.islands.count, report.islands.nodes), (1, 2)); // the footway
+        assert_eq!(report.high_degree_nodes.count, 0);
+
+        // without a fallback speed the footway can't be used at all
+        let profile = ProfileConfig { fallback_speed: None, ..ProfileConfig::default() };
+        let roads = read_inputs(&[Input::Elements(&source)], &import, &profile).unwrap();
+        assert_eq!(roads.report.dropped_ways.not_routable, 1);
+        assert_eq!(roads.report.unknown_highways["footway"], 1);
+    }
+
     #[test]
     fn test_parsed_map() {
         let (graph, tree) = parse_map("./data/delaware-latest.osm.pbf").unwrap();
@@ -194,12 +743,17 @@ mod tests {
         println!("Start ID: {:?}", start_node.data);
         println!("Target ID: {:?}", target_node.data);
 
-        let start_node_edges = graph.adjacent_edges(start_node.data).unwrap();
+        // or the first node of the edge of a shape point
+        let index = |id| graph.get_node_index(id).or_else(|| graph.shape_point_position(id).map(|p| p.from_node)).unwrap();
+        let start_index = index(start_node.data);
+        let target_index = index(target_node.data);
+
+        let start_node_edges = graph.adjacent_edges(start_index).unwrap();
         for edge in start_node_edges {
-            println!("To Node {}", edge.to_node.field1)
+            println!("To Node {}", edge.to_node.0)
         }
 
-        let (dist, path) = shortest_path(&graph, start_node.data, target_node.data).unwrap();
+        let (dist, path) = shortest_path(&graph, start_index, target_index).unwrap();
         println!("Distance: {}", dist);
         println!("Path: {:?}", path);
 
@@ -0,0 +1,166 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use tokio::sync::Semaphore;
+
+use crate::{config::LimitsConfig, monitoring};
+
+// the routing queries (searches, tables, map matching) are cpu bound: they run on tokio's blocking
+// pool, not on the async workers, so that a long search doesn't stall the other requests.
+//
+// at most `max_concurrent_queries` run at once, up to `max_queued_queries` more wait for their turn,
+// anything beyond gets a 503. A query (waiting included) longer than `query_timeout` gets a 503 too.
+// A search can't be interrupted: after a timeout it still runs to its end, and keeps its slot.
+
+pub(crate) struct QueryPool {
+    permits: Arc<Semaphore>,
+    queued: AtomicUsize,
+    max_queued: usize,
+    timeout: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum QueryError {
+    Overloaded, // the queue is full
+    Timeout,
+    Panicked,
+}
+
+// one waiting query, removed from the count even if the request is dropped while waiting
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl QueryPool {
+    pub(crate) fn new(limits: &LimitsConfig) -> QueryPool {
+        QueryPool {
+            permits: Arc::new(Semaphore::new(limits.max_concurrent_queries)),
+            queued: AtomicUsize::new(0),
+            max_queued: limits.max_queued_queries,
+            timeout: Duration::from_secs_f64(limits.query_timeout),
+        }
+    }
+
+    pub(crate) async fn run<T, F>(&self, query: F) -> Result<T, QueryError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let _queued = Queued(&self.queued);
+                if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+                    monitoring::query_rejected("overloaded");
+                    return Err(QueryError::Overloaded);
+                }
+                match tokio::time::timeout_at(deadline, self.permits.clone().acquire_owned()).await {
+                    Ok(Ok(permit)) => permit,
+                    Ok(Err(_)) => return Err(QueryError::Overloaded), // closed, never happens
+                    Err(_) => {
+                        monitoring::query_rejected("timeout");
+                        return Err(QueryError::Timeout);
+                    }
+                }
+            }
+        };
+
+        // the logs of the query stay in the span of its request
+        let span = tracing::Span::current();
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit; // released when the query is over, even after a timeout
+            span.in_scope(query)
+        });
+        match tokio::time::timeout_at(deadline, task).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(e)) => {
+                tracing::error!("query panicked: {}", e);
+                Err(QueryError::Panicked)
+            }
+            Err(_) => {
+                monitoring::query_rejected("timeout");
+                Err(QueryError::Timeout)
+            }
+        }
+    }
+
+    // runs a handler's query, the errors of the pool are responses too
+    pub(crate) async fn respond<R, F>(&self, query: F) -> Response
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoResponse + Send + 'static,
+    {
+        match self.run(move || query().into_response()).await {
+            Ok(response) => response,
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+impl IntoResponse for QueryError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            QueryError::Overloaded => (StatusCode::SERVICE_UNAVAILABLE, "too many queries, try again later"),
+            QueryError::Timeout => (StatusCode::SERVICE_UNAVAILABLE, "the query took too long"),
+            QueryError::Panicked => (StatusCode::INTERNAL_SERVER_ERROR, "internal error"),
+        };
+        let body = Json(serde_json::json!({ "error_message": message, "code": status.as_u16() }));
+        match self {
+            QueryError::Panicked => (status, body).into_response(),
+            _ => (status, [(header::RETRY_AFTER, "1")], body).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue_and_timeout() {
+        let limits = LimitsConfig {
+            max_concurrent_queries: 1,
+            max_queued_queries: 1,
+            query_timeout: 0.2,
+            ..LimitsConfig::default()
+        };
+        let pool = Arc::new(QueryPool::new(&limits));
+        assert_eq!(pool.run(|| 42).await, Ok(42));
+
+        // one running, one waiting, the third one is turned away
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || wait.recv().is_ok()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| 1).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(pool.run(|| 2).await, Err(QueryError::Overloaded));
+
+        // nobody releases the first query in time
+        assert_eq!(running.await.unwrap(), Err(QueryError::Timeout));
+        assert_eq!(waiting.await.unwrap(), Err(QueryError::Timeout));
+        release.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(pool.run(|| 3).await, Ok(3));
+    }
+}
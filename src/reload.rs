@@ -0,0 +1,136 @@
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use chrono::Utc;
+use serde_json::json;
+
+use crate::{dataset::Dataset, monitoring, server::AppState};
+
+// replacing the map data without a restart.
+//
+// a new dataset is built in the background with the same loader as at startup, while the current
+// one keeps answering. It's swapped in only if it's valid; requests already running finish with
+// the dataset they started with (they hold an Arc to it).
+//
+// two triggers:
+//   POST /admin/reload, with `Authorization: Bearer <server.admin_token>` (no token, no endpoint)
+//   server.watch: the data files are checked every WATCH_INTERVAL, and reloaded once they changed
+//   and stopped changing (so that we don't read a file which is still being copied).
+//   We poll the modification times instead of using inotify: it also works on docker volumes
+//   and network file systems, and the data doesn't change often.
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/admin/reload", post(admin_reload))
+}
+
+// loads a dataset and checks it, runs the loader on a blocking thread: parsing is cpu bound
+pub(crate) async fn build_dataset(state: &AppState) -> Result<Dataset, String> {
+    let started = Instant::now();
+    let loader = state.loader.clone();
+    let mut dataset = match tokio::task::spawn_blocking(move || loader()).await {
+        Ok(result) => result?,
+        Err(e) => return Err(format!("loading panicked: {}", e)),
+    };
+
+    dataset.validate()?;
+
+    dataset.loaded_at = Utc::now();
+    dataset.load_seconds = started.elapsed().as_secs_f64();
+    Ok(dataset)
+}
+
+// false if a reload is already running
+pub(crate) fn start_reload(state: &Arc<AppState>) -> bool {
+    if state.reloading.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        tracing::info!("reloading the map data");
+        match build_dataset(&state).await {
+            Ok(dataset) => {
+                let version = state.set_dataset(dataset);
+                tracing::info!("reloaded, dataset version {}", version);
+                monitoring::dataset_reload("success");
+            }
+            Err(e) => {
+                tracing::error!("reload failed, keeping the current data: {}", e);
+                monitoring::dataset_reload("failure");
+            }
+        }
+        state.reloading.store(false, Ordering::SeqCst);
+    });
+    true
+}
+
+async fn admin_reload(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let authorized = match (&state.admin_token, headers.get(header::AUTHORIZATION)) {
+        (Some(token), Some(value)) => value.to_str().ok() == Some(format!("Bearer {}", token).as_str()),
+        _ => false,
+    };
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "error_message": "unauthorized" }))).into_response();
+    }
+
+    let current = match state.dataset() {
+        Some(d) => d,
+        None => {
+            let message = "the map data is still loading";
+            return (StatusCode::CONFLICT, Json(json!({ "error_message": message }))).into_response();
+        }
+    };
+    if !start_reload(&state) {
+        let message = "a reload is already running";
+        return (StatusCode::CONFLICT, Json(json!({ "error_message": message }))).into_response();
+    }
+    // the result shows up in /health, and in the x-dataset-version header of the responses
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "status": "reloading", "current_version": current.version })),
+    )
+        .into_response()
+}
+
+fn modification_times(state: &AppState) -> Vec<Option<SystemTime>> {
+    let sources = match state.dataset() {
+        Some(d) => d.sources(),
+        None => return vec![],
+    };
+    sources
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+pub(crate) async fn watch_files(state: Arc<AppState>) {
+    let mut known = modification_times(&state);
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        let now = modification_times(&state);
+        // empty: nothing was loaded yet when we started
+        if known.is_empty() || now == known {
+            known = now;
+            continue;
+        }
+        // wait until the copy is over
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        if modification_times(&state) != now {
+            continue;
+        }
+        tracing::info!("the data files changed");
+        if start_reload(&state) {
+            known = now;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::graph::{Graph, NodeIndex};
+
+// what an import skipped or found suspicious in the data, see `preprocess --report`.
+// the lists only keep their first few entries (with the total), a country has thousands.
+
+pub const LONG_EDGE: f64 = 5_000.0; // meters between 2 consecutive nodes of a way
+pub const HIGH_DEGREE: usize = 8; // neighbours of a node, more is likely a mapping error
+const SAMPLE: usize = 20;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    pub ways_read: u64,       // all the ways of the extracts
+    pub ways_imported: usize, // in the graph, a way cut by the clip area counts once per part
+    pub ways_split: u64,      // around their missing nodes, the parts left are imported
+    pub dropped_ways: DroppedWays,
+    pub unknown_highways: BTreeMap<String, u64>, // way tag values without a speed in the profile: fallback speed or dropped
+    pub missing_nodes: Sample<i64>,             // referenced by a way but in none of the extracts
+    pub duplicate_nodes: Sample<i64>,           // repeated in a row in a way (1, 2, 2, 3)
+    pub zero_length_edges: Sample<[i64; 2]>,    // different nodes at the same place
+    pub long_edges: Sample<LongEdge>,           // longer than LONG_EDGE
+    pub islands: Islands,
+    pub high_degree_nodes: Sample<(i64, usize)>, // with their number of neighbours, highest first
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DroppedWays {
+    pub excluded: u64,      // import.exclude
+    pub not_routable: u64,  // no speed in the profile and no fallback_speed
+    pub duplicates: u64,    // in several extracts, imported once
+    pub outside_clip: u64,  // import.clip
+    pub missing_nodes: u64, // no 2 consecutive nodes of it in the extracts
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sample<T> {
+    pub count: u64,
+    pub first: Vec<T>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LongEdge {
+    pub from: i64,
+    pub to: i64,
+    pub meters: f64,
+}
+
+// the connected components besides the largest one: unreachable from most of the graph
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Islands {
+    pub count: usize,
+    pub nodes: usize,
+    pub largest: usize, // nodes of the biggest island
+}
+
+impl<T> Default for Sample<T> {
+    fn default() -> Self {
+        Sample { count: 0, first: Vec::new() }
+    }
+}
+
+impl<T> Sample<T> {
+    pub fn add(&mut self, item: T) {
+        self.count += 1;
+        if self.first.len() < SAMPLE {
+            self.first.push(item);
+        }
+    }
+}
+
+impl ImportReport {
+    // islands and high degree nodes, from the built graph
+    pub fn add_graph(&mut self, graph: &Graph) {
+        let components = graph.components();
+        let islands = components.get(1..).unwrap_or_default();
+        self.islands = Islands {
+            count: islands.len(),
+            nodes: islands.iter().sum(),
+            largest: islands.first().copied().unwrap_or(0),
+        };
+
+        let mut high_degree: Vec<(i64, usize)> = graph
+            .for_each_node()
+            .filter_map(|node| {
+                let degree = degree(graph, node);
+                let id = graph.get_osm_id(node)?;
+                (degree >= HIGH_DEGREE).then_some((id.0, degree))
+            })
+            .collect();
+        high_degree.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        self.high_degree_nodes = Sample::default();
+        for node in high_degree {
+            self.high_degree_nodes.add(node);
+        }
+    }
+}
+
+// distinct neighbours, the edges of a node go to them (and the reverse ones come back)
+fn degree(graph: &Graph, node: NodeIndex) -> usize {
+    let mut neighbours: Vec<NodeIndex> = graph.adjacent_edges(node).into_iter().flatten().map(|e| e.to_node).collect();
+    neighbours.sort_by_key(|n| n.index());
+    neighbours.dedup();
+    neighbours.len()
+}
+
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dropped = &self.dropped_ways;
+        writeln!(f, "ways: {} read, {} imported", self.ways_read, self.ways_imported)?;
+        writeln!(
+            f,
+            "dropped ways: {} excluded, {} not routable, {} duplicates, {} outside the clip area, {} without 2 nodes in a row",
+            dropped.excluded, dropped.not_routable, dropped.duplicates, dropped.outside_clip, dropped.missing_nodes
+        )?;
+        if !self.unknown_highways.is_empty() {
+            let mut unknown: Vec<(&String, &u64)> = self.unknown_highways.iter().collect();
+            unknown.sort_by(|a, b| b.1.cmp(a.1));
+            let unknown: Vec<String> = unknown.iter().take(10).map(|(k, n)| format!("{} ({})", k, n)).collect();
+            writeln!(f, "unknown highway values: {}", unknown.join(", "))?;
+        }
+        writeln!(f, "missing nodes: {} ({} ways cut around them)", self.missing_nodes.count, self.ways_split)?;
+        writeln!(f, "duplicate consecutive nodes: {}", self.duplicate_nodes.count)?;
+        writeln!(f, "zero length edges: {}", self.zero_length_edges.count)?;
+        writeln!(f, "edges longer than {} m: {}", LONG_EDGE, self.long_edges.count)?;
+        writeln!(f, "islands: {} ({} nodes, the largest {})", self.islands.count, self.islands.nodes, self.islands.largest)?;
+        write!(f, "nodes with {} neighbours or more: {}", HIGH_DEGREE, self.high_degree_nodes.count)
+    }
+}
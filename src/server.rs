@@ -1,67 +1,463 @@
-use std::{rc::Rc, sync::Arc};
+use std::{
+    error::Error,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+};
 
 use axum::{
-    body,
-    extract::{Query, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    extract::{FromRequestParts, Query, Request, State},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::get,
     Json, Router,
 };
-use rstar::RTree;
+use chrono::{DateTime, Utc};
+use metrics_exporter_prometheus::PrometheusHandle;
 use serde::{Deserialize, Serialize};
 use tower::ServiceBuilder;
-use tower_http::{cors::{Any, CorsLayer}, services::{ServeDir, ServeFile}};
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::{DefaultOnFailure, DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+use tokio::sync::oneshot;
+use tracing::Level;
 
 use crate::{
-    engine::Engine,
-    graph::{shortest_path, Graph, LatLon},
-    parser::{parse_map, NodeLocation},
+    config::LimitsConfig,
+    dataset::{Dataset, DatasetError},
+    graph::LatLon,
+    monitoring, osrm,
+    queries::QueryPool,
+    reload,
 };
 
-struct AppState {
-    engine: Engine,
+// builds a dataset, the same way at startup and for every reload
+pub type Loader = Arc<dyn Fn() -> Result<Dataset, String> + Send + Sync>;
+
+pub(crate) struct AppState {
+    dataset: RwLock<Option<Arc<Dataset>>>, // None while loading
+    pub(crate) loader: Loader,
+    pub(crate) reloading: AtomicBool,
+    pub(crate) admin_token: Option<String>,
+    shutting_down: AtomicBool,
+    started_at: DateTime<Utc>,
+    pub(crate) limits: LimitsConfig,
+    pub(crate) queries: QueryPool,
+}
+
+impl AppState {
+    pub(crate) fn dataset(&self) -> Option<Arc<Dataset>> {
+        self.dataset.read().unwrap().clone()
+    }
+
+    // swaps the dataset, returns its version
+    pub(crate) fn set_dataset(&self, mut dataset: Dataset) -> u64 {
+        let mut current = self.dataset.write().unwrap();
+        dataset.version = current.as_ref().map_or(1, |d| d.version + 1);
+        let version = dataset.version;
+        *current = Some(Arc::new(dataset));
+        monitoring::dataset_version(version);
+        version
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    // nothing loaded yet, `loader` is what the first load or a reload runs
+    pub(crate) fn for_tests(loader: Loader) -> Arc<AppState> {
+        Arc::new(AppState {
+            dataset: RwLock::new(None),
+            loader,
+            reloading: AtomicBool::new(false),
+            admin_token: Some("secret".to_string()),
+            shutting_down: AtomicBool::new(false),
+            started_at: Utc::now(),
+            limits: LimitsConfig::default(),
+            queries: QueryPool::new(&LimitsConfig::default()),
+        })
+    }
+}
+
+// the dataset used to answer a request, for the x-dataset-* response headers (see dataset_headers).
+// the slot is shared between the request and the middleware.
+#[derive(Clone, Default)]
+struct UsedDataset(Arc<OnceLock<(u64, DateTime<Utc>)>>);
+
+// handlers that need the map data take this one: 503 until it's loaded
+pub(crate) struct Ready(pub(crate) Arc<Dataset>);
+
+impl FromRequestParts<Arc<AppState>> for Ready {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let dataset = state.dataset();
+        if let (Some(d), Some(used)) = (&dataset, parts.extensions.get::<UsedDataset>()) {
+            let _ = used.0.set((d.version, d.loaded_at));
+        }
+        dataset.map(Ready).ok_or_else(|| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, "5")],
+                Json(serde_json::json!({ "error_message": "the map data is still loading", "code": 503 })),
+            )
+                .into_response()
+        })
+    }
+}
+
+pub struct ServerOptions {
+    pub address: String,           // e.g. 0.0.0.0:3000
+    pub cors_origins: Vec<String>, // e.g. https://example.com, empty means any origin
+    pub limits: LimitsConfig,
+    pub web_dir: String,           // static files, served at /
+    pub metrics: Option<PrometheusHandle>, // see monitoring::install, no /metrics without it
+    pub admin_token: Option<String>, // for /admin/reload, no admin endpoints without it
+    pub watch: bool,                 // reload when the data files change
 }
 
-pub async fn server_start(address: &str, map_path: &str) {
-    let engine = Engine::build(map_path).unwrap();
-    let shared_state = Arc::new(AppState { engine });
+// the page of web/, so that a lone binary still has a UI
+const EMBEDDED_INDEX_HTML: &str = include_str!("../web/index.html");
+
+// the listener starts right away, `load` runs in the background: /health_check and /health
+// answer during the (long) loading, /ready and the routing endpoints answer 503 until it's done.
+// If the first `load` fails the server stops with its error, see reload.rs for the next ones.
+// Stops on SIGINT / SIGTERM, after the requests in flight are answered.
+pub async fn server_start(options: ServerOptions, load: Loader) -> Result<(), Box<dyn Error>> {
+    let shared_state = Arc::new(AppState {
+        dataset: RwLock::new(None),
+        loader: load,
+        reloading: AtomicBool::new(false),
+        admin_token: options.admin_token.clone(),
+        shutting_down: AtomicBool::new(false),
+        started_at: Utc::now(),
+        queries: QueryPool::new(&options.limits),
+        limits: options.limits.clone(),
+    });
+
+    let app = router(&options, shared_state.clone())?;
+
+    let listener = tokio::net::TcpListener::bind(&options.address).await?;
+    tracing::info!("listening on {}", options.address);
+
+    let (failed_tx, failed_rx) = oneshot::channel();
+    tokio::spawn(load_dataset(shared_state.clone(), failed_tx));
+    if options.watch {
+        tokio::spawn(reload::watch_files(shared_state.clone()));
+    }
+
+    let load_error = Arc::new(Mutex::new(None));
+    let shutdown = {
+        let load_error = load_error.clone();
+        let state = shared_state.clone();
+        async move {
+            tokio::select! {
+                _ = shutdown_signal() => tracing::info!("shutting down, waiting for the requests in flight"),
+                // the sender is dropped when the loading succeeds, this branch is then disabled
+                Ok(e) = failed_rx => *load_error.lock().unwrap() = Some(e),
+            }
+            state.shutting_down.store(true, Ordering::Relaxed);
+        }
+    };
+    axum::serve(listener, app).with_graceful_shutdown(shutdown).await?;
+
+    let load_error = load_error.lock().unwrap().take();
+    match load_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+// the endpoints, the web page and the middlewares
+fn router(options: &ServerOptions, state: Arc<AppState>) -> Result<Router, Box<dyn Error>> {
+    let allow_origin = if options.cors_origins.is_empty() {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = options
+            .cors_origins
+            .iter()
+            .map(|o| o.parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
 
     let cors = CorsLayer::new()
         .allow_methods(Any)
-        .allow_origin(Any)
+        .allow_origin(allow_origin)
         .allow_headers(Any);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/health_check", get(health_check))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
         .route("/nav", get(nav))
-        .layer(ServiceBuilder::new().layer(cors))
-        // put web/index.html in the same dir of our final binary
-        // in docker:
-        //    /app (dir)
-        //    /app/simple-nav (binary)
-        //    /app/web/index.html (static web page)
-        .with_state(shared_state);
+        .route("/nearest", get(nearest))
+        .route("/profiles", get(profiles))
+        .route("/regions", get(regions))
+        .merge(osrm::routes());
+
+    if options.admin_token.is_some() {
+        app = app.merge(reload::routes());
+    }
+
+    if let Some(handle) = options.metrics.clone() {
+        tokio::spawn(monitoring::run_upkeep(handle.clone()));
+        app = app.route("/metrics", get(move || std::future::ready(handle.render())));
+    }
+
+    // the api routes win, anything else is a static file.
+    // in docker:
+    //    /app (dir, working directory)
+    //    /app/simple-nav (binary)
+    //    /app/web/index.html (static web page)
+    if Path::new(&options.web_dir).join("index.html").is_file() {
+        tracing::info!("serving the web page from {}", options.web_dir);
+        app = app.fallback_service(ServeDir::new(&options.web_dir));
+    } else {
+        tracing::info!("{} has no index.html, serving the built-in web page", options.web_dir);
+        app = app
+            .route("/", get(embedded_index))
+            .route("/index.html", get(embedded_index));
+    }
+
+    // every request gets an id (or keeps the x-request-id it came with), it's in the logs
+    // of the request and in the response headers, so a client can tell us which one failed.
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(|request: &Request| {
+            let request_id = request
+                .headers()
+                .get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                path = %request.uri().path(),
+                request_id,
+            )
+        })
+        .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(LatencyUnit::Millis))
+        .on_failure(DefaultOnFailure::new().latency_unit(LatencyUnit::Millis));
+
+    let app = app
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(trace)
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(middleware::from_fn(monitoring::track_requests))
+                .layer(middleware::from_fn(dataset_headers))
+                .layer(cors),
+        )
+        .with_state(state);
+    Ok(app)
+}
+
+async fn load_dataset(state: Arc<AppState>, failed: oneshot::Sender<String>) {
+    match reload::build_dataset(&state).await {
+        Ok(dataset) => {
+            tracing::info!("ready, loaded in {:.1} s", dataset.load_seconds);
+            state.set_dataset(dataset);
+        }
+        Err(e) => {
+            tracing::error!("can't load the map data: {}", e);
+            let _ = failed.send(e);
+        }
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+// x-dataset-version / x-dataset-loaded-at: which data answered, useful around reloads
+async fn dataset_headers(mut request: Request, next: Next) -> Response {
+    let used = UsedDataset::default();
+    request.extensions_mut().insert(used.clone());
+    let mut response = next.run(request).await;
+    if let Some((version, loaded_at)) = used.0.get() {
+        let headers = response.headers_mut();
+        headers.insert("x-dataset-version", HeaderValue::from(*version));
+        if let Ok(value) = HeaderValue::from_str(&loaded_at.to_rfc3339()) {
+            headers.insert("x-dataset-loaded-at", value);
+        }
+    }
+    response
 }
 
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("can't listen to ctrl-c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("can't listen to SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// liveness: the process answers
 async fn health_check() -> &'static str {
-    return "Ok";
+    "Ok"
+}
+
+// readiness: the map data is loaded, and we're not shutting down
+async fn ready(app_state: State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let ready = app_state.dataset().is_some() && !app_state.shutting_down.load(Ordering::Relaxed);
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(serde_json::json!({ "ready": ready })))
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: &'static str, // loading, ready or shutting_down
+    version: &'static str,
+    commit: &'static str, // GIT_COMMIT at build time
+    started_at: String,
+    uptime_seconds: i64,
+    dataset: Option<DatasetResponse>, // None while loading
+}
+
+#[derive(Debug, Serialize)]
+struct DatasetResponse {
+    version: u64,
+    default_profile: String,
+    loaded_at: String,
+    load_seconds: f64,
+    regions: serde_json::Value, // name -> profile name -> ProfileInfo
+}
+
+// build info and what is loaded, for humans and dashboards
+async fn health(app_state: State<Arc<AppState>>) -> Json<HealthResponse> {
+    let dataset = app_state.dataset();
+    let status = if app_state.shutting_down.load(Ordering::Relaxed) {
+        "shutting_down"
+    } else if dataset.is_some() {
+        "ready"
+    } else {
+        "loading"
+    };
+    Json(HealthResponse {
+        status,
+        version: env!("CARGO_PKG_VERSION"),
+        commit: option_env!("GIT_COMMIT").unwrap_or("unknown"),
+        started_at: app_state.started_at.to_rfc3339(),
+        uptime_seconds: (Utc::now() - app_state.started_at).num_seconds(),
+        dataset: dataset.map(|d| DatasetResponse {
+            version: d.version,
+            default_profile: d.default_profile.clone(),
+            loaded_at: d.loaded_at.to_rfc3339(),
+            load_seconds: d.load_seconds,
+            regions: d
+                .regions()
+                .iter()
+                .map(|(name, r)| (name.clone(), serde_json::json!(r.profiles())))
+                .collect(),
+        }),
+    })
+}
+
+async fn embedded_index() -> Html<&'static str> {
+    Html(EMBEDDED_INDEX_HTML)
+}
+
+#[derive(Debug, Serialize)]
+struct ProfilesResponse {
+    default: String,
+    profiles: Vec<String>,
+}
+
+// what the `profile` parameter accepts
+async fn profiles(Ready(dataset): Ready) -> Json<ProfilesResponse> {
+    Json(ProfilesResponse {
+        default: dataset.default_profile.clone(),
+        profiles: dataset.profiles().iter().map(|p| p.to_string()).collect(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct RegionResponse {
+    name: String,
+    bounds: Option<[f64; 4]>, // [min_lon, min_lat, max_lon, max_lat], like geojson
+    profiles: serde_json::Value, // name -> ProfileInfo (source, nodes, edges)
+}
+
+// what the `region` parameter accepts, and where each region is
+async fn regions(Ready(dataset): Ready) -> Json<Vec<RegionResponse>> {
+    Json(
+        dataset
+            .regions()
+            .iter()
+            .map(|(name, r)| RegionResponse {
+                name: name.clone(),
+                bounds: r.bounds().map(|b| [b.min.lon, b.min.lat, b.max.lon, b.max.lat]),
+                profiles: serde_json::json!(r.profiles()),
+            })
+            .collect(),
+    )
+}
+
+impl From<DatasetError> for ErrResponse {
+    fn from(e: DatasetError) -> Self {
+        let code = match e {
+            DatasetError::OutsideRegions => 222, // like a point we can't snap
+            _ => 111,
+        };
+        ErrResponse { error_message: e.to_string(), code }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct NavParameters {
     orig: String,
     dest: String,
+    depart_at: Option<String>, // RFC 3339, e.g. 2026-10-20T08:00:00-04:00
+    geometries: Option<String>, // latlon (default), polyline, polyline6, geojson, feature, gpx
+    profile: Option<String>,    // e.g. car, bike, default: the default profile of the config
+    region: Option<String>,     // default: the smallest region containing orig and dest
+    steps: Option<bool>,        // turn by turn instructions, false by default
+}
+
+// how `path` looks like in the response
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum PathGeometry {
+    LatLons(Vec<LatLon>),         // [{lat, lon}, ...]
+    Polyline(String),             // encoded polyline
+    GeoJson(serde_json::Value),   // LineString
 }
 
 #[derive(Debug, Serialize)]
 struct NavResponse {
     distance: f64,
     duration: f64,
-    path: Vec<LatLon>,
+    path: PathGeometry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depart_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eta: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steps: Option<Vec<NavStep>>,
+}
+
+#[derive(Debug, Serialize)]
+struct NavStep {
+    instruction: String, // e.g. "Turn left onto Main Street"
+    name: String,
+    distance: f64,
+    duration: f64,
+    location: LatLon, // where the maneuver happens
 }
 
 impl IntoResponse for NavResponse {
@@ -69,7 +465,7 @@ impl IntoResponse for NavResponse {
         let body = serde_json::to_string(&self); // Result<String>
         match body {
             Ok(b) => (StatusCode::OK, b).into_response(), // http 200
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, "json seriliaze error").into_response(), // http 500 error
+            Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "json seriliaze error").into_response(), // http 500 error
         }
     }
 }
@@ -85,7 +481,7 @@ impl IntoResponse for ErrResponse {
         let body = serde_json::to_string(&self); // Result<String>
         match body {
             Ok(b) => (StatusCode::OK, b).into_response(), // http 500
-            Err(e) => (StatusCode::OK, "json seriliaze error").into_response(), // http 500 error
+            Err(_) => (StatusCode::OK, "json seriliaze error").into_response(), // http 500 error
         }
     }
 }
@@ -94,23 +490,456 @@ impl IntoResponse for ErrResponse {
 // extractor (axum): special trick.
 async fn nav(
     Query(req): Query<NavParameters>,
-    app_state: State<Arc<AppState>>,
-) -> Result<NavResponse, ErrResponse> {
-    let origin = LatLon::parse(&req.orig).map_err(|e| ErrResponse {
+    Ready(dataset): Ready,
+    State(app_state): State<Arc<AppState>>,
+) -> Response {
+    app_state.queries.respond(move || nav_query(req, &dataset)).await
+}
+
+// runs on the blocking pool, see queries.rs
+fn nav_query(req: NavParameters, dataset: &Dataset) -> Result<Response, ErrResponse> {
+    let origin = LatLon::parse(&req.orig).map_err(|_| ErrResponse {
         error_message: "origin  format error".to_string(),
         code: 111,
     })?;
 
-    let destination = LatLon::parse(&req.dest).map_err(|e| ErrResponse {
+    let destination = LatLon::parse(&req.dest).map_err(|_| ErrResponse {
         error_message: "destination format error".to_string(),
         code: 111,
     })?;
 
-    let result = app_state.engine.routing(origin, destination).unwrap();
+    let depart_at = match &req.depart_at {
+        Some(t) => Some(DateTime::parse_from_rfc3339(t).map_err(|_| ErrResponse {
+            error_message: "depart_at format error, expected RFC 3339".to_string(),
+            code: 111,
+        })?),
+        None => None,
+    };
+
+    let engine = dataset.engine(req.region.as_deref(), req.profile.as_deref(), &[origin, destination])?;
+
+    let geometries = req.geometries.as_deref().unwrap_or("latlon");
+    if !matches!(geometries, "latlon" | "polyline" | "polyline6" | "geojson" | "feature" | "gpx") {
+        return Err(ErrResponse {
+            error_message: format!("unknown geometries: {}", geometries),
+            code: 111,
+        });
+    }
+
+    let result = match depart_at {
+        Some(t) => engine.routing_at(origin, destination, t),
+        None => engine.routing(origin, destination),
+    }
+    .map_err(|e| ErrResponse {
+        error_message: e.to_string(),
+        code: 222,
+    })?;
+
+    let path = match geometries {
+        "polyline" => PathGeometry::Polyline(result.to_polyline(5)),
+        "polyline6" => PathGeometry::Polyline(result.to_polyline(6)),
+        "geojson" => PathGeometry::GeoJson(result.to_geojson_linestring()),
+        // the whole response is a geojson Feature
+        "feature" => {
+            let mut feature = result.to_geojson_feature();
+            if let Some(t) = depart_at {
+                feature["properties"]["depart_at"] = serde_json::json!(t.to_rfc3339());
+            }
+            return Ok((
+                [(header::CONTENT_TYPE, "application/geo+json")],
+                feature.to_string(),
+            )
+                .into_response());
+        }
+        // download as a file
+        "gpx" => {
+            return Ok((
+                [
+                    (header::CONTENT_TYPE, "application/gpx+xml"),
+                    (header::CONTENT_DISPOSITION, "attachment; filename=\"route.gpx\""),
+                ],
+                result.to_gpx("route"),
+            )
+                .into_response());
+        }
+        _ => PathGeometry::LatLons(result.route_path.clone()),
+    };
+
+    let steps = req.steps.unwrap_or(false).then(|| {
+        engine
+            .steps(&result)
+            .iter()
+            .map(|s| NavStep {
+                instruction: s.instruction(),
+                name: s.name.clone(),
+                distance: s.distance,
+                duration: s.duration,
+                location: s.maneuver.location,
+            })
+            .collect()
+    });
 
     Ok(NavResponse {
         distance: result.total_distance,
-        duration: 0.0,
-        path: result.route_path,
-    })
+        duration: result.total_duration,
+        path,
+        depart_at: depart_at.map(|t| t.to_rfc3339()),
+        eta: result.arrival.map(|t| t.to_rfc3339()),
+        steps,
+    }
+    .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct NearestParameters {
+    loc: String,         // lat,lon
+    k: Option<usize>,    // how many nodes, 1 by default (all of them within `radius` when it's given)
+    radius: Option<f64>, // meters
+    profile: Option<String>,
+    region: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct NearestNode {
+    node_id: i64, // osm id
+    location: LatLon,
+    distance: f64, // meters
+    roads: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct NearestResponse {
+    nodes: Vec<NearestNode>,
+}
+
+impl IntoResponse for NearestResponse {
+    fn into_response(self) -> axum::response::Response {
+        Json(self).into_response()
+    }
+}
+
+// where does a coordinate snap to? the closest node is what /nav would use.
+async fn nearest(
+    Query(req): Query<NearestParameters>,
+    Ready(dataset): Ready,
+    State(app_state): State<Arc<AppState>>,
+) -> Response {
+    let limits = app_state.limits.clone();
+    app_state
+        .queries
+        .respond(move || nearest_query(req, &dataset, &limits))
+        .await
+}
+
+// runs on the blocking pool: a big radius collects many nodes
+fn nearest_query(
+    req: NearestParameters,
+    dataset: &Dataset,
+    limits: &LimitsConfig,
+) -> Result<NearestResponse, ErrResponse> {
+    let location = LatLon::parse(&req.loc).map_err(|_| ErrResponse {
+        error_message: "loc format error".to_string(),
+        code: 111,
+    })?;
+
+    let engine = dataset.engine(req.region.as_deref(), req.profile.as_deref(), &[location])?;
+
+    let max_nearest = limits.max_nearest;
+    let k = req.k.unwrap_or(match req.radius {
+        Some(_) => max_nearest,
+        None => 1,
+    });
+    if k == 0 || k > max_nearest {
+        return Err(ErrResponse {
+            error_message: format!("k must be between 1 and {}", max_nearest),
+            code: 111,
+        });
+    }
+
+    let found = match req.radius {
+        Some(radius) if radius > 0.0 && radius <= limits.max_radius => {
+            engine.within_radius(location, radius)
+        }
+        Some(_) => {
+            return Err(ErrResponse {
+                error_message: format!(
+                    "radius must be positive and at most {} meters",
+                    limits.max_radius
+                ),
+                code: 111,
+            })
+        }
+        None => engine.nearest(location, k),
+    };
+
+    let nodes = found
+        .iter()
+        .take(k)
+        .map(|s| NearestNode {
+            node_id: s.osm_id.0,
+            location: s.location,
+            distance: s.distance,
+            roads: engine.road_names(s).iter().map(|n| n.to_string()).collect(),
+        })
+        .collect();
+
+    Ok(NearestResponse { nodes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    use crate::{engine::Engine, graph::Graph, osm};
+
+    // one street going north
+    fn test_dataset() -> Dataset {
+        let points: Vec<_> = (1..=3).map(|i| (i, 38.0 + i as f64 * 0.01, -75.0)).collect();
+        let (nodes, way) = osm::street("Main Street", &points);
+        let mut dataset = Dataset::new("car");
+        dataset.add("default", "car", Engine::from_graph(Graph::build(nodes, vec![way])), "test");
+        dataset
+    }
+
+    fn test_state() -> Arc<AppState> {
+        AppState::for_tests(Arc::new(|| Ok(test_dataset())))
+    }
+
+    // the whole app, as server_start serves it
+    fn test_router(web_dir: &str, state: Arc<AppState>) -> Router {
+        let options = ServerOptions {
+            address: "127.0.0.1:0".to_string(),
+            cors_origins: vec![],
+            limits: LimitsConfig::default(),
+            web_dir: web_dir.to_string(),
+            metrics: None,
+            admin_token: None,
+            watch: false,
+        };
+        router(&options, state).unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_while_loading() {
+        let state = test_state();
+        let app = Router::new()
+            .route("/ready", get(ready))
+            .route("/nav", get(nav))
+            .with_state(state.clone());
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/ready")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = app.clone().oneshot(get("/nav?orig=38.0,-75.0&dest=38.1,-75.0")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+
+        state.set_dataset(test_dataset());
+        let response = app.clone().oneshot(get("/ready")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        state.shutting_down.store(true, Ordering::Relaxed);
+        let response = app.oneshot(get("/ready")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let state = test_state();
+        let app = Router::new()
+            .route("/nav", get(nav))
+            .merge(reload::routes())
+            .layer(middleware::from_fn(dataset_headers))
+            .with_state(state.clone());
+        let reload_request = |token: &str| {
+            Request::post("/admin/reload")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let nav = || Request::get("/nav?orig=38.01,-75.0&dest=38.03,-75.0").body(Body::empty()).unwrap();
+
+        // nothing to reload yet
+        let response = app.clone().oneshot(reload_request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        state.set_dataset(reload::build_dataset(&state).await.unwrap());
+        let response = app.clone().oneshot(nav()).await.unwrap();
+        assert_eq!(response.headers()["x-dataset-version"], "1");
+
+        let response = app.clone().oneshot(reload_request("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(reload_request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        while state.reloading.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        let response = app.oneshot(nav()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-dataset-version"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_nearest() {
+        let state = test_state();
+        state.set_dataset(test_dataset());
+        let app = Router::new().route("/nearest", get(nearest)).with_state(state);
+        let get_json = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (content_type, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        // Main Street has nodes every ~1.1 km, from 38.01 to 38.03
+        let (content_type, body) = get_json("/nearest?loc=38.0201,-75.0&k=2").await;
+        assert_eq!(content_type.unwrap(), "application/json");
+        let nodes = body["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0]["node_id"], 2);
+        assert_eq!(nodes[0]["location"], serde_json::json!({ "lat": 38.02, "lon": -75.0 }));
+        assert!((nodes[0]["distance"].as_f64().unwrap() - 11.1).abs() < 0.5);
+        assert_eq!(nodes[0]["roads"], serde_json::json!(["Main Street"]));
+
+        // within a radius: all of them, or the k closest. 38.025 is 556 m from 2 and 3
+        let (_, body) = get_json("/nearest?loc=38.025,-75.0&radius=1000").await;
+        assert_eq!(body["nodes"].as_array().unwrap().len(), 2);
+        let (_, body) = get_json("/nearest?loc=38.025,-75.0&radius=1000&k=1").await;
+        assert_eq!(body["nodes"].as_array().unwrap().len(), 1);
+        let (_, body) = get_json("/nearest?loc=38.025,-75.0&radius=500").await;
+        assert!(body["nodes"].as_array().unwrap().is_empty());
+
+        let max_radius = LimitsConfig::default().max_radius;
+        for uri in [
+            "/nearest?loc=38.0201".to_string(),
+            "/nearest?loc=38.0201,-75.0&k=0".to_string(),
+            format!("/nearest?loc=38.0201,-75.0&k={}", LimitsConfig::default().max_nearest + 1),
+            "/nearest?loc=38.0201,-75.0&radius=0".to_string(),
+            "/nearest?loc=38.0201,-75.0&radius=-5".to_string(),
+            format!("/nearest?loc=38.0201,-75.0&radius={}", max_radius + 1.0),
+            "/nearest?loc=38.0201,-75.0&region=mars".to_string(),
+        ] {
+            let (_, body) = get_json(&uri).await;
+            assert_eq!(body["code"], 111, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_web_page() {
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        // no index.html there: the page built into the binary
+        let app = test_router("/nonexistent", test_state());
+        for uri in ["/", "/index.html"] {
+            let response = app.clone().oneshot(get(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+            assert_eq!(body_text(response).await, EMBEDDED_INDEX_HTML);
+        }
+        let response = app.clone().oneshot(get("/app.js")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // a web directory: its files, the api routes still win
+        let dir = std::env::temp_dir().join(format!("simple-nav-web-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>custom</h1>").unwrap();
+        std::fs::write(dir.join("app.js"), "let a = 1;").unwrap();
+        let app = test_router(dir.to_str().unwrap(), test_state());
+        assert_eq!(body_text(app.clone().oneshot(get("/")).await.unwrap()).await, "<h1>custom</h1>");
+        assert_eq!(body_text(app.clone().oneshot(get("/app.js")).await.unwrap()).await, "let a = 1;");
+        assert_eq!(body_text(app.clone().oneshot(get("/health_check")).await.unwrap()).await, "Ok");
+        let response = app.oneshot(get("/missing.css")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let state = test_state();
+        let app = test_router("/nonexistent", state.clone());
+        let health = || async {
+            let response = app.clone().oneshot(Request::get("/health").body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_str::<serde_json::Value>(&body_text(response).await).unwrap()
+        };
+
+        let body = health().await;
+        assert_eq!(body["status"], "loading");
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["commit"].is_string());
+        assert!(DateTime::parse_from_rfc3339(body["started_at"].as_str().unwrap()).is_ok());
+        assert!(body["uptime_seconds"].as_i64().unwrap() >= 0);
+        assert!(body["dataset"].is_null());
+
+        state.set_dataset(test_dataset());
+        let body = health().await;
+        assert_eq!(body["status"], "ready");
+        let dataset = &body["dataset"];
+        assert_eq!((dataset["version"].as_u64(), dataset["default_profile"].as_str()), (Some(1), Some("car")));
+        assert!(DateTime::parse_from_rfc3339(dataset["loaded_at"].as_str().unwrap()).is_ok());
+        let car = &dataset["regions"]["default"]["car"];
+        assert_eq!((car["source"].as_str(), car["nodes"].as_u64()), (Some("test"), Some(2)));
+
+        state.shutting_down.store(true, Ordering::Relaxed);
+        assert_eq!(health().await["status"], "shutting_down");
+    }
+
+    #[tokio::test]
+    async fn test_regions() {
+        // a street going north in each region, 1 km apart between their nodes
+        let street = |first: i64, lat: f64| {
+            let points: Vec<_> = (0..3).map(|i| (first + i, lat + i as f64 * 0.01, -75.0)).collect();
+            let (nodes, way) = osm::street("Main Street", &points);
+            Engine::from_graph(Graph::build(nodes, vec![way]))
+        };
+        let mut dataset = Dataset::new("car");
+        dataset.add("south", "car", street(1, 38.0), "south.graph");
+        dataset.add("north", "car", street(11, 39.0), "north.graph");
+        let state = test_state();
+        state.set_dataset(dataset);
+        let app = test_router("/nonexistent", state);
+        let get_json = |uri: String| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+                serde_json::from_str::<serde_json::Value>(&body_text(response).await).unwrap()
+            }
+        };
+
+        let regions = get_json("/regions".to_string()).await;
+        let names: Vec<&str> = regions.as_array().unwrap().iter().map(|r| r["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["north", "south"]);
+        assert_eq!(regions[1]["bounds"], serde_json::json!([-75.0, 38.0, -75.0, 38.02]));
+        assert_eq!(regions[1]["profiles"]["car"]["source"], "south.graph");
+
+        // the region containing both points
+        let nav = |orig: &str, dest: &str, region: &str| format!("/nav?orig={}&dest={}{}", orig, dest, region);
+        let body = get_json(nav("39.0,-75.0", "39.02,-75.0", "")).await;
+        assert!((body["distance"].as_f64().unwrap() - 2224.0).abs() < 1.0);
+        assert_eq!(body["path"][0], serde_json::json!({ "lat": 39.0, "lon": -75.0 }));
+        let body = get_json(nav("38.0,-75.0", "38.01,-75.0", "&region=south")).await;
+        assert!((body["distance"].as_f64().unwrap() - 1112.0).abs() < 1.0);
+        assert_eq!(get_json(nav("38.0,-75.0", "39.02,-75.0", "")).await["code"], 222);
+        assert_eq!(get_json(nav("38.0,-75.0", "38.01,-75.0", "&region=west")).await["code"], 111);
+
+        // and on the osrm api
+        let body = get_json("/route/v1/driving/-75.0,39.0;-75.0,39.01".to_string()).await;
+        assert_eq!(body["waypoints"][0]["location"], serde_json::json!([-75.0, 39.0]));
+        let body = get_json("/route/v1/driving/-75.0,38.0;-75.0,39.01".to_string()).await;
+        assert_eq!(body["code"], "NoSegment");
+        let body = get_json("/route/v1/driving/-75.0,38.0;-75.0,38.01?region=west".to_string()).await;
+        assert_eq!(body["code"], "InvalidOptions");
+    }
 }
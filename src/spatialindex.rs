@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
-use rstar::{primitives::GeomWithData, RTree};
+use geo::{Distance, Haversine, Point};
+use rstar::{primitives::GeomWithData, RTree, AABB};
 
-use crate::{graph::Node, osm};
+use crate::{graph::{Graph, LatLon, Node}, osm};
 
 
 // make it a real type
@@ -11,13 +12,33 @@ pub struct SpatialIndex(RTree<NodeLocation>);
 
 pub type NodeLocation = GeomWithData<[f64; 2], osm::NodeID>;
 
+// a node found by a query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Neighbor {
+    pub node_id: osm::NodeID,
+    pub location: LatLon,
+    pub distance: f64, // meters from the query location
+}
+
+// the mean earth radius of geo's haversine, so the box and the exact filter agree
+const EARTH_RADIUS: f64 = 6_371_008.8;
+const METERS_PER_DEGREE: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.0; // of latitude, and of longitude at the equator
+
+fn to_neighbor(from: LatLon, n: &NodeLocation) -> Neighbor {
+    let [lon, lat] = *n.geom();
+    Neighbor {
+        node_id: n.data,
+        location: LatLon { lat, lon },
+        distance: Haversine::distance(Point::new(from.lon, from.lat), Point::new(lon, lat)),
+    }
+}
 
 impl SpatialIndex {
     pub fn build(used_nodes: &HashMap<osm::NodeID, Node>) -> Self {
         let node_locations = used_nodes
         .iter()
-        .map(|(nodeId, node)| {
-            NodeLocation::new([node.location.lon, node.location.lat], *nodeId)
+        .map(|(node_id, node)| {
+            NodeLocation::new([node.location.lon, node.location.lat], *node_id)
         })
         .collect();
         let tree = RTree::bulk_load(node_locations);
@@ -25,8 +46,155 @@ impl SpatialIndex {
         SpatialIndex(tree)
     }
 
-    // todo
-    pub fn nearest_neighbor () {
+    // same, for a graph loaded from a prepared file
+    pub fn from_graph(graph: &Graph) -> Self {
+        let node_locations = graph
+            .nodes()
+            .chain(graph.shape_points())
+            .map(|node| NodeLocation::new([node.location.lon, node.location.lat], node.id))
+            .collect();
+        SpatialIndex(RTree::bulk_load(node_locations))
+    }
+
+    // point: [lon, lat]
+    pub fn nearest_neighbor(&self, point: &[f64; 2]) -> Option<&NodeLocation> {
+        self.0.nearest_neighbor(point)
+    }
+
+    // the `k` closest nodes in meters, closest first.
+    // the tree orders by distance in degrees, and a degree of longitude is shorter than one of latitude
+    // away from the equator: the k first in degrees can miss a closer node east or west.
+    // So it keeps taking nodes until one `degrees` away can't be closer than the k-th best in meters.
+    pub fn k_nearest(&self, location: LatLon, k: usize) -> Vec<Neighbor> {
+        let mut found: Vec<Neighbor> = Vec::with_capacity(k + 1); // sorted in meters
+        if k == 0 {
+            return found;
+        }
+        for (n, distance_2) in self.0.nearest_neighbor_iter_with_distance_2(&[location.lon, location.lat]) {
+            let degrees = distance_2.sqrt();
+            if found.len() == k {
+                // at least this many meters: a degree of longitude is the shortest on the poleward side
+                let poleward = (location.lat.abs() + degrees).min(90.0);
+                let at_least = degrees * METERS_PER_DEGREE * poleward.to_radians().cos();
+                if at_least > found[k - 1].distance {
+                    break;
+                }
+            }
+            let neighbor = to_neighbor(location, n);
+            let at = found.partition_point(|f| f.distance <= neighbor.distance);
+            if at < k {
+                found.insert(at, neighbor);
+                found.truncate(k);
+            }
+        }
+        found
+    }
+
+    // all nodes closer than `radius` meters, closest first
+    pub fn within_radius(&self, location: LatLon, radius: f64) -> Vec<Neighbor> {
+        // a box in degrees around the circle, then the exact distance
+        let dlat = radius / METERS_PER_DEGREE;
+        // the circle is widest in longitude on its poleward side
+        let poleward = (location.lat.abs() + dlat).min(90.0);
+        let dlon = radius / (METERS_PER_DEGREE * poleward.to_radians().cos().max(1e-6));
+        let envelope = AABB::from_corners(
+            [location.lon - dlon, location.lat - dlat],
+            [location.lon + dlon, location.lat + dlat],
+        );
+
+        let mut found: Vec<Neighbor> = self
+            .0
+            .locate_in_envelope(&envelope)
+            .map(|n| to_neighbor(location, n))
+            .filter(|n| n.distance <= radius)
+            .collect();
+        found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SpatialIndex {
+        // nodes every ~111 m going north
+        let nodes = (0..10)
+            .map(|i| {
+                let id = osm::NodeID(i);
+                let location = LatLon { lat: 38.0 + i as f64 * 0.001, lon: -75.0 };
+                (id, Node { id, location })
+            })
+            .collect();
+        SpatialIndex::build(&nodes)
+    }
+
+    #[test]
+    fn test_k_nearest() {
+        let found = index().k_nearest(LatLon { lat: 38.0021, lon: -75.0 }, 3);
+        let ids: Vec<_> = found.iter().map(|n| n.node_id.0).collect();
+        assert_eq!(ids, vec![2, 3, 1]);
+        assert!((found[0].distance - 11.1).abs() < 0.5);
+        assert_eq!(found[0].location, LatLon { lat: 38.002, lon: -75.0 });
+    }
+
+    #[test]
+    fn test_k_nearest_high_latitude() {
+        // at 60° north, 0.0015° east is 83 m and 0.001° north is 111 m: the closest in meters
+        // is further in degrees
+        let nodes = [(1, 60.0, 10.0015), (2, 60.001, 10.0), (3, 59.998, 10.0)]
+            .into_iter()
+            .map(|(i, lat, lon)| {
+                let id = osm::NodeID(i);
+                (id, Node { id, location: LatLon { lat, lon } })
+            })
+            .collect();
+        let index = SpatialIndex::build(&nodes);
+        let at = LatLon { lat: 60.0, lon: 10.0 };
+        let found = index.k_nearest(at, 1);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].node_id.0, 1);
+        assert!((found[0].distance - 83.4).abs() < 0.5);
+        let ids: Vec<_> = index.k_nearest(at, 2).iter().map(|n| n.node_id.0).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(index.k_nearest(at, 5).len(), 3);
+        assert!(index.k_nearest(at, 0).is_empty());
+    }
+
+    #[test]
+    fn test_within_radius() {
+        let index = index();
+        let found = index.within_radius(LatLon { lat: 38.005, lon: -75.0 }, 250.0);
+        let ids: Vec<_> = found.iter().map(|n| n.node_id.0).collect();
+        assert_eq!(ids.len(), 5);
+        assert_eq!(ids[0], 5);
+        assert!(found.iter().all(|n| n.distance <= 250.0));
+
+        assert!(index.within_radius(LatLon { lat: 38.1, lon: -75.0 }, 50.0).is_empty());
+    }
+
+    #[test]
+    fn test_within_radius_boundary() {
+        // just over the distance to the next nodes north and south finds both
+        let index = index();
+        let at = LatLon { lat: 38.005, lon: -75.0 };
+        let step = to_neighbor(at, &NodeLocation::new([-75.0, 38.006], osm::NodeID(6))).distance;
+        let mut ids: Vec<_> = index.within_radius(at, step + 0.01).iter().map(|n| n.node_id.0).collect();
+        ids.sort();
+        assert_eq!(ids, vec![4, 5, 6]);
 
+        // and east and west, far from the equator
+        let nodes = [(1, 10.0), (2, 10.02), (3, 9.98)]
+            .into_iter()
+            .map(|(i, lon)| {
+                let id = osm::NodeID(i);
+                (id, Node { id, location: LatLon { lat: 60.0, lon } })
+            })
+            .collect();
+        let index = SpatialIndex::build(&nodes);
+        let at = LatLon { lat: 60.0, lon: 10.0 };
+        let step = to_neighbor(at, &NodeLocation::new([10.02, 60.0], osm::NodeID(2))).distance;
+        assert_eq!(index.within_radius(at, step + 0.01).len(), 3);
+        assert_eq!(index.within_radius(at, step - 0.01).len(), 1);
     }
 }
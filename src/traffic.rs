@@ -0,0 +1,199 @@
+use std::{collections::HashMap, error::Error, fs};
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::osm;
+
+// time-dependent speeds.
+// a week is cut into 15 minutes buckets: 7 days * 24 hours * 4 = 672 buckets.
+// a profile with 96 buckets (one day) is repeated for every day of the week.
+pub const BUCKET_SECONDS: f64 = 15.0 * 60.0;
+pub const DAY_SECONDS: f64 = 24.0 * 60.0 * 60.0;
+pub const WEEK_SECONDS: f64 = 7.0 * DAY_SECONDS;
+pub const DAY_BUCKETS: usize = 96;
+pub const WEEK_BUCKETS: usize = 672;
+
+// seconds since Monday 00:00 (local time of the departure), always in [0, WEEK_SECONDS)
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub struct WeekTime(pub f64);
+
+impl WeekTime {
+    pub fn from_datetime<Tz: TimeZone>(t: &DateTime<Tz>) -> WeekTime {
+        let days = t.weekday().num_days_from_monday() as f64;
+        WeekTime(days * DAY_SECONDS + t.num_seconds_from_midnight() as f64)
+    }
+
+    // `seconds` later, wrapping around the end of the week
+    pub fn add(&self, seconds: f64) -> WeekTime {
+        WeekTime((self.0 + seconds).rem_euclid(WEEK_SECONDS))
+    }
+}
+
+#[derive(Debug)]
+pub enum TrafficError {
+    Io(std::io::Error),
+    Format { line: usize, message: String },
+}
+
+impl std::fmt::Display for TrafficError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrafficError::Io(e) => f.write_str(&format!("can't read speed profiles: {}", e)),
+            TrafficError::Format { line, message } => {
+                f.write_str(&format!("speed profiles line {}: {}", line, message))
+            }
+        }
+    }
+}
+
+impl Error for TrafficError {}
+
+impl From<std::io::Error> for TrafficError {
+    fn from(value: std::io::Error) -> Self {
+        TrafficError::Io(value)
+    }
+}
+
+// speeds (km/h) of one road segment over the week
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeedProfile {
+    speeds: Vec<f64>,
+}
+
+impl SpeedProfile {
+    pub fn new(speeds: Vec<f64>) -> Result<SpeedProfile, String> {
+        if speeds.len() != DAY_BUCKETS && speeds.len() != WEEK_BUCKETS {
+            return Err(format!(
+                "expected {} or {} speeds, got {}",
+                DAY_BUCKETS,
+                WEEK_BUCKETS,
+                speeds.len()
+            ));
+        }
+        // a zero speed would make the travel time infinite (and the loop below never ends)
+        if speeds.iter().any(|s| !s.is_finite() || *s <= 0.0) {
+            return Err("speeds must be positive numbers".to_string());
+        }
+        Ok(SpeedProfile { speeds })
+    }
+
+    // km/h at the given time
+    pub fn speed_at(&self, t: WeekTime) -> f64 {
+        let bucket = (t.0 / BUCKET_SECONDS) as usize;
+        self.speeds[bucket % self.speeds.len()]
+    }
+
+    // seconds needed to drive `distance` meters when entering the segment at `enter`.
+    //
+    // we don't use `distance / speed_at(enter)`: with a slow bucket followed by a fast one,
+    // a car entering a bit later could arrive earlier than one entering now (breaks FIFO,
+    // and Dijkstra is no longer correct). Instead the car drives at the speed of each bucket
+    // it crosses, so whoever enters first always leaves first.
+    pub fn travel_time(&self, distance: f64, enter: WeekTime) -> f64 {
+        let mut remaining = distance;
+        let mut now = enter.0;
+        let mut elapsed = 0.0;
+        loop {
+            let speed = self.speed_at(WeekTime(now)) / 3.6; // m/s
+            let bucket_end = ((now / BUCKET_SECONDS).floor() + 1.0) * BUCKET_SECONDS;
+            let reachable = speed * (bucket_end - now);
+            if reachable >= remaining {
+                return elapsed + remaining / speed;
+            }
+            remaining -= reachable;
+            elapsed += bucket_end - now;
+            now = bucket_end % WEEK_SECONDS;
+        }
+    }
+}
+
+// CSV, one directed segment per line:
+//   from_osm_node_id,to_osm_node_id,speed_0,speed_1,...
+// with 96 (every day) or 672 (Monday 00:00 to Sunday 23:45) speeds in km/h.
+// empty lines and lines starting with `#` are ignored.
+pub fn load_speed_profiles(
+    path: &str,
+) -> Result<HashMap<(osm::NodeID, osm::NodeID), SpeedProfile>, TrafficError> {
+    let content = fs::read_to_string(path)?;
+    parse_speed_profiles(&content)
+}
+
+pub fn parse_speed_profiles(
+    content: &str,
+) -> Result<HashMap<(osm::NodeID, osm::NodeID), SpeedProfile>, TrafficError> {
+    let mut profiles = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let format_error = |message: String| TrafficError::Format {
+            line: i + 1,
+            message,
+        };
+
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() < 3 {
+            return Err(format_error("expected from,to,speeds...".to_string()));
+        }
+        let from = fields[0]
+            .parse()
+            .map_err(|e| format_error(format!("bad from node id: {}", e)))?;
+        let to = fields[1]
+            .parse()
+            .map_err(|e| format_error(format!("bad to node id: {}", e)))?;
+        let speeds = fields[2..]
+            .iter()
+            .map(|s| s.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format_error(format!("bad speed: {}", e)))?;
+
+        let profile = SpeedProfile::new(speeds).map_err(format_error)?;
+        profiles.insert((osm::NodeID(from), osm::NodeID(to)), profile);
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_travel_time_is_fifo() {
+        // 10 km/h during the first bucket of the day, 100 km/h afterwards
+        let mut speeds = vec![100.0; DAY_BUCKETS];
+        speeds[0] = 10.0;
+        let profile = SpeedProfile::new(speeds).unwrap();
+
+        // 1 km entering at 00:00: 15 minutes at 10 km/h = 2500 m, enough.
+        assert!((profile.travel_time(1000.0, WeekTime(0.0)) - 360.0).abs() < 1e-6);
+
+        // entering later never means leaving earlier
+        let mut last_arrival = 0.0;
+        for enter in (0..1200).step_by(30) {
+            let enter = enter as f64;
+            let arrival = enter + profile.travel_time(5000.0, WeekTime(enter));
+            assert!(arrival >= last_arrival);
+            last_arrival = arrival;
+        }
+    }
+
+    #[test]
+    fn test_week_wraps_around() {
+        let t = WeekTime(WEEK_SECONDS - 10.0).add(20.0);
+        assert!((t.0 - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_parse_speed_profiles() {
+        let speeds = vec!["50"; DAY_BUCKETS].join(",");
+        let content = format!("# from,to,speeds\n1,2,{}\n", speeds);
+        let profiles = parse_speed_profiles(&content).unwrap();
+        let profile = &profiles[&(osm::NodeID(1), osm::NodeID(2))];
+        assert_eq!(profile.speed_at(WeekTime(3.0 * DAY_SECONDS)), 50.0);
+
+        assert!(parse_speed_profiles("1,2,50,60").is_err());
+    }
+}